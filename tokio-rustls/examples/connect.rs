@@ -1,9 +1,6 @@
 use std::sync::Arc;
 
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use tokio_rustls_fork_shadow_tls::TlsConnector;
 use rustls_fork_shadow_tls::{OwnedTrustAnchor, RootCertStore};
@@ -24,11 +21,7 @@ async fn main() {
         .with_no_client_auth();
 
     let connector = TlsConnector::from(Arc::new(config));
-    let stream = TcpStream::connect("rsproxy.cn:443").await.unwrap();
-    println!("rsproxy.cn:443 connected");
-
-    let domain = rustls_fork_shadow_tls::ServerName::try_from("rsproxy.cn").unwrap();
-    let mut stream = connector.connect(domain, stream).await.unwrap();
+    let mut stream = connector.connect_addr("rsproxy.cn:443").await.unwrap();
     println!("handshake success");
 
     let content = b"GET / HTTP/1.0\r\nHost: rsproxy.cn\r\n\r\n";
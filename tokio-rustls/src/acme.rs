@@ -0,0 +1,738 @@
+//! Certificates issued by an ACME CA, such as Let's Encrypt, validated with TLS-ALPN-01.
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use rustls_fork_shadow_tls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+};
+
+use crate::{base64, dial, x509, TlsConnector};
+
+/// The ALPN protocol of TLS-ALPN-01 validation handshakes.
+pub(crate) const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+/// The directory of Let's Encrypt.
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+/// The directory of Let's Encrypt's staging environment, with generous rate limits and
+/// untrusted certificates.
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+const MAX_RESPONSE: usize = 1 << 20;
+/// How often the certificate's expiry is looked at again.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Delay before retrying a failed issuance, well within CAs' failed validation limits.
+const RETRY_DELAY: Duration = Duration::from_secs(15 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 60;
+
+#[derive(Clone)]
+struct Config {
+    directory: String,
+    domains: Vec<String>,
+    contact: Vec<String>,
+    connector: TlsConnector,
+    cache: Option<PathBuf>,
+    renew_before: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    cert: RwLock<Option<Arc<CertifiedKey>>>,
+    /// Validation certificates by domain, while their challenges are pending.
+    challenges: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+/// Certificates for a set of domains, issued and renewed by an ACME CA, set with `with_acme`
+/// on an acceptor.
+///
+/// Domains are validated with TLS-ALPN-01 by the acceptor itself, so it must be reachable on
+/// port 443 of every domain. [`spawn`](Self::spawn) the renewal task to get a certificate;
+/// until then handshakes fail. Set a cache directory, or every start creates a new account and
+/// certificate, which CAs rate limit.
+#[derive(Clone)]
+pub struct AcmeCertResolver {
+    config: Arc<Config>,
+    state: Arc<State>,
+}
+
+impl AcmeCertResolver {
+    /// Certificates for `domains` from the CA with the directory URL `directory`, such as
+    /// [`LETS_ENCRYPT`]. `connector` talks to the CA, and needs to trust its server.
+    pub fn new(directory: &str, domains: Vec<String>, connector: TlsConnector) -> Self {
+        Self {
+            config: Arc::new(Config {
+                directory: directory.to_owned(),
+                domains,
+                contact: Vec::new(),
+                connector,
+                cache: None,
+                renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+            }),
+            state: Arc::default(),
+        }
+    }
+
+    /// Register the account with a contact URL, e.g. `mailto:admin@example.com`, which the CA
+    /// may use to warn about problems.
+    pub fn with_contact(mut self, contact: &str) -> Self {
+        Arc::make_mut(&mut self.config)
+            .contact
+            .push(contact.to_owned());
+        self
+    }
+
+    /// Keep the account key and the certificate in `dir`, to reuse them after a restart.
+    /// Each resolver needs a directory of its own.
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        Arc::make_mut(&mut self.config).cache = Some(dir.into());
+        self
+    }
+
+    /// Renew the certificate this long before it expires. Defaults to 30 days.
+    pub fn with_renew_before(mut self, renew_before: Duration) -> Self {
+        Arc::make_mut(&mut self.config).renew_before = renew_before;
+        self
+    }
+
+    /// Load the cached certificate, then issue one whenever there is none or it is due for
+    /// renewal. Failures are retried after 15 minutes. Abort the returned task to stop.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.load_cached();
+            loop {
+                let wait = this
+                    .renew_at()
+                    .and_then(|at| at.duration_since(SystemTime::now()).ok());
+                if let Some(wait) = wait {
+                    tokio::time::sleep(wait.min(CHECK_INTERVAL)).await;
+                    continue;
+                }
+                let result = this.issue().await;
+                #[cfg(feature = "tracing")]
+                if let Err(err) = &result {
+                    tracing::warn!(%err, "acme issuance failed");
+                }
+                if result.is_err() {
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        })
+    }
+
+    /// Order a certificate now and serve it once issued.
+    pub async fn issue(&self) -> io::Result<()> {
+        let mut client = Client::new(&self.config).await?;
+        let (order_url, order) = client.new_order(&self.config.domains).await?;
+        for authorization in order.get("authorizations").map_or(&[][..], Json::array) {
+            let url = authorization
+                .str()
+                .ok_or_else(|| malformed("authorization"))?;
+            self.authorize(&mut client, url).await?;
+        }
+
+        let key = rcgen::KeyPair::generate().map_err(io::Error::other)?;
+        let mut params =
+            rcgen::CertificateParams::new(self.config.domains.clone()).map_err(io::Error::other)?;
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let csr = params.serialize_request(&key).map_err(io::Error::other)?;
+        let finalize = order
+            .get("finalize")
+            .and_then(Json::str)
+            .ok_or_else(|| malformed("order"))?;
+        let payload = format!("{{\"csr\":\"{}\"}}", base64::encode_url(csr.der()));
+        let mut order = client.post_json(finalize, Some(&payload)).await?;
+        if order.get("status").and_then(Json::str) != Some("valid") {
+            order = client.poll(&order_url, "valid").await?;
+        }
+        let url = order
+            .get("certificate")
+            .and_then(Json::str)
+            .ok_or_else(|| malformed("order"))?;
+        let pem = client.post(url, None).await?.body;
+
+        let key = key.serialize_der();
+        let chain = rustls_pemfile::certs(&mut &pem[..])?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        *self.state.cert.write().unwrap() = Some(certified_key(chain, key.clone())?);
+        if let Some(dir) = &self.config.cache {
+            fs::write(dir.join("chain.pem"), &pem)?;
+            fs::write(dir.join("key.der"), key)?;
+        }
+        Ok(())
+    }
+
+    async fn authorize(&self, client: &mut Client<'_>, url: &str) -> io::Result<()> {
+        let authorization = client.post_json(url, None).await?;
+        if authorization.get("status").and_then(Json::str) == Some("valid") {
+            return Ok(());
+        }
+        let domain = authorization
+            .get("identifier")
+            .and_then(|identifier| identifier.get("value"))
+            .and_then(Json::str)
+            .ok_or_else(|| malformed("authorization"))?
+            .to_ascii_lowercase();
+        let challenge = authorization
+            .get("challenges")
+            .map_or(&[][..], Json::array)
+            .iter()
+            .find(|challenge| challenge.get("type").and_then(Json::str) == Some("tls-alpn-01"))
+            .ok_or_else(|| io::Error::other(format!("no tls-alpn-01 challenge for {domain}")))?;
+        let (Some(challenge_url), Some(token)) = (
+            challenge.get("url").and_then(Json::str),
+            challenge.get("token").and_then(Json::str),
+        ) else {
+            return Err(malformed("challenge"));
+        };
+
+        let key_authorization = format!("{token}.{}", client.thumbprint());
+        let cert = validation_cert(&domain, &key_authorization)?;
+        self.state
+            .challenges
+            .lock()
+            .unwrap()
+            .insert(domain.clone(), cert);
+        let result = async {
+            client.post(challenge_url, Some("{}")).await?;
+            client.poll(url, "valid").await
+        }
+        .await;
+        self.state.challenges.lock().unwrap().remove(&domain);
+        result.map(drop)
+    }
+
+    fn load_cached(&self) {
+        let Some(dir) = &self.config.cache else {
+            return;
+        };
+        let (Ok(pem), Ok(key)) = (
+            fs::read(dir.join("chain.pem")),
+            fs::read(dir.join("key.der")),
+        ) else {
+            return;
+        };
+        let Ok(chain) = rustls_pemfile::certs(&mut &pem[..]) else {
+            return;
+        };
+        if let Ok(cert) = certified_key(chain.into_iter().map(Certificate).collect(), key) {
+            *self.state.cert.write().unwrap() = Some(cert);
+        }
+    }
+
+    /// When the certificate is due for renewal, or `None` when there is none.
+    fn renew_at(&self) -> Option<SystemTime> {
+        let cert = self.state.cert.read().unwrap().clone()?;
+        let (_, not_after) = x509::tbs(&cert.cert.first()?.0)?.validity()?;
+        Some(
+            not_after
+                .checked_sub(self.config.renew_before)
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        )
+    }
+}
+
+impl ResolvesServerCert for AcmeCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        if is_validation(&client_hello) {
+            let domain = client_hello.server_name()?.to_ascii_lowercase();
+            return self.state.challenges.lock().unwrap().get(&domain).cloned();
+        }
+        self.state.cert.read().unwrap().clone()
+    }
+}
+
+/// Whether `client_hello` starts a TLS-ALPN-01 validation handshake.
+pub(crate) fn is_validation(client_hello: &ClientHello) -> bool {
+    client_hello
+        .alpn()
+        .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ACME_TLS_ALPN))
+}
+
+/// The self-signed certificate answering the TLS-ALPN-01 challenge for `domain`.
+fn validation_cert(domain: &str, key_authorization: &str) -> io::Result<Arc<CertifiedKey>> {
+    let digest = digest::digest(&digest::SHA256, key_authorization.as_bytes());
+    let mut params =
+        rcgen::CertificateParams::new(vec![domain.to_owned()]).map_err(io::Error::other)?;
+    params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(digest.as_ref())];
+    let key = rcgen::KeyPair::generate().map_err(io::Error::other)?;
+    let cert = params.self_signed(&key).map_err(io::Error::other)?;
+    certified_key(vec![Certificate(cert.der().to_vec())], key.serialize_der())
+}
+
+fn certified_key(chain: Vec<Certificate>, key: Vec<u8>) -> io::Result<Arc<CertifiedKey>> {
+    let key = sign::any_supported_type(&PrivateKey(key))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid private key"))?;
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed ACME {what}"))
+}
+
+struct Response {
+    status: u16,
+    location: Option<String>,
+    nonce: Option<String>,
+    body: Vec<u8>,
+}
+
+/// An ACME account session, signing requests with the account key.
+struct Client<'a> {
+    config: &'a Config,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    new_nonce: String,
+    new_order: String,
+    nonce: Option<String>,
+    /// The account URL, once registered.
+    kid: Option<String>,
+}
+
+impl<'a> Client<'a> {
+    /// Fetch the directory and register or look up the account.
+    async fn new(config: &'a Config) -> io::Result<Client<'a>> {
+        let rng = SystemRandom::new();
+        let key = account_key(config, &rng)?;
+        let directory = http(config, "GET", &config.directory, None).await?;
+        let directory = Json::parse(&directory.body).ok_or_else(|| malformed("directory"))?;
+        let url = |name| {
+            directory
+                .get(name)
+                .and_then(Json::str)
+                .map(str::to_owned)
+                .ok_or_else(|| malformed("directory"))
+        };
+        let mut client = Client {
+            config,
+            key,
+            rng,
+            new_nonce: url("newNonce")?,
+            new_order: url("newOrder")?,
+            nonce: None,
+            kid: None,
+        };
+
+        let contact: Vec<String> = config.contact.iter().map(|c| json_string(c)).collect();
+        let payload = format!(
+            "{{\"termsOfServiceAgreed\":true,\"contact\":[{}]}}",
+            contact.join(",")
+        );
+        let account = client.post(&url("newAccount")?, Some(&payload)).await?;
+        client.kid = Some(account.location.ok_or_else(|| malformed("account"))?);
+        Ok(client)
+    }
+
+    /// Place an order for `domains`, returning its URL and the order.
+    async fn new_order(&mut self, domains: &[String]) -> io::Result<(String, Json)> {
+        let identifiers: Vec<String> = domains
+            .iter()
+            .map(|domain| format!("{{\"type\":\"dns\",\"value\":{}}}", json_string(domain)))
+            .collect();
+        let payload = format!("{{\"identifiers\":[{}]}}", identifiers.join(","));
+        let url = self.new_order.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        let order = Json::parse(&response.body).ok_or_else(|| malformed("order"))?;
+        Ok((response.location.ok_or_else(|| malformed("order"))?, order))
+    }
+
+    /// Fetch `url` until its status is `status`.
+    async fn poll(&mut self, url: &str, status: &str) -> io::Result<Json> {
+        for _ in 0..POLL_ATTEMPTS {
+            let object = self.post_json(url, None).await?;
+            match object.get("status").and_then(Json::str) {
+                Some(current) if current == status => return Ok(object),
+                Some("invalid") => {
+                    let detail = object
+                        .get("challenges")
+                        .map_or(&[][..], Json::array)
+                        .iter()
+                        .chain([&object])
+                        .find_map(|o| o.get("error")?.get("detail")?.str())
+                        .unwrap_or("no details");
+                    return Err(io::Error::other(format!("ACME {url} is invalid: {detail}")));
+                }
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("ACME {url} did not become {status}"),
+        ))
+    }
+
+    async fn post_json(&mut self, url: &str, payload: Option<&str>) -> io::Result<Json> {
+        let response = self.post(url, payload).await?;
+        Json::parse(&response.body).ok_or_else(|| malformed("response"))
+    }
+
+    /// POST a JWS signed request with `payload`, or a POST-as-GET without one.
+    async fn post(&mut self, url: &str, payload: Option<&str>) -> io::Result<Response> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => http(self.config, "HEAD", &self.new_nonce, None)
+                    .await?
+                    .nonce
+                    .ok_or_else(|| malformed("nonce"))?,
+            };
+            let key = match &self.kid {
+                Some(kid) => format!("\"kid\":{}", json_string(kid)),
+                None => format!("\"jwk\":{}", self.jwk()),
+            };
+            let protected = base64::encode_url(
+                format!(
+                    "{{\"alg\":\"ES256\",{key},\"nonce\":{},\"url\":{}}}",
+                    json_string(&nonce),
+                    json_string(url)
+                )
+                .as_bytes(),
+            );
+            let payload = payload.map_or_else(String::new, |p| base64::encode_url(p.as_bytes()));
+            let signature = self
+                .key
+                .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+                .map_err(|_| io::Error::other("signing failed"))?;
+            let body = format!(
+                "{{\"protected\":\"{protected}\",\"payload\":\"{payload}\",\"signature\":\"{}\"}}",
+                base64::encode_url(signature.as_ref())
+            );
+
+            let response = http(self.config, "POST", url, Some(body.as_bytes())).await?;
+            self.nonce = response.nonce.clone();
+            if response.status < 400 {
+                return Ok(response);
+            }
+            let problem = Json::parse(&response.body);
+            let field = |name| problem.as_ref()?.get(name)?.str();
+            if field("type") == Some("urn:ietf:params:acme:error:badNonce") && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(io::Error::other(format!(
+                "ACME error {}: {}",
+                field("type").unwrap_or("unknown"),
+                field("detail").unwrap_or("no details")
+            )));
+        }
+    }
+
+    /// The account public key as a JWK, with its members in the order thumbprints use.
+    fn jwk(&self) -> String {
+        // An uncompressed point.
+        let point = &self.key.public_key().as_ref()[1..];
+        let (x, y) = point.split_at(point.len() / 2);
+        format!(
+            "{{\"crv\":\"P-256\",\"kty\":\"EC\",\"x\":\"{}\",\"y\":\"{}\"}}",
+            base64::encode_url(x),
+            base64::encode_url(y)
+        )
+    }
+
+    fn thumbprint(&self) -> String {
+        base64::encode_url(digest::digest(&digest::SHA256, self.jwk().as_bytes()).as_ref())
+    }
+}
+
+/// The cached account key, or a new one which is cached.
+fn account_key(config: &Config, rng: &SystemRandom) -> io::Result<EcdsaKeyPair> {
+    let path = config.cache.as_ref().map(|dir| dir.join("account.der"));
+    let pkcs8 = match path.as_ref().map(fs::read) {
+        Some(Ok(pkcs8)) => pkcs8,
+        _ => {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, rng)
+                .map_err(|_| io::Error::other("key generation failed"))?;
+            if let Some(path) = &path {
+                fs::create_dir_all(path.parent().unwrap_or(path))?;
+                fs::write(path, pkcs8.as_ref())?;
+            }
+            pkcs8.as_ref().to_vec()
+        }
+    };
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid ACME account key"))
+}
+
+/// Make an HTTP/1.0 request over TLS, so the response is delimited by the connection.
+async fn http(
+    config: &Config,
+    method: &str,
+    url: &str,
+    body: Option<&[u8]>,
+) -> io::Result<Response> {
+    let rest = url.strip_prefix("https://").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not an https URL: {url}"),
+        )
+    })?;
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = if path.is_empty() { "/" } else { path };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => dial::split_host_port(authority)?,
+        _ => (authority.trim_matches(['[', ']']), 443),
+    };
+
+    let mut stream = config.connector.connect_host(host, port).await?;
+    let body = body.unwrap_or_default();
+    let head = format!(
+        "{method} {path} HTTP/1.0\r\nHost: {authority}\r\nContent-Type: application/jose+json\r\n\
+         Content-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+    while response.len() < MAX_RESPONSE {
+        match stream.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            // Servers commonly close without close_notify once the response is sent.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+    let head = String::from_utf8_lossy(&response[..split]).into_owned();
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+    let mut location = None;
+    let mut nonce = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "location" => location = Some(value.trim().to_owned()),
+            "replay-nonce" => nonce = Some(value.trim().to_owned()),
+            _ => {}
+        }
+    }
+    Ok(Response {
+        status,
+        location,
+        nonce,
+        body: response.split_off(split + 4),
+    })
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Just enough JSON to read ACME responses; only strings, arrays and objects are kept.
+enum Json {
+    Other,
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(input: &[u8]) -> Option<Json> {
+        let mut parser = JsonParser { input, pos: 0 };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        (parser.pos == input.len()).then_some(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    const MAX_DEPTH: usize = 32;
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.input.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn literal(&mut self, literal: &[u8]) -> Option<Json> {
+        let found = self.input[self.pos..].starts_with(literal);
+        self.pos += literal.len();
+        found.then_some(Json::Other)
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Json> {
+        if depth > Self::MAX_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        match *self.input.get(self.pos)? {
+            b'{' => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        let key = self.string()?;
+                        if !self.eat(b':') {
+                            return None;
+                        }
+                        members.push((key, self.value(depth + 1)?));
+                        if !self.eat(b',') {
+                            break;
+                        }
+                    }
+                    self.eat(b'}').then_some(())?;
+                }
+                Some(Json::Object(members))
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value(depth + 1)?);
+                        if !self.eat(b',') {
+                            break;
+                        }
+                    }
+                    self.eat(b']').then_some(())?;
+                }
+                Some(Json::Array(items))
+            }
+            b'"' => self.string().map(Json::String),
+            b't' => self.literal(b"true"),
+            b'f' => self.literal(b"false"),
+            b'n' => self.literal(b"null"),
+            _ => {
+                let start = self.pos;
+                while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') =
+                    self.input.get(self.pos)
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+                number.parse::<f64>().ok().map(|_| Json::Other)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return None;
+        }
+        let mut out = Vec::new();
+        loop {
+            let byte = *self.input.get(self.pos)?;
+            self.pos += 1;
+            match byte {
+                b'"' => return String::from_utf8(out).ok(),
+                b'\\' => {
+                    let escaped = *self.input.get(self.pos)?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return None,
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte => out.push(byte),
+            }
+        }
+    }
+
+    /// The character of a `\u` escape, whose `\u` has been read.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+        if !self.input[self.pos..].starts_with(b"\\u") {
+            return None;
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.input.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
@@ -0,0 +1,52 @@
+//! What a client keeps of a handshake when it drops the TLS session afterwards.
+use std::io;
+
+use rustls_fork_shadow_tls::{Certificate, CipherSuite, NamedGroup, ProtocolVersion};
+
+use crate::{client::TlsStream, stream::HandshakeSummary};
+
+/// The parameters of a finished handshake, see
+/// [`TlsConnector::handshake_only`](crate::TlsConnector::handshake_only).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeArtifacts {
+    pub client_random: [u8; 32],
+    pub server_random: [u8; 32],
+    /// Legacy session id of the ClientHello, where a shadow-tls client puts its HMAC.
+    pub client_session_id: Vec<u8>,
+    /// Legacy session id of the ServerHello; a TLS 1.3 server echoes the client's.
+    pub server_session_id: Vec<u8>,
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    pub kx_group: Option<NamedGroup>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The server's certificate chain, end-entity first.
+    pub chain: Vec<Certificate>,
+    pub handshake: HandshakeSummary,
+}
+
+impl HandshakeArtifacts {
+    /// Capture the artifacts of the finished handshake of `stream`. Fails when a hello was
+    /// too long to be scanned.
+    pub(crate) fn capture<IO>(stream: &TlsStream<IO>) -> io::Result<Self> {
+        let (read, written) = stream.hellos();
+        let missing = || io::Error::new(io::ErrorKind::InvalidData, "hello too long to capture");
+        let session = stream.session();
+        Ok(Self {
+            client_random: written.hello_random.ok_or_else(missing)?,
+            server_random: read.hello_random.ok_or_else(missing)?,
+            client_session_id: written.hello_session_id.clone().ok_or_else(missing)?,
+            server_session_id: read.hello_session_id.clone().ok_or_else(missing)?,
+            protocol_version: session.protocol_version(),
+            cipher_suite: session.negotiated_cipher_suite().map(|s| s.suite()),
+            kx_group: stream.kx_group(),
+            alpn_protocol: session.alpn_protocol().map(<[u8]>::to_vec),
+            chain: session
+                .peer_certificates()
+                .map(<[Certificate]>::to_vec)
+                .unwrap_or_default(),
+            handshake: stream
+                .handshake_summary()
+                .expect("handshake finished before capturing"),
+        })
+    }
+}
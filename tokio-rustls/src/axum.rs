@@ -0,0 +1,42 @@
+//! axum `Listener` implementation, so `axum::serve(tls_listener, app)` serves https.
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::net::TcpStream;
+
+use crate::{server::TlsStream, TlsError, TlsListener};
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match TlsListener::accept(self).await {
+                Ok(accepted) => return accepted,
+                // A failed handshake only affects its own connection.
+                Err(
+                    TlsError::Rustls(_) | TlsError::HandshakeAlert(_) | TlsError::Context { .. },
+                ) => (),
+                Err(TlsError::Io(e)) if !is_accept_error(&e) => (),
+                // Errors like running out of fds; back off like axum does for tcp.
+                Err(TlsError::Io(_)) => tokio::time::sleep(Duration::from_secs(1)).await,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        TlsListener::local_addr(self)
+    }
+}
+
+fn is_accept_error(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::BrokenPipe
+    )
+}
@@ -0,0 +1,117 @@
+//! Base64 (RFC 4648) for proxy credentials, key pins and ACME requests.
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+#[cfg(feature = "acme")]
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[cfg(any(feature = "proxy", feature = "acme", test))]
+fn encode_with(data: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(alphabet[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else if pad {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Standard base64, padded.
+#[cfg(feature = "proxy")]
+pub(crate) fn encode(data: &[u8]) -> String {
+    encode_with(data, STANDARD, true)
+}
+
+/// URL-safe base64, unpadded, as JOSE wants it.
+#[cfg(feature = "acme")]
+pub(crate) fn encode_url(data: &[u8]) -> String {
+    encode_with(data, URL_SAFE, false)
+}
+
+/// Decode standard, padded base64. Anything else is rejected rather than read leniently:
+/// the URL-safe alphabet, missing or misplaced padding, and set bits past the last byte.
+pub(crate) fn decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    if input.len() % 4 != 0 {
+        return None;
+    }
+    let data = input
+        .strip_suffix(b"==")
+        .or_else(|| input.strip_suffix(b"="))
+        .unwrap_or(input);
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in data {
+        let v = STANDARD.iter().position(|&a| a == c)? as u32;
+        acc = (acc << 6 | v) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // The bits of the last character past the last byte are zero in the canonical encoding.
+    if acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test vectors of RFC 4648, section 10.
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn rfc4648_vectors() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(encode_with(plain.as_bytes(), STANDARD, true), encoded);
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..data.len() {
+            let encoded = encode_with(&data[..len], STANDARD, true);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode(&encoded).unwrap(), &data[..len]);
+        }
+    }
+
+    #[test]
+    fn decode_is_strict() {
+        let rejected = |inputs: &[&str]| {
+            for input in inputs {
+                assert_eq!(decode(input), None, "{input:?}");
+            }
+        };
+        // URL-safe, alone and mixed with the standard alphabet.
+        rejected(&["-_8=", "+_8="]);
+        // Missing, misplaced and excess padding.
+        rejected(&["Zg", "Zm8", "Z=g=", "Z===", "Zg===", "===="]);
+        // Set bits past the last byte.
+        rejected(&["Zh==", "Zm9="]);
+        // Not base64 at all.
+        rejected(&["Zm9\n", "Zm 9"]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Driving many client handshakes with bounded concurrency.
+use std::{
+    future::poll_fn,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    task::JoinSet,
+};
+use rustls_fork_shadow_tls::ServerName;
+
+use crate::{client::TlsStream, TlsConnector, TlsError};
+
+type Outcome<IO> = (ServerName, Result<TlsStream<IO>, TlsError>);
+
+/// Handshakes started by [`TlsConnector::handshake_all`], yielded as they finish.
+///
+/// Each handshake runs in its own task on the current runtime. Dropping this aborts the ones
+/// still running.
+pub struct Handshakes<IO, I> {
+    connector: TlsConnector,
+    pending: I,
+    running: JoinSet<Outcome<IO>>,
+    concurrency: usize,
+}
+
+impl<IO, I> Handshakes<IO, I>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I: Iterator<Item = (IO, ServerName)>,
+{
+    pub(crate) fn new(connector: TlsConnector, pending: I, concurrency: usize) -> Self {
+        Self {
+            connector,
+            pending,
+            running: JoinSet::new(),
+            concurrency: concurrency.max(1),
+        }
+    }
+
+    fn start(&mut self) {
+        while self.running.len() < self.concurrency {
+            let Some((io, domain)) = self.pending.next() else {
+                break;
+            };
+            let connector = self.connector.clone();
+            self.running.spawn(async move {
+                let res = connector.connect(domain.clone(), io).await;
+                (domain, res)
+            });
+        }
+    }
+
+    /// The next handshake to finish with the name it was for, or `None` when all are done.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Outcome<IO>>> {
+        self.start();
+        match ready!(self.running.poll_join_next(cx)) {
+            Some(Ok(outcome)) => Poll::Ready(Some(outcome)),
+            Some(Err(e)) => std::panic::resume_unwind(e.into_panic()),
+            None => Poll::Ready(None),
+        }
+    }
+
+    /// See [`poll_next`](Self::poll_next).
+    pub async fn next(&mut self) -> Option<Outcome<IO>> {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<IO, I> futures_core::Stream for Handshakes<IO, I>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I: Iterator<Item = (IO, ServerName)> + Unpin,
+{
+    type Item = Outcome<IO>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Handshakes::poll_next(self.get_mut(), cx)
+    }
+}
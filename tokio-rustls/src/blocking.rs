@@ -0,0 +1,120 @@
+//! Blocking TLS streams, for callers without an async runtime.
+use std::{
+    io::{self, Read, Write},
+    net,
+    ops::{Deref, DerefMut},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    runtime::{Builder, Runtime},
+};
+
+use rustls_fork_shadow_tls::{
+    ClientConnection, ConnectionCommon, ServerConnection, ServerName, SideData,
+};
+
+use crate::{stream::Stream, TlsAcceptor, TlsConnector, TlsError};
+
+/// A TLS stream over TCP with blocking reads and writes, for CLI tools and build scripts
+/// which don't run an async runtime. It is built with the same connectors and acceptors, and
+/// drives the async stream on a current-thread runtime of its own.
+///
+/// Don't use it from within a runtime: tokio panics on the nested `block_on`.
+pub struct BlockingTlsStream<C> {
+    // Declared first, so the socket is dropped while the runtime it is registered with is
+    // still alive.
+    stream: Stream<TcpStream, C>,
+    runtime: Runtime,
+}
+
+impl BlockingTlsStream<ClientConnection> {
+    /// Resolve `addr` (`host:port`), connect and perform the handshake, as
+    /// [`TlsConnector::connect_addr`] does.
+    pub fn connect(connector: &TlsConnector, addr: &str) -> Result<Self, TlsError> {
+        let runtime = runtime()?;
+        let stream = runtime.block_on(connector.connect_addr(addr))?;
+        Ok(Self { stream, runtime })
+    }
+
+    /// Perform the handshake over a connected socket.
+    pub fn connect_with(
+        connector: &TlsConnector,
+        domain: ServerName,
+        tcp: net::TcpStream,
+    ) -> Result<Self, TlsError> {
+        let runtime = runtime()?;
+        let stream = runtime.block_on(async {
+            let tcp = register(tcp)?;
+            connector.connect(domain, tcp).await
+        })?;
+        Ok(Self { stream, runtime })
+    }
+}
+
+impl BlockingTlsStream<ServerConnection> {
+    /// Perform the handshake over a socket accepted from a `std::net::TcpListener`.
+    pub fn accept(acceptor: &TlsAcceptor, tcp: net::TcpStream) -> Result<Self, TlsError> {
+        let runtime = runtime()?;
+        let stream = runtime.block_on(async {
+            let tcp = register(tcp)?;
+            acceptor.accept(tcp).await
+        })?;
+        Ok(Self { stream, runtime })
+    }
+}
+
+impl<C> BlockingTlsStream<C> {
+    /// The async stream, for the session, statistics and the rest of its accessors.
+    pub fn get_ref(&self) -> &Stream<TcpStream, C> {
+        &self.stream
+    }
+
+    /// The async stream, e.g. to set read and write timeouts.
+    pub fn get_mut(&mut self) -> &mut Stream<TcpStream, C> {
+        &mut self.stream
+    }
+}
+
+impl<C, SD: SideData + 'static> BlockingTlsStream<C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    /// Send a close_notify and shut the socket down for writing.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        self.runtime.block_on(self.stream.shutdown())
+    }
+}
+
+impl<C, SD: SideData + 'static> Read for BlockingTlsStream<C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.runtime.block_on(self.stream.read(buf))
+    }
+}
+
+impl<C, SD: SideData + 'static> Write for BlockingTlsStream<C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.runtime.block_on(self.stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.runtime.block_on(self.stream.flush())
+    }
+}
+
+fn runtime() -> io::Result<Runtime> {
+    Builder::new_current_thread().enable_all().build()
+}
+
+/// Register `tcp` with the runtime of the current `block_on`.
+fn register(tcp: net::TcpStream) -> io::Result<TcpStream> {
+    tcp.set_nonblocking(true)?;
+    TcpStream::from_std(tcp)
+}
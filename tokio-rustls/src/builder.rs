@@ -0,0 +1,355 @@
+//! Connectors and acceptors built in one go, without the rustls config builders.
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use rustls_fork_shadow_tls::{
+    server::{
+        AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoServerSessionStorage,
+    },
+    Certificate, ClientConfig, Error, RootCertStore, ServerConfig, SupportedProtocolVersion,
+    Ticketer, ALL_VERSIONS,
+};
+
+#[cfg(feature = "dangerous_configuration")]
+use crate::verify::ClockedClientVerifier;
+use crate::{
+    clock::{Clock, TimeProvider},
+    keys::Identity,
+    ticketer::RotatingTicketer,
+    TlsAcceptor, TlsConnector,
+};
+
+/// How often rustls' `Ticketer` rotates its keys.
+const DEFAULT_TICKET_LIFETIME: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Builds a [`TlsConnector`] for the common case: servers verified against a set of roots,
+/// rustls' safe default cipher suites and key exchange groups, and no client certificate
+/// unless one is given. Start from [`TlsConnector::builder`].
+///
+/// Options which don't go into the rustls config, such as pins or version limits, are set on
+/// the built connector.
+#[derive(Debug, Clone)]
+pub struct TlsConnectorBuilder {
+    roots: RootCertStore,
+    alpn_protocols: Vec<Vec<u8>>,
+    versions: Vec<&'static SupportedProtocolVersion>,
+    enable_sni: bool,
+    identity: Option<Identity>,
+}
+
+impl Default for TlsConnectorBuilder {
+    fn default() -> Self {
+        Self {
+            roots: RootCertStore::empty(),
+            alpn_protocols: Vec::new(),
+            versions: ALL_VERSIONS.to_vec(),
+            enable_sni: true,
+            identity: None,
+        }
+    }
+}
+
+impl TlsConnectorBuilder {
+    /// A builder trusting no roots yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the Mozilla roots bundled with the `webpki-roots` crate.
+    #[cfg(feature = "webpki-roots")]
+    pub fn with_mozilla_roots(mut self) -> Self {
+        self.roots
+            .add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                rustls_fork_shadow_tls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+        self
+    }
+
+    /// Trust the roots of the operating system's certificate store. Certificates in the store
+    /// which can't be parsed are skipped; errors only when the store can't be read.
+    #[cfg(feature = "native-roots")]
+    pub fn with_native_roots(mut self) -> io::Result<Self> {
+        let certs: Vec<Vec<u8>> = rustls_native_certs::load_native_certs()?
+            .into_iter()
+            .map(|cert| cert.0)
+            .collect();
+        self.roots.add_parsable_certificates(&certs);
+        Ok(self)
+    }
+
+    /// Trust `cert`, e.g. a private CA. Errors when it isn't a valid certificate.
+    pub fn with_root_certificate(mut self, cert: &Certificate) -> io::Result<Self> {
+        self.roots
+            .add(cert)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{e:?}")))?;
+        Ok(self)
+    }
+
+    /// Trust every `CERTIFICATE` block of PEM input.
+    pub fn with_root_certificates_pem(mut self, mut pem: &[u8]) -> io::Result<Self> {
+        for der in rustls_pemfile::certs(&mut pem)? {
+            self = self.with_root_certificate(&Certificate(der))?;
+        }
+        Ok(self)
+    }
+
+    /// Trust the roots of an upstream rustls root store.
+    #[cfg(feature = "rustls-interop")]
+    pub fn with_rustls_roots(mut self, roots: &rustls::RootCertStore) -> Self {
+        let roots = crate::interop::root_store_from_rustls(roots);
+        self.roots.roots.extend(roots.roots);
+        self
+    }
+
+    /// Take the ALPN protocols and the SNI setting of an upstream rustls config. Its roots
+    /// can't be read back out of it: pass the store it was built from to
+    /// [`with_rustls_roots`](Self::with_rustls_roots).
+    #[cfg(feature = "rustls-interop")]
+    pub fn with_rustls_options(mut self, config: &rustls::ClientConfig) -> Self {
+        self.alpn_protocols = config.alpn_protocols.clone();
+        self.enable_sni = config.enable_sni;
+        self
+    }
+
+    /// ALPN protocols to offer, most preferred first, e.g. `h2` and `http/1.1`. None by
+    /// default.
+    pub fn with_alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+
+    /// Protocol versions to offer. All those rustls supports by default.
+    pub fn with_protocol_versions(
+        mut self,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Self {
+        self.versions = versions.to_vec();
+        self
+    }
+
+    /// Send the server name in the ClientHello. On by default; some servers behind IP
+    /// addresses or old middleboxes want it off.
+    pub fn with_sni(mut self, enabled: bool) -> Self {
+        self.enable_sni = enabled;
+        self
+    }
+
+    /// Present `identity` to servers which ask for a client certificate.
+    pub fn with_client_identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Errors when no root is trusted, when no cipher suite supports the versions, or when the
+    /// client identity's key can't be used.
+    pub fn build(self) -> io::Result<TlsConnector> {
+        if self.roots.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no root certificates to verify servers with",
+            ));
+        }
+        let invalid = |e: Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let builder = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&self.versions)
+            .map_err(invalid)?
+            .with_root_certificates(self.roots);
+        let mut config = match self.identity {
+            Some(identity) => builder
+                .with_single_cert(identity.chain, identity.key)
+                .map_err(invalid)?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = self.alpn_protocols;
+        config.enable_sni = self.enable_sni;
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+/// Whether a [`TlsAcceptorBuilder`] asks clients for a certificate.
+#[derive(Debug, Clone, Default)]
+pub enum ClientAuth {
+    /// Don't ask.
+    #[default]
+    None,
+    /// Ask, and verify the certificates of the clients which send one against the roots.
+    Optional(RootCertStore),
+    /// Turn away clients without a certificate issued under the roots.
+    Required(RootCertStore),
+}
+
+/// Builds a [`TlsAcceptor`] presenting one certificate chain, with rustls' safe default
+/// cipher suites and key exchange groups. Start from [`TlsAcceptor::builder`].
+///
+/// Options which don't go into the rustls config, such as version limits or the PROXY
+/// protocol, are set on the built acceptor.
+#[derive(Debug, Clone)]
+pub struct TlsAcceptorBuilder {
+    identity: Option<Identity>,
+    alpn_protocols: Vec<Vec<u8>>,
+    versions: Vec<&'static SupportedProtocolVersion>,
+    session_tickets: bool,
+    ticket_lifetime: Option<Duration>,
+    tls13_tickets: usize,
+    client_auth: ClientAuth,
+    clock: Clock,
+}
+
+impl Default for TlsAcceptorBuilder {
+    fn default() -> Self {
+        Self {
+            identity: None,
+            alpn_protocols: Vec::new(),
+            versions: ALL_VERSIONS.to_vec(),
+            session_tickets: false,
+            ticket_lifetime: None,
+            tls13_tickets: 1,
+            client_auth: ClientAuth::None,
+            clock: Clock::default(),
+        }
+    }
+}
+
+impl TlsAcceptorBuilder {
+    /// A builder without a certificate yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Present the chain and key of `identity`.
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Present the chain and key loaded from PEM input, see [`Identity::from_pem`].
+    pub fn with_single_cert_pem(self, chain_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        Ok(self.with_identity(Identity::from_pem(chain_pem, key_pem)?))
+    }
+
+    /// Present the chain and key loaded from PEM files, see [`Identity::from_pem_files`].
+    pub fn with_single_cert_pem_files(
+        self,
+        chain_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        Ok(self.with_identity(Identity::from_pem_files(chain_path, key_path)?))
+    }
+
+    /// ALPN protocols to accept, most preferred first. None by default, so no protocol is
+    /// negotiated whatever clients offer.
+    pub fn with_alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+
+    /// Protocol versions to accept. All those rustls supports by default.
+    pub fn with_protocol_versions(
+        mut self,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Self {
+        self.versions = versions.to_vec();
+        self
+    }
+
+    /// Issue session tickets, so clients resume sessions without the server keeping them.
+    /// Off by default: sessions are then resumed from an in-memory cache. The ticket keys are
+    /// random and rotate every 6 hours, so tickets don't survive a restart.
+    pub fn with_session_tickets(mut self, enabled: bool) -> Self {
+        self.session_tickets = enabled;
+        self
+    }
+
+    /// Rotate the ticket keys every `lifetime` rather than every 6 hours, and tell clients
+    /// tickets last that long. Tickets are accepted for up to twice the lifetime, until the
+    /// key after theirs is rotated out. Only applies with
+    /// [`with_session_tickets`](Self::with_session_tickets): the sessions of the in-memory
+    /// cache are given 24 hours by the rustls fork, and are evicted when the cache is full.
+    pub fn with_ticket_lifetime(mut self, lifetime: Duration) -> Self {
+        self.ticket_lifetime = Some(lifetime);
+        self
+    }
+
+    /// How many NewSessionTicket messages to send after a TLS 1.3 handshake: 0 to turn
+    /// resumption off, which also stops TLS 1.2 sessions being cached, or 1, the default. The
+    /// rustls fork sends no more than one per handshake, so larger counts fail with
+    /// `InvalidInput`.
+    ///
+    /// Tickets of the in-memory cache, used unless
+    /// [`with_session_tickets`](Self::with_session_tickets) is on, are single-use: a resumed
+    /// session takes its ticket out of the cache, and the client gets a new one.
+    pub fn with_tls13_tickets(mut self, count: usize) -> io::Result<Self> {
+        if count > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the rustls fork sends at most one session ticket per handshake",
+            ));
+        }
+        self.tls13_tickets = count;
+        Ok(self)
+    }
+
+    /// Whether to ask clients for a certificate. [`ClientAuth::None`] by default.
+    pub fn with_client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Take "now" from `provider` rather than the system clock, to rotate the session ticket
+    /// keys and, with the `dangerous_configuration` feature, to check the validity of client
+    /// certificates. Ticket keys then rotate every 6 hours of the provider's time unless
+    /// [`with_ticket_lifetime`](Self::with_ticket_lifetime) says otherwise.
+    pub fn with_time_provider(mut self, provider: impl TimeProvider) -> Self {
+        self.clock = Clock::new(provider);
+        self
+    }
+
+    /// Errors without a certificate, when no cipher suite supports the versions, when the key
+    /// doesn't fit the certificate, or when the ticket keys can't be generated.
+    pub fn build(self) -> io::Result<TlsAcceptor> {
+        let identity = self.identity.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no certificate to present")
+        })?;
+        let invalid = |e: Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let builder = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&self.versions)
+            .map_err(invalid)?;
+        let verifier = match self.client_auth {
+            ClientAuth::None => None,
+            ClientAuth::Optional(roots) => Some(AllowAnyAnonymousOrAuthenticatedClient::new(roots)),
+            ClientAuth::Required(roots) => Some(AllowAnyAuthenticatedClient::new(roots)),
+        };
+        #[cfg(feature = "dangerous_configuration")]
+        let verifier = match self.clock.is_system() {
+            true => verifier,
+            false => verifier.map(|inner| ClockedClientVerifier::new(inner, self.clock.clone())),
+        };
+        let builder = match verifier {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(identity.chain, identity.key)
+            .map_err(invalid)?;
+        config.alpn_protocols = self.alpn_protocols;
+        if self.tls13_tickets == 0 {
+            config.session_storage = Arc::new(NoServerSessionStorage {});
+        } else if self.session_tickets {
+            config.ticketer = match self.ticket_lifetime {
+                Some(lifetime) => Arc::new(RotatingTicketer::new(lifetime, self.clock)?),
+                None if !self.clock.is_system() => {
+                    Arc::new(RotatingTicketer::new(DEFAULT_TICKET_LIFETIME, self.clock)?)
+                }
+                None => Ticketer::new().map_err(invalid)?,
+            };
+        }
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
@@ -0,0 +1,254 @@
+//! Answering failed handshakes without giving the server away to active probes.
+use std::{fmt, io, ops::Range, time::Duration};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time::Instant,
+};
+
+use crate::{
+    record,
+    server::{Accepted, TlsStream},
+    TlsError,
+};
+
+/// Largest record payload, which a dripped record announces.
+const DRIPPED_RECORD_LEN: u16 = 0x4000;
+
+/// What an acceptor does with a connection whose handshake failed, set with
+/// `TlsAcceptor::with_failure_response`.
+///
+/// A relay answering probes with its own alerts, at its own speed, stands out from the server
+/// it imitates. The other responses make every failure look the same, or look like the
+/// camouflage server itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FailureResponse {
+    /// Send the alert rustls picked and close right away.
+    #[default]
+    Alert,
+    /// Send no alert, and close after a delay drawn uniformly from `delay`.
+    Silent { delay: Range<Duration> },
+    /// Send no alert, and hand the connection to the server at `backend` (`host:port`):
+    /// everything the client sent is replayed to it, then the two are relayed until either
+    /// side closes. The relay is a [`Relay`] of its own, see
+    /// `TlsAcceptor::accept_or_relay`.
+    Relay { backend: String },
+    /// Send no alert, and hold the connection as the [`Tarpit`] says before closing it.
+    Tarpit(Tarpit),
+}
+
+/// How a client turned away is held before its connection is closed, to raise the cost of
+/// scanning the server. Set with `TlsAcceptor::with_tarpit` for the clients the ClientHello
+/// policy tarpits, and with [`FailureResponse::Tarpit`] for failed handshakes.
+///
+/// A held connection keeps its slot of `TlsListener::with_max_handshakes` and
+/// `with_max_connections`, so those bound how many are held at once, and the listener's
+/// handshake timeout cuts holds short. The hold ends early when the client closes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tarpit {
+    /// Answer nothing for `duration`, discarding what the client sends.
+    Stall { duration: Duration },
+    /// Answer with the header of a handshake record announcing 16 KiB, then the record's
+    /// payload, one byte every `interval` for `duration`, so clients keep waiting for the
+    /// record to complete. The bytes are random.
+    Drip {
+        interval: Duration,
+        duration: Duration,
+    },
+}
+
+impl Default for Tarpit {
+    /// Stall for 30 seconds.
+    fn default() -> Self {
+        Self::Stall {
+            duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Tarpit {
+    /// Hold the client on `io`, then close the connection.
+    pub(crate) async fn hold<IO>(&self, io: &mut IO)
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (interval, duration) = match self {
+            Self::Stall { duration } => (None, *duration),
+            // Dripping without pause would be a flood.
+            Self::Drip { interval, duration } => {
+                (Some((*interval).max(Duration::from_millis(1))), *duration)
+            }
+        };
+        let mut buf = [0; 1024];
+        let _ = tokio::time::timeout(duration, async {
+            let [len_hi, len_lo] = DRIPPED_RECORD_LEN.to_be_bytes();
+            let header = [record::CONTENT_TYPE_HANDSHAKE, 3, 3, len_hi, len_lo];
+            let mut sent = 0;
+            let mut next = interval.map(|interval| Instant::now() + interval);
+            loop {
+                let read = match next {
+                    Some(at) => tokio::time::timeout_at(at, io.read(&mut buf)).await,
+                    None => Ok(io.read(&mut buf).await),
+                };
+                match read {
+                    Ok(Ok(0) | Err(_)) => break,
+                    Ok(Ok(_)) => (),
+                    // Time for the next byte.
+                    Err(_) => {
+                        let byte = match header.get(sent) {
+                            Some(byte) => *byte,
+                            None => random_byte(),
+                        };
+                        if io.write_all(&[byte]).await.is_err() || io.flush().await.is_err() {
+                            break;
+                        }
+                        sent += 1;
+                        next = next.zip(interval).map(|(at, interval)| at + interval);
+                    }
+                }
+            }
+        })
+        .await;
+        let _ = io.shutdown().await;
+    }
+}
+
+impl FailureResponse {
+    pub(crate) fn sends_alert(&self) -> bool {
+        matches!(self, Self::Alert)
+    }
+
+    pub(crate) fn captures(&self) -> bool {
+        matches!(self, Self::Relay { .. })
+    }
+
+    /// Respond to the failed handshake of `stream`, returning the error `accept` fails with,
+    /// or the relay to the backend.
+    pub(crate) async fn respond<IO>(
+        &self,
+        mut stream: TlsStream<IO>,
+        err: io::Error,
+    ) -> Result<Accepted<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self {
+            Self::Alert => (),
+            Self::Silent { delay } => {
+                tokio::time::sleep(random_delay(delay)).await;
+                let _ = stream.get_mut().shutdown().await;
+            }
+            Self::Relay { backend } => {
+                let mut sent = stream.capture.take().unwrap_or_default();
+                // Read from the IO but not yet by rustls, and lost with the stream otherwise.
+                sent.extend(stream.take_buffered_ciphertext());
+                let (io, _) = stream.into_inner();
+                let err = io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "tls handshake failed, relayed to the backend",
+                );
+                return Ok(Accepted::Relayed(Relay {
+                    io,
+                    backend: backend.clone(),
+                    sent,
+                    error: TlsError::from_handshake(err),
+                }));
+            }
+            Self::Tarpit(tarpit) => tarpit.hold(stream.get_mut()).await,
+        }
+        Err(TlsError::from_handshake(err))
+    }
+}
+
+/// A connection handed to a TLS server rather than accepted: a client the fallback of
+/// `TlsAcceptor::with_fallback` didn't authenticate, or one whose handshake failed under
+/// [`FailureResponse::Relay`]. Returned by `TlsAcceptor::accept_or_relay`, for the caller to
+/// [`run`](Self::run) off its accept path.
+pub struct Relay<IO> {
+    pub(crate) io: IO,
+    pub(crate) backend: String,
+    /// Everything the client sent before the relay, replayed to the backend first.
+    pub(crate) sent: Vec<u8>,
+    pub(crate) error: TlsError,
+}
+
+impl<IO> fmt::Debug for Relay<IO> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Relay")
+            .field("backend", &self.backend)
+            .field("sent", &self.sent.len())
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<IO> Relay<IO> {
+    /// The server the connection is relayed to, as `host:port`.
+    pub fn backend(&self) -> &str {
+        &self.backend
+    }
+
+    /// Why the connection wasn't accepted: the error `TlsAcceptor::accept` fails with once
+    /// the relay ends.
+    pub fn error(&self) -> &TlsError {
+        &self.error
+    }
+
+    /// The bytes the client sent before the relay, which are replayed to the backend.
+    pub fn sent(&self) -> &[u8] {
+        &self.sent
+    }
+}
+
+impl<IO> Relay<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Connect to the backend, replay what the client sent, then relay the two until either
+    /// side closes.
+    pub async fn run(mut self) -> io::Result<()> {
+        self.relay().await
+    }
+
+    pub(crate) async fn relay(&mut self) -> io::Result<()> {
+        relay(&mut self.io, &self.backend, &self.sent).await
+    }
+}
+
+/// Connect to `backend`, replay `sent` to it, then relay it and `io` until either side closes.
+pub(crate) async fn relay<IO>(io: &mut IO, backend: &str, sent: &[u8]) -> io::Result<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let res = async {
+        let mut backend = TcpStream::connect(backend).await?;
+        backend.write_all(sent).await?;
+        tokio::io::copy_bidirectional(io, &mut backend).await
+    }
+    .await;
+    #[cfg(feature = "tracing")]
+    if let Err(e) = &res {
+        tracing::debug!(%e, backend, "relaying to the backend failed");
+    }
+    res.map(drop)
+}
+
+/// A delay in `range`, unpredictable to whoever measures it.
+fn random_delay(range: &Range<Duration>) -> Duration {
+    let span = range.end.saturating_sub(range.start);
+    let mut bytes = [0; 8];
+    if span.is_zero() || SystemRandom::new().fill(&mut bytes).is_err() {
+        return range.start;
+    }
+    // 53 random bits give a uniform float in [0, 1).
+    let sample = (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64;
+    range.start + span.mul_f64(sample)
+}
+
+fn random_byte() -> u8 {
+    let mut byte = [0];
+    let _ = SystemRandom::new().fill(&mut byte);
+    byte[0]
+}
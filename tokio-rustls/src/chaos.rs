@@ -0,0 +1,226 @@
+//! Fault-injecting IO wrapper for testing streams over adversarial transports.
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+/// Seed used unless `with_seed` is called, so runs are reproducible by default.
+const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Wraps an IO and injects faults into it.
+///
+/// Every fault fires with the configured probability per operation, drawn from a PRNG seeded
+/// with `with_seed`, so the same seed and sequence of operations gives the same schedule.
+/// Faults which return `Pending` always arrange for the task to be woken again.
+pub struct ChaosIo<IO> {
+    inner: IO,
+    rng: u64,
+    short_read: f64,
+    short_write: f64,
+    would_block: f64,
+    delay: f64,
+    delay_duration: Duration,
+    corrupt: f64,
+    corrupt_read_at: Vec<u64>,
+    eof_after_read: Option<u64>,
+    bytes_read: u64,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<IO> ChaosIo<IO> {
+    /// Wrap `inner` with no faults enabled.
+    pub fn new(inner: IO) -> Self {
+        Self {
+            inner,
+            rng: DEFAULT_SEED,
+            short_read: 0.0,
+            short_write: 0.0,
+            would_block: 0.0,
+            delay: 0.0,
+            delay_duration: Duration::ZERO,
+            corrupt: 0.0,
+            corrupt_read_at: Vec::new(),
+            eof_after_read: None,
+            bytes_read: 0,
+            sleep: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // xorshift gets stuck at zero.
+        self.rng = if seed == 0 { DEFAULT_SEED } else { seed };
+        self
+    }
+
+    /// Return fewer bytes than asked for from reads, at least one.
+    pub fn with_short_reads(mut self, probability: f64) -> Self {
+        self.short_read = probability;
+        self
+    }
+
+    /// Accept fewer bytes than given to writes, at least one.
+    pub fn with_short_writes(mut self, probability: f64) -> Self {
+        self.short_write = probability;
+        self
+    }
+
+    /// Return `Pending` and wake the task immediately, as a spurious `WouldBlock` would.
+    pub fn with_would_block(mut self, probability: f64) -> Self {
+        self.would_block = probability;
+        self
+    }
+
+    /// Return `Pending` and wake the task only after `duration`.
+    pub fn with_delayed_wakeups(mut self, probability: f64, duration: Duration) -> Self {
+        self.delay = probability;
+        self.delay_duration = duration;
+        self
+    }
+
+    /// Flip a random bit in the bytes returned by a read.
+    pub fn with_corruption(mut self, probability: f64) -> Self {
+        self.corrupt = probability;
+        self
+    }
+
+    /// Flip the lowest bit of the byte at `offset` in the read stream.
+    pub fn with_corrupt_read_at(mut self, offset: u64) -> Self {
+        self.corrupt_read_at.push(offset);
+        self
+    }
+
+    /// Report EOF once `bytes` have been read, even in the middle of a record.
+    pub fn with_eof_after_read(mut self, bytes: u64) -> Self {
+        self.eof_after_read = Some(bytes);
+        self
+    }
+
+    pub fn get_ref(&self) -> &IO {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> IO {
+        self.inner
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn roll(&mut self, probability: f64) -> bool {
+        // 53 random bits give a uniform float in [0, 1).
+        let sample = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        probability > 0.0 && sample < probability
+    }
+
+    /// A length in `1..=len` if a short operation fires, `len` otherwise.
+    fn shorten(&mut self, len: usize, probability: f64) -> usize {
+        if len > 1 && self.roll(probability) {
+            1 + (self.next_u64() % len as u64) as usize
+        } else {
+            len
+        }
+    }
+
+    /// Decide whether this operation is interrupted by `WouldBlock` or a delayed wakeup.
+    fn poll_interrupt(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(sleep) = self.sleep.as_mut() {
+            ready!(sleep.as_mut().poll(cx));
+            self.sleep = None;
+            return Poll::Ready(());
+        }
+        if self.roll(self.would_block) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        if self.roll(self.delay) {
+            let mut sleep = Box::pin(tokio::time::sleep(self.delay_duration));
+            if sleep.as_mut().poll(cx).is_pending() {
+                self.sleep = Some(sleep);
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for ChaosIo<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_interrupt(cx));
+
+        let mut limit = buf.remaining();
+        if let Some(eof) = this.eof_after_read {
+            let left = eof.saturating_sub(this.bytes_read);
+            if left == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            limit = limit.min(left.try_into().unwrap_or(usize::MAX));
+        }
+        limit = this.shorten(limit, this.short_read);
+
+        let mut limited = buf.take(limit);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let n = limited.filled().len();
+        let start = this.bytes_read;
+        let data = limited.filled_mut();
+        for &offset in &this.corrupt_read_at {
+            if (start..start + n as u64).contains(&offset) {
+                data[(offset - start) as usize] ^= 1;
+            }
+        }
+        if n > 0 && this.roll(this.corrupt) {
+            let bit = this.next_u64();
+            data[(bit >> 3) as usize % n] ^= 1 << (bit & 7);
+        }
+
+        // SAFETY: the inner read initialized the first `n` bytes of the unfilled part.
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+        this.bytes_read += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for ChaosIo<IO> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_interrupt(cx));
+        let len = this.shorten(buf.len(), this.short_write);
+        Pin::new(&mut this.inner).poll_write(cx, &buf[..len])
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_interrupt(cx));
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
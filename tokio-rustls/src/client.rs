@@ -1,11 +1,60 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    io::{self, Write},
+    pin::Pin,
+    sync::Arc,
+};
+#[cfg(feature = "dangerous_configuration")]
+use std::time::{Duration, SystemTime};
 
-use tokio::io::{AsyncRead, AsyncWrite};
-use rustls_fork_shadow_tls::{ClientConfig, ClientConnection};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use rustls_fork_shadow_tls::{
+    client::{NoClientSessionStorage, ResolvesClientCert},
+    sign::{self, CertifiedKey, SigningKey},
+    Certificate, CipherSuite, ClientConfig, ClientConnection, Error, KeyLog, NamedGroup,
+    ProtocolVersion, ServerName, SignatureScheme,
+};
+#[cfg(feature = "dangerous_configuration")]
+use rustls_fork_shadow_tls::{
+    client::{ServerCertVerifier, WebPkiVerifier},
+    RootCertStore,
+};
 
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, StreamMetrics};
+#[cfg(feature = "pcap")]
+use crate::pcap::PcapCapture;
+#[cfg(feature = "dangerous_configuration")]
 use crate::{
+    ocsp::StaplePolicy,
+    verify::{FnVerifier, Layers, NoVerifier, VerifyNames},
+};
+use crate::{
+    artifacts::HandshakeArtifacts,
+    batch::Handshakes,
+    builder::TlsConnectorBuilder,
+    clock::{Clock, TimeProvider},
+    crl::CrlSet,
+    ct::{CtPolicy, Sct},
+    observer::{HandshakeObserver, ObserverSlot},
+    dial,
+    error::ErrorContext,
+    flush::{DeferredFlush, FlushScheduler},
+    keys::Identity,
+    memory::{MemoryBudget, MemoryCharge},
+    negotiated::Allowed,
+    ocsp::StapleSlot,
+    pin::PinSet,
+    probe::ProbeReport,
+    record,
     split::{ReadHalf, WriteHalf},
     stream::Stream,
+    tap::RecordTap,
     TlsError,
 };
 
@@ -16,27 +65,530 @@ pub type TlsStreamReadHalf<IO> = ReadHalf<IO, ClientConnection>;
 /// TlsStream for write only.
 pub type TlsStreamWriteHalf<IO> = WriteHalf<IO, ClientConnection>;
 
+/// Fragment size, header included, at which the records of a connection nested in another
+/// fit whole in 16 KiB of the outer's plaintext: 16384 less the largest expansion of a
+/// record, 24 bytes for the explicit nonce and tag of TLS 1.2's AES-GCM.
+const LAYERED_FRAGMENT_SIZE: usize = 16384 - 24;
+
 /// A wrapper around a `rustls::ClientConfig`, providing an async `connect` method.
 #[derive(Clone)]
 pub struct TlsConnector {
     inner: Arc<ClientConfig>,
+    nodelay: bool,
+    error_context: bool,
+    /// Off for connections which neither resume a session nor store theirs.
+    resumption: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    observer: Option<Arc<dyn HandshakeObserver>>,
+    pins: Option<PinSet>,
+    crls: Option<CrlSet>,
+    ct: Option<CtPolicy>,
+    clock: Clock,
+    allowed: Allowed,
+    budget: Option<MemoryBudget>,
+    flush_scheduler: Option<FlushScheduler>,
+    record_tap: Option<Arc<dyn RecordTap>>,
+    #[cfg(feature = "pcap")]
+    pcap: Option<PcapCapture>,
+    #[cfg(feature = "dangerous_configuration")]
+    verification: Layers,
 }
 
 impl From<Arc<ClientConfig>> for TlsConnector {
     fn from(inner: Arc<ClientConfig>) -> TlsConnector {
-        TlsConnector { inner }
+        TlsConnector {
+            inner,
+            nodelay: false,
+            error_context: false,
+            resumption: true,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            observer: None,
+            pins: None,
+            crls: None,
+            ct: None,
+            clock: Clock::default(),
+            allowed: Allowed::default(),
+            budget: None,
+            flush_scheduler: None,
+            record_tap: None,
+            #[cfg(feature = "pcap")]
+            pcap: None,
+            #[cfg(feature = "dangerous_configuration")]
+            verification: Layers::default(),
+        }
     }
 }
 
 impl From<ClientConfig> for TlsConnector {
     fn from(inner: ClientConfig) -> TlsConnector {
-        TlsConnector {
-            inner: Arc::new(inner),
-        }
+        TlsConnector::from(Arc::new(inner))
+    }
+}
+
+#[cfg(feature = "webpki-roots")]
+impl Default for TlsConnector {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl TlsConnector {
+    /// A connector trusting the Mozilla roots, with safe defaults and no client certificate.
+    /// See [`builder`](Self::builder) for other roots and options.
+    #[cfg(feature = "webpki-roots")]
+    pub fn new() -> Self {
+        Self::builder()
+            .with_mozilla_roots()
+            .build()
+            .expect("the bundled roots and default versions make a valid config")
+    }
+
+    /// Build a connector from roots and common options, without the rustls config builder.
+    pub fn builder() -> TlsConnectorBuilder {
+        TlsConnectorBuilder::new()
+    }
+
+    /// The rustls config used for new connections.
+    pub fn config(&self) -> &Arc<ClientConfig> {
+        &self.inner
+    }
+
+    /// A copy of this connector using `config`, keeping the other options.
+    #[cfg(feature = "tower")]
+    pub(crate) fn with_config(&self, config: Arc<ClientConfig>) -> Self {
+        Self {
+            inner: config,
+            ..self.clone()
+        }
+    }
+
+    /// Log the TLS secrets of new connections to `key_log`.
+    pub fn with_key_log(mut self, key_log: Arc<dyn KeyLog>) -> Self {
+        Arc::make_mut(&mut self.inner).key_log = key_log;
+        self
+    }
+
+    /// Log the TLS secrets of new connections to the file named by the `SSLKEYLOGFILE`
+    /// environment variable, in the NSS key log format Wireshark reads. Nothing is logged when
+    /// the variable is unset.
+    #[cfg(feature = "key_log")]
+    pub fn with_key_log_file(self) -> Self {
+        self.with_key_log(Arc::new(rustls_fork_shadow_tls::KeyLogFile::new()))
+    }
+
+    /// Write the records of new connections to `capture`, along with their secrets. This
+    /// replaces the key log.
+    #[cfg(feature = "pcap")]
+    pub fn with_pcap(mut self, capture: PcapCapture) -> Self {
+        Arc::make_mut(&mut self.inner).key_log = Arc::new(capture.clone());
+        self.pcap = Some(capture);
+        self
+    }
+
+    /// Record the handshakes and traffic of new connections in `metrics`.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Only accept connections which negotiate TLS 1.3.
+    pub fn tls13_only(self) -> Self {
+        self.min_version(ProtocolVersion::TLSv1_3)
+    }
+
+    /// Only accept connections which negotiate TLS 1.2.
+    ///
+    /// The client still offers every version its config supports, so a server preferring
+    /// TLS 1.3 fails the connection instead of falling back. Build the config with
+    /// `with_protocol_versions(&[&version::TLS12])` to offer only TLS 1.2.
+    pub fn tls12_only(mut self) -> Self {
+        self.allowed.min_version = Some(ProtocolVersion::TLSv1_2);
+        self.allowed.max_version = Some(ProtocolVersion::TLSv1_2);
+        self
+    }
+
+    /// Fail connections which negotiate a version older than `version`. Connecting fails
+    /// up front when the config supports none which is recent enough.
+    pub fn min_version(mut self, version: ProtocolVersion) -> Self {
+        self.allowed.min_version = Some(version);
+        self.allowed.max_version = None;
+        self
+    }
+
+    /// Fail connections which negotiate a cipher suite other than `suites`, e.g. to rule out
+    /// CBC or to meet a compliance profile. Errors when a suite isn't implemented by rustls.
+    ///
+    /// Like the version restrictions, this checks what was negotiated rather than changing
+    /// what the config offers; connecting fails up front when the config can't use any.
+    pub fn with_cipher_suites(mut self, suites: &[CipherSuite]) -> io::Result<Self> {
+        self.allowed.set_cipher_suites(suites)?;
+        Ok(self)
+    }
+
+    /// Fail connections whose key exchange used a group other than `groups`, e.g. to require
+    /// X25519 or P-256. Errors when a group isn't implemented by rustls.
+    ///
+    /// The server still picks among the groups the config offers. Resumed TLS 1.2 sessions
+    /// have no key exchange and are let through.
+    pub fn with_kx_groups(mut self, groups: &[NamedGroup]) -> io::Result<Self> {
+        self.allowed.set_kx_groups(groups)?;
+        Ok(self)
+    }
+
+    /// Fragment outgoing records into at most `size` bytes, header and overhead included, so
+    /// a constrained client never sends a record larger than it can afford to buffer. Errors
+    /// unless `size` is between 32 and 16389.
+    ///
+    /// The limit only applies to what this side writes: the rustls fork implements neither the
+    /// `record_size_limit` nor the `max_fragment_length` extension, so the server is not told
+    /// and may still send full 16 KiB records.
+    pub fn with_max_fragment_size(mut self, size: usize) -> io::Result<Self> {
+        record::check_fragment_size(size)?;
+        Arc::make_mut(&mut self.inner).max_fragment_size = Some(size);
+        Ok(self)
+    }
+
+    /// Accept any server certificate, without checking who the server is.
+    ///
+    /// This makes the connection open to interception. It is meant for development, test
+    /// rigs, and relays which talk to a camouflage server they deliberately don't validate.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn dangerous_disable_verification(self) -> Self {
+        self.with_verifier(Arc::new(NoVerifier))
+    }
+
+    /// Verify servers with WebPKI against `roots`, like a config built with them.
+    ///
+    /// Unlike a verifier set in the config, this one can be combined with
+    /// [`with_ocsp_stapling`](Self::with_ocsp_stapling) and
+    /// [`with_verify_names`](Self::with_verify_names).
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn with_webpki_roots(self, roots: RootCertStore) -> Self {
+        self.with_verifier(Arc::new(WebPkiVerifier::new(roots, None)))
+    }
+
+    /// Install `verifier` in the config, keeping it at hand to layer checks over.
+    #[cfg(feature = "dangerous_configuration")]
+    fn with_verifier(mut self, verifier: Arc<dyn ServerCertVerifier>) -> Self {
+        Arc::make_mut(&mut self.inner)
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
+        self.verification.base = Some(verifier);
+        self
+    }
+
+    /// Decide whether to trust the server with `verify`, called with the end-entity
+    /// certificate, the intermediates, the name connected to and the current time.
+    ///
+    /// This replaces the config's certificate verification entirely, including the check
+    /// against root certificates. The server's handshake signature is still checked against
+    /// its certificate.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn with_cert_verifier_fn<F>(self, verify: F) -> Self
+    where
+        F: Fn(&Certificate, &[Certificate], &ServerName, SystemTime) -> Result<(), Error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.with_verifier(Arc::new(FnVerifier(verify)))
+    }
+
+    /// Require the server's chain to contain a key from `pins`, on top of the config's
+    /// certificate verification.
+    pub fn with_pins(mut self, pins: PinSet) -> Self {
+        self.pins = Some(pins);
+        self
+    }
+
+    /// Trust the server if its chain contains a key from `pins`, instead of verifying it
+    /// against root certificates.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn with_pins_only(self, pins: PinSet) -> Self {
+        self.with_cert_verifier_fn(move |end_entity, intermediates, server_name, _now| {
+            let chain: Vec<Certificate> =
+                std::iter::once(end_entity).chain(intermediates).cloned().collect();
+            pins.check(server_name, &chain)
+        })
+    }
+
+    /// Check the server's chain against the revocation lists in `crls`, on top of the config's
+    /// certificate verification.
+    pub fn with_crls(mut self, crls: CrlSet) -> Self {
+        self.crls = Some(crls);
+        self
+    }
+
+    /// Require the server's certificate to be logged according to `policy`, on top of the
+    /// config's certificate verification. The SCTs which checked out are available through
+    /// [`TlsStream::scts`].
+    pub fn with_ct_policy(mut self, policy: CtPolicy) -> Self {
+        self.ct = Some(policy);
+        self
+    }
+
+    /// Check the OCSP response the server staples according to `policy`. The response is
+    /// available through [`TlsStream::ocsp_response`].
+    ///
+    /// rustls only hands the stapled response to the certificate verifier, so this is layered
+    /// over the verifier set on the connector, e.g. with
+    /// [`with_webpki_roots`](Self::with_webpki_roots); connecting fails without one.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn with_ocsp_stapling(mut self, policy: StaplePolicy) -> Self {
+        self.verification.staple_policy = Some(policy);
+        self
+    }
+
+    /// Accept server certificates which expired, or only become valid, within `tolerance` of
+    /// now, for fleets whose clocks are known to drift. The rest of the chain is verified as
+    /// usual, and only an error about the validity period is retried with the time moved by
+    /// `tolerance`.
+    ///
+    /// Layered over the verifier set on the connector, e.g. with
+    /// [`with_webpki_roots`](Self::with_webpki_roots); connecting fails without one.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn cert_time_tolerance(mut self, tolerance: Duration) -> Self {
+        self.verification.tolerance = Some(tolerance);
+        self
+    }
+
+    /// Accept a server certificate valid for any of `names`, instead of the name passed to
+    /// `connect`, which is still sent as SNI. Useful for a service known under several
+    /// hostnames, or a camouflage SNI which differs from the name verified.
+    ///
+    /// The names are checked with the verifier set on the connector, e.g. with
+    /// [`with_webpki_roots`](Self::with_webpki_roots); connecting fails without one.
+    #[cfg(feature = "dangerous_configuration")]
+    pub fn with_verify_names(mut self, names: Vec<ServerName>) -> Self {
+        self.verification.names = Some(VerifyNames::Any(names));
+        self
+    }
+
+    /// Authenticate to servers asking for a client certificate with `chain`, signing with
+    /// `key`, which can keep the private key in an HSM, TPM or KMS. Signing is synchronous and
+    /// blocks the task driving the handshake.
+    pub fn with_client_signing_key(
+        mut self,
+        chain: Vec<Certificate>,
+        key: Arc<dyn SigningKey>,
+    ) -> Self {
+        let resolver = SingleClientCert(Arc::new(CertifiedKey::new(chain, key)));
+        Arc::make_mut(&mut self.inner).client_auth_cert_resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Authenticate to servers asking for a client certificate with `identity`.
+    pub fn with_client_identity(self, identity: Identity) -> Result<Self, Error> {
+        let key = sign::any_supported_type(&identity.key)
+            .map_err(|_| Error::General("invalid private key".into()))?;
+        Ok(self.with_client_signing_key(identity.chain, key))
+    }
+
+    /// Take "now" from `provider` rather than the system clock when checking the server's
+    /// certificates against CRLs and CT logs, and, with a verifier set on the connector, e.g.
+    /// with `with_webpki_roots`, their validity and OCSP staples.
+    /// A verifier in the config keeps to the system clock.
+    pub fn with_time_provider(mut self, provider: impl TimeProvider) -> Self {
+        self.clock = Clock::new(provider);
+        #[cfg(feature = "dangerous_configuration")]
+        {
+            self.verification.clock = self.clock.clone();
+        }
+        self
+    }
+
+    /// Charge the buffers of new connections to `budget`, failing those it can't take with
+    /// `OutOfMemory`.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Defer the writes of new connections to the time slices of `scheduler`.
+    pub fn with_flush_scheduler(mut self, scheduler: FlushScheduler) -> Self {
+        self.flush_scheduler = Some(scheduler);
+        self
+    }
+
+    /// Report every record new connections read or write to `tap`.
+    pub fn with_record_tap(mut self, tap: Arc<dyn RecordTap>) -> Self {
+        self.record_tap = Some(tap);
+        self
+    }
+
+    /// Report the handshake progress of new connections to `observer`.
+    pub fn with_handshake_observer(mut self, observer: Arc<dyn HandshakeObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Return handshake errors as [`TlsError::Context`], carrying the server name and, for
+    /// [`connect_addr`](Self::connect_addr), the server's address.
+    pub fn with_error_context(mut self, enabled: bool) -> Self {
+        self.error_context = enabled;
+        self
+    }
+
+    /// The connector for connections which neither resume a cached session nor store their
+    /// own, so they can't be linked to other connections by a ticket or session id, e.g.
+    /// `connector.no_resumption().connect(domain, io)`. The connector's other connections
+    /// keep using the cache.
+    pub fn no_resumption(&self) -> Self {
+        Self {
+            resumption: false,
+            ..self.clone()
+        }
+    }
+
+    fn new_stream<IO>(
+        &self,
+        io: IO,
+        session: ClientConnection,
+        memory: Option<MemoryCharge>,
+    ) -> TlsStream<IO> {
+        let mut stream = Stream::new(io, session);
+        stream.memory = memory;
+        #[cfg(feature = "metrics")]
+        {
+            stream.metrics = self.metrics.clone().map(|metrics| StreamMetrics {
+                metrics,
+                active: false,
+            });
+        }
+        stream.observer = self.observer.clone().map(ObserverSlot::new);
+        stream.deferred_flush = self.flush_scheduler.clone().map(DeferredFlush::new);
+        if let Some(tap) = &self.record_tap {
+            stream.add_record_tap(tap.clone());
+        }
+        #[cfg(feature = "pcap")]
+        if let Some(capture) = &self.pcap {
+            stream.add_record_tap(Arc::new(capture.flow(true)));
+        }
+        stream
+    }
+
+    /// The share of the memory budget for a new connection.
+    fn reserve_memory(&self) -> io::Result<Option<MemoryCharge>> {
+        self.budget.as_ref().map(MemoryBudget::reserve).transpose()
+    }
+
+    /// The config for a new connection, and where its stapled OCSP response will be put.
+    fn session_config(&self) -> io::Result<(Arc<ClientConfig>, Option<StapleSlot>)> {
+        #[cfg(feature = "dangerous_configuration")]
+        return self.layered_config(&self.verification);
+        #[cfg(not(feature = "dangerous_configuration"))]
+        {
+            self.allowed
+                .check_offered(|version| self.inner.supports_version(version))?;
+            Ok((self.resumption_config(self.inner.clone()), None))
+        }
+    }
+
+    /// `config`, without the session cache when resumption is off.
+    fn resumption_config(&self, config: Arc<ClientConfig>) -> Arc<ClientConfig> {
+        if self.resumption {
+            return config;
+        }
+        let mut config = ClientConfig::clone(&config);
+        config.session_storage = Arc::new(NoClientSessionStorage {});
+        config.enable_tickets = false;
+        Arc::new(config)
+    }
+
+    /// The config for a new connection with the checks of `layers` over the verifier.
+    #[cfg(feature = "dangerous_configuration")]
+    fn layered_config(
+        &self,
+        layers: &Layers,
+    ) -> io::Result<(Arc<ClientConfig>, Option<StapleSlot>)> {
+        self.allowed
+            .check_offered(|version| self.inner.supports_version(version))?;
+        if let Some((verifier, slot)) = layers.session_verifier()? {
+            let mut config = ClientConfig::clone(&self.inner);
+            config.dangerous().set_certificate_verifier(verifier);
+            return Ok((self.resumption_config(Arc::new(config)), slot));
+        }
+        Ok((self.resumption_config(self.inner.clone()), None))
+    }
+
+    /// Perform the handshake of a new connection and run the checks on the server.
+    async fn handshake<IO>(
+        &self,
+        stream: TlsStream<IO>,
+        domain: &ServerName,
+        staple: Option<StapleSlot>,
+    ) -> Result<TlsStream<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.run_handshake(stream, domain, staple)
+            .await
+            .map_err(|e| match self.error_context {
+                true => e.with_context(ErrorContext {
+                    server_name: Some(server_name_str(domain)),
+                    peer_addr: None,
+                }),
+                false => e,
+            })
+    }
+
+    async fn run_handshake<IO>(
+        &self,
+        mut stream: TlsStream<IO>,
+        domain: &ServerName,
+        staple: Option<StapleSlot>,
+    ) -> Result<TlsStream<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("tls_connect", server_name = ?domain);
+        #[cfg(feature = "tracing")]
+        tracing::Instrument::instrument(Pin::new(&mut stream).handshake(), span)
+            .await
+            .map_err(TlsError::from_handshake)?;
+        #[cfg(not(feature = "tracing"))]
+        Pin::new(&mut stream)
+            .handshake()
+            .await
+            .map_err(TlsError::from_handshake)?;
+        if stream.session.is_early_data_accepted() {
+            stream.early_data_accepted();
+        }
+        self.verify_peer(&mut stream, domain)?;
+        stream.ocsp_response = staple.and_then(|staple| staple.lock().unwrap().take());
+        Ok(stream)
+    }
+
+    /// Checks on the server which run once the handshake is done.
+    fn verify_peer<IO>(
+        &self,
+        stream: &mut TlsStream<IO>,
+        domain: &ServerName,
+    ) -> Result<(), TlsError> {
+        self.allowed.check(stream)?;
+        let chain = stream.session.peer_certificates().unwrap_or_default();
+        if let Some(pins) = &self.pins {
+            pins.check(domain, chain)?;
+        }
+        if let Some(crls) = &self.crls {
+            crls.check(chain, self.clock.now())?;
+        }
+        if let Some(ct) = &self.ct {
+            stream.scts = ct.check(chain, self.clock.now())?;
+        }
+        Ok(())
+    }
+
+    /// Set `TCP_NODELAY` on sockets dialed by [`connect_addr`](Self::connect_addr).
+    pub fn with_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
     pub async fn connect<IO>(
         &self,
         domain: rustls_fork_shadow_tls::ServerName,
@@ -45,12 +597,119 @@ impl TlsConnector {
     where
         IO: AsyncRead + AsyncWrite + Unpin,
     {
-        let session = ClientConnection::new(self.inner.clone(), domain)?;
-        let mut stream = Stream::new(stream, session);
-        stream.handshake().await?;
+        let memory = self.reserve_memory()?;
+        let (config, staple) = self.session_config()?;
+        let session = ClientConnection::new(config, domain.clone())?;
+        let stream = self.new_stream(stream, session, memory);
+        self.handshake(stream, &domain, staple).await
+    }
+
+    /// Connect like `connect` and send `data` as the first application data without waiting
+    /// for an extra round trip: as 0-RTT early data when the session cache holds a ticket
+    /// which allows it, otherwise queued to go out in the same write as the final handshake
+    /// flight. The stream is returned once `data` is written and flushed.
+    ///
+    /// Early data the server rejects is sent again after the handshake. With `with_pins`,
+    /// `with_crls` or `with_ct_policy` set, `data` is only written once their checks pass. A
+    /// TLS 1.2 server gets it after its Finished, which the protocol requires.
+    pub async fn connect_with_first_write<IO>(
+        &self,
+        domain: ServerName,
+        stream: IO,
+        data: &[u8],
+    ) -> Result<TlsStream<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let memory = self.reserve_memory()?;
+        let (config, staple) = self.session_config()?;
+        let mut session = ClientConnection::new(config, domain.clone())?;
+        let checked = self.pins.is_some() || self.crls.is_some() || self.ct.is_some();
+        let early = match session.early_data() {
+            Some(mut early_data) if !checked => early_data.write(data)?,
+            _ => 0,
+        };
+        let queued = match early == 0 && !checked {
+            true => session.writer().write(data)?,
+            false => 0,
+        };
+        let mut stream = self.new_stream(stream, session, memory);
+        stream.account_held(queued);
+        let mut stream = self.handshake(stream, &domain, staple).await?;
+        let mut sent = queued;
+        if early > 0 && stream.session.is_early_data_accepted() {
+            stream.account_written(early);
+            sent = early;
+        }
+        stream.write_all(&data[sent..]).await?;
+        stream.flush().await?;
         Ok(stream)
     }
 
+    /// Perform the handshake over `stream` and give the IO back, with the parameters of the
+    /// handshake, instead of a TLS stream. This is how a shadow-tls client completes a real
+    /// handshake with the camouflage server before switching to its own data protocol. The
+    /// server is verified like with `connect`.
+    ///
+    /// The client Finished is written and flushed before returning. Records the server sent
+    /// after its Finished and which were read along with it are dropped.
+    pub async fn handshake_only<IO>(
+        &self,
+        domain: ServerName,
+        stream: IO,
+    ) -> Result<(IO, HandshakeArtifacts), TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = self.connect(domain, stream).await?;
+        let artifacts = HandshakeArtifacts::capture(&stream)?;
+        let (io, _) = stream.into_inner();
+        Ok((io, artifacts))
+    }
+
+    /// Perform only the handshake over `stream`, capture what the server presented and
+    /// negotiated, and close the connection again. A building block for monitoring; the
+    /// server is verified like with `connect`.
+    pub async fn probe<IO>(&self, domain: ServerName, stream: IO) -> Result<ProbeReport, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = self.connect(domain, stream).await?;
+        Ok(ProbeReport::capture(stream).await)
+    }
+
+    /// Send `sni_name` as SNI but verify the server certificate for `verify_name`, or for no
+    /// particular name when it is `None`; the chain is checked either way.
+    ///
+    /// Like [`with_verify_names`](Self::with_verify_names), which this overrides, it relies on
+    /// the verifier set on the connector, e.g. with
+    /// [`with_webpki_roots`](Self::with_webpki_roots).
+    #[cfg(feature = "dangerous_configuration")]
+    pub async fn connect_with_sni<IO>(
+        &self,
+        verify_name: Option<ServerName>,
+        sni_name: ServerName,
+        stream: IO,
+    ) -> Result<TlsStream<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let names = match &verify_name {
+            Some(name) => VerifyNames::Any(vec![name.clone()]),
+            None => VerifyNames::Unchecked,
+        };
+        let layers = Layers {
+            names: Some(names),
+            ..self.verification.clone()
+        };
+        let memory = self.reserve_memory()?;
+        let (config, staple) = self.layered_config(&layers)?;
+        let session = ClientConnection::new(config, sni_name.clone())?;
+        let stream = self.new_stream(stream, session, memory);
+        let domain = verify_name.unwrap_or(sni_name);
+        self.handshake(stream, &domain, staple).await
+    }
+
     pub async fn connect_with_session_id_generator<IO>(
         &self,
         domain: rustls_fork_shadow_tls::ServerName,
@@ -60,10 +719,210 @@ impl TlsConnector {
     where
         IO: AsyncRead + AsyncWrite + Unpin,
     {
+        let memory = self.reserve_memory()?;
+        let (config, staple) = self.session_config()?;
         let session =
-            ClientConnection::new_with_session_id_generator(self.inner.clone(), domain, generator)?;
-        let mut stream = Stream::new(stream, session);
-        stream.handshake().await?;
-        Ok(stream)
+            ClientConnection::new_with_session_id_generator(config, domain.clone(), generator)?;
+        let stream = self.new_stream(stream, session, memory);
+        self.handshake(stream, &domain, staple).await
+    }
+
+    /// Resolve `addr` (`host:port`), dial it with Happy Eyeballs and perform the handshake.
+    ///
+    /// The host part is used as the server name, so it must be a valid DNS name or an IP
+    /// address.
+    pub async fn connect_addr(&self, addr: &str) -> Result<TlsStream<TcpStream>, TlsError> {
+        let (host, port) = dial::split_host_port(addr)?;
+        self.connect_host(host, port).await
+    }
+
+    pub(crate) async fn connect_host(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<TlsStream<TcpStream>, TlsError> {
+        let domain = server_name(host)?;
+        let stream = dial::connect_tcp(host, port).await?;
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        let peer_addr = stream.peer_addr().ok();
+        self.connect(domain, stream).await.map_err(|e| match e {
+            e @ TlsError::Context { .. } => e.with_context(ErrorContext {
+                server_name: None,
+                peer_addr,
+            }),
+            e => e,
+        })
+    }
+
+    /// Perform the handshake over any byte stream, e.g. a `DuplexStream`, a Unix socket or a
+    /// WebSocket, verifying the server as `name`: a DNS name, or an IP address, bracketed or
+    /// not, for which no SNI is sent. Endpoints named by neither, such as a socket path, are
+    /// verified against a name their certificate carries, e.g. `localhost`.
+    pub async fn connect_io<IO>(&self, name: &str, stream: IO) -> Result<TlsStream<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connect(server_name(name)?, stream).await
+    }
+
+    /// Connect to the Unix socket at `path` and perform the handshake, verifying the server
+    /// as `name`, see [`connect_io`](Self::connect_io).
+    #[cfg(unix)]
+    pub async fn connect_unix(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        name: &str,
+    ) -> Result<TlsStream<UnixStream>, TlsError> {
+        let stream = UnixStream::connect(path).await?;
+        self.connect_io(name, stream).await
+    }
+
+    /// Perform the handshake over `outer`, an established TLS stream, e.g. to a TLS proxy or
+    /// through a shadow-tls relay. The records of the nested connection are sized to fit whole
+    /// in full-size records of `outer`; at full size each would take a full outer record and a
+    /// runt of a few dozen bytes, doubling the records and the writes to the IO. A smaller
+    /// [`with_max_fragment_size`](Self::with_max_fragment_size) of the connector is kept.
+    pub async fn connect_layered<IO, C>(
+        &self,
+        domain: ServerName,
+        outer: Stream<IO, C>,
+    ) -> Result<TlsStream<Stream<IO, C>>, TlsError>
+    where
+        Stream<IO, C>: AsyncRead + AsyncWrite + Unpin,
+    {
+        let connector = match self.inner.max_fragment_size {
+            Some(size) if size <= LAYERED_FRAGMENT_SIZE => self.clone(),
+            _ => self.clone().with_max_fragment_size(LAYERED_FRAGMENT_SIZE)?,
+        };
+        connector.connect(domain, outer).await
+    }
+
+    /// Perform a handshake over each `(io, domain)` of `conns`, with at most `concurrency` of
+    /// them in flight, e.g. to scan hosts, health check a fleet or prewarm a pool.
+    ///
+    /// The IO is taken from `conns` only when its handshake starts. Results come in the order
+    /// the handshakes finish, each with its server name.
+    pub fn handshake_all<IO, I>(&self, conns: I, concurrency: usize) -> Handshakes<IO, I::IntoIter>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        I: IntoIterator<Item = (IO, ServerName)>,
+    {
+        Handshakes::new(self.clone(), conns.into_iter(), concurrency)
+    }
+
+    /// Connect with each of `domains` as SNI in order, until one handshake succeeds. When
+    /// they all fail, one more attempt leaves the SNI extension out, for networks which block
+    /// by SNI value; the server is still verified against the first of `domains`. Configs
+    /// with SNI disabled make no such extra attempt.
+    ///
+    /// Before every attempt a fresh transport is created by `dialer`. Only failures which
+    /// usually mean the SNI value has been blocked (connection reset or aborted, unexpected eof,
+    /// timeout, or an alert from the peer) move on to the next attempt; any other error is
+    /// returned immediately. When all attempts fail, the last error is returned.
+    pub async fn connect_with_fallbacks<IO, F, Fut>(
+        &self,
+        domains: &[ServerName],
+        mut dialer: F,
+    ) -> Result<TlsStream<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<IO>>,
+    {
+        let Some(first) = domains.first() else {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "no server name to connect").into(),
+            );
+        };
+        let without_sni = self.inner.enable_sni.then(|| {
+            let mut connector = self.clone();
+            Arc::make_mut(&mut connector.inner).enable_sni = false;
+            connector
+        });
+        let attempts = domains
+            .iter()
+            .map(|domain| (self, domain))
+            .chain(without_sni.iter().map(|connector| (connector, first)));
+        let mut last_err = None;
+        for (connector, domain) in attempts {
+            let stream = dialer().await?;
+            match connector.connect(domain.clone(), stream).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if is_fallback_error(&e) => last_err = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_err.expect("at least one attempt was made"))
+    }
+}
+
+impl<IO> TlsStream<IO> {
+    /// The OCSP response the server stapled, when the connector checks staples with
+    /// `with_ocsp_stapling`. Resumed sessions carry none.
+    pub fn ocsp_response(&self) -> Option<&[u8]> {
+        self.ocsp_response.as_deref()
+    }
+
+    /// The embedded SCTs which satisfied the connector's `with_ct_policy`.
+    pub fn scts(&self) -> &[Sct] {
+        &self.scts
+    }
+}
+
+/// Presents the same certificate to every server which asks for one.
+struct SingleClientCert(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for SingleClientCert {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
     }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// `name` as it was given: a DNS name or an IP address.
+fn server_name_str(name: &ServerName) -> String {
+    match name {
+        ServerName::DnsName(name) => name.as_ref().to_owned(),
+        ServerName::IpAddress(addr) => addr.to_string(),
+        _ => format!("{name:?}"),
+    }
+}
+
+fn is_fallback_error(e: &TlsError) -> bool {
+    match e {
+        TlsError::Io(e) => match e.kind() {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut => true,
+            io::ErrorKind::InvalidData => matches!(
+                e.get_ref()
+                    .and_then(|e| e.downcast_ref::<rustls_fork_shadow_tls::Error>()),
+                Some(rustls_fork_shadow_tls::Error::AlertReceived(_))
+            ),
+            _ => false,
+        },
+        TlsError::HandshakeAlert(_) => true,
+        TlsError::Rustls(_) => false,
+        TlsError::Context { source, .. } => is_fallback_error(source),
+    }
+}
+
+/// The server name for `name`, a DNS name or an IP address, bracketed or not.
+fn server_name(name: &str) -> io::Result<ServerName> {
+    let name = name
+        .strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .unwrap_or(name);
+    ServerName::try_from(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
 }
@@ -0,0 +1,62 @@
+//! The time certificates and ticket keys are checked against.
+use std::{
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where "now" comes from, for devices whose real-time clock can't be trusted and for tests
+/// which pin the time. Implemented for closures returning a `SystemTime`.
+///
+/// Set with `TlsConnector::with_time_provider` and `TlsAcceptorBuilder::with_time_provider`.
+/// The rustls fork reads the system clock itself to age session tickets, so resumption keeps
+/// following the system clock.
+pub trait TimeProvider: Send + Sync + 'static {
+    fn now(&self) -> SystemTime;
+}
+
+impl<F> TimeProvider for F
+where
+    F: Fn() -> SystemTime + Send + Sync + 'static,
+{
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
+
+/// A [`TimeProvider`], or the system clock when none is set.
+#[derive(Clone, Default)]
+pub(crate) struct Clock(Option<Arc<dyn TimeProvider>>);
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Clock")
+            .field(&if self.is_system() { "system" } else { "custom" })
+            .finish()
+    }
+}
+
+impl Clock {
+    pub(crate) fn new(provider: impl TimeProvider) -> Self {
+        Self(Some(Arc::new(provider)))
+    }
+
+    pub(crate) fn is_system(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub(crate) fn now(&self) -> SystemTime {
+        match &self.0 {
+            Some(provider) => provider.now(),
+            None => SystemTime::now(),
+        }
+    }
+
+    /// Seconds since the epoch.
+    pub(crate) fn unix_secs(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
@@ -0,0 +1,159 @@
+//! `tokio_util::codec` helpers for TLS streams.
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream as FuturesStream;
+use futures_sink::Sink;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Framed, FramedRead, FramedWrite};
+use rustls_fork_shadow_tls::{ClientConnection, ProtocolVersion, ServerConnection};
+
+use crate::stream::Stream;
+
+/// Max plaintext size of a TLS record. Buffers sized to it hold a whole decrypted record, and
+/// flushing at it lets every write fill a record.
+const RECORD_SIZE: usize = 16 * 1024;
+
+/// TLS metadata captured when a stream is framed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The negotiated ALPN protocol.
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The SNI hostname sent by the client, available on the server side.
+    pub server_name: Option<String>,
+    pub protocol_version: Option<ProtocolVersion>,
+}
+
+impl From<&ClientConnection> for TlsInfo {
+    fn from(conn: &ClientConnection) -> Self {
+        Self {
+            alpn_protocol: conn.alpn_protocol().map(<[u8]>::to_vec),
+            server_name: None,
+            protocol_version: conn.protocol_version(),
+        }
+    }
+}
+
+impl From<&ServerConnection> for TlsInfo {
+    fn from(conn: &ServerConnection) -> Self {
+        Self {
+            alpn_protocol: conn.alpn_protocol().map(<[u8]>::to_vec),
+            server_name: conn.sni_hostname().map(str::to_owned),
+            protocol_version: conn.protocol_version(),
+        }
+    }
+}
+
+/// A `Framed` over a TLS stream, with buffers sized to TLS records and the TLS metadata
+/// kept alongside the codec.
+pub struct TlsFramed<IO, C, U> {
+    framed: Framed<Stream<IO, C>, U>,
+    info: TlsInfo,
+}
+
+impl<IO, C, U> TlsFramed<IO, C, U>
+where
+    Stream<IO, C>: AsyncRead + AsyncWrite,
+    for<'a> TlsInfo: From<&'a C>,
+{
+    pub fn new(stream: Stream<IO, C>, codec: U) -> Self {
+        let info = TlsInfo::from(stream.session());
+        let mut framed = Framed::with_capacity(stream, codec, RECORD_SIZE);
+        framed.set_backpressure_boundary(RECORD_SIZE);
+        Self { framed, info }
+    }
+}
+
+impl<IO, C, U> TlsFramed<IO, C, U> {
+    pub fn info(&self) -> &TlsInfo {
+        &self.info
+    }
+
+    pub fn codec(&self) -> &U {
+        self.framed.codec()
+    }
+
+    pub fn codec_mut(&mut self) -> &mut U {
+        self.framed.codec_mut()
+    }
+
+    pub fn get_ref(&self) -> &Framed<Stream<IO, C>, U> {
+        &self.framed
+    }
+
+    pub fn get_mut(&mut self) -> &mut Framed<Stream<IO, C>, U> {
+        &mut self.framed
+    }
+
+    pub fn into_inner(self) -> Framed<Stream<IO, C>, U> {
+        self.framed
+    }
+}
+
+impl<IO, C, U> FuturesStream for TlsFramed<IO, C, U>
+where
+    Framed<Stream<IO, C>, U>: FuturesStream + Unpin,
+{
+    type Item = <Framed<Stream<IO, C>, U> as FuturesStream>::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.framed).poll_next(cx)
+    }
+}
+
+impl<IO, C, U, I> Sink<I> for TlsFramed<IO, C, U>
+where
+    Framed<Stream<IO, C>, U>: Sink<I> + Unpin,
+{
+    type Error = <Framed<Stream<IO, C>, U> as Sink<I>>::Error;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.framed).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: I) -> Result<(), Self::Error> {
+        Pin::new(&mut self.framed).start_send(item)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.framed).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.framed).poll_close(cx)
+    }
+}
+
+impl<IO, C> Stream<IO, C>
+where
+    Self: AsyncRead + AsyncWrite,
+    for<'a> TlsInfo: From<&'a C>,
+{
+    /// Frame the stream with `codec`, see [`TlsFramed`].
+    pub fn framed<U>(self, codec: U) -> TlsFramed<IO, C, U> {
+        TlsFramed::new(self, codec)
+    }
+}
+
+/// A `FramedRead` with its buffer sized to TLS records, e.g. for a split read half.
+pub fn framed_read<R: AsyncRead, D: Decoder>(reader: R, decoder: D) -> FramedRead<R, D> {
+    FramedRead::with_capacity(reader, decoder, RECORD_SIZE)
+}
+
+/// A `FramedWrite` flushing at TLS record size, e.g. for a split write half.
+pub fn framed_write<W: AsyncWrite, E>(writer: W, encoder: E) -> FramedWrite<W, E> {
+    let mut framed = FramedWrite::new(writer, encoder);
+    framed.set_backpressure_boundary(RECORD_SIZE);
+    framed
+}
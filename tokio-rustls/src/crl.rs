@@ -0,0 +1,91 @@
+//! Certificate revocation lists.
+use std::{collections::HashSet, io, time::SystemTime};
+
+use rustls_fork_shadow_tls::{Certificate, Error};
+
+use crate::x509;
+
+/// What to do when no current CRL covers a certificate of the server's chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RevocationPolicy {
+    /// Fail the connection.
+    #[default]
+    HardFail,
+    /// Allow the connection. Certificates listed as revoked still fail it.
+    SoftFail,
+}
+
+#[derive(Clone, Debug)]
+struct Crl {
+    issuer: Vec<u8>,
+    next_update: Option<SystemTime>,
+    revoked: HashSet<Vec<u8>>,
+}
+
+/// CRLs the server's chain is checked against, set with `with_crls` on a connector.
+///
+/// Every certificate the server presents, except self-signed ones, must be covered by a CRL
+/// from its issuer which hasn't passed its next update; what happens otherwise is decided by
+/// the [`RevocationPolicy`]. The CRLs are trusted as given and their signatures are not
+/// checked, so load them from a source as trustworthy as the root certificates.
+#[derive(Clone, Debug, Default)]
+pub struct CrlSet {
+    crls: Vec<Crl>,
+    policy: RevocationPolicy,
+}
+
+impl CrlSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a DER encoded CRL.
+    pub fn with_der(mut self, der: &[u8]) -> io::Result<Self> {
+        let crl = x509::crl(der)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed CRL"))?;
+        self.crls.push(Crl {
+            issuer: crl.issuer.to_vec(),
+            next_update: crl.next_update,
+            revoked: crl.revoked.into_iter().map(<[u8]>::to_vec).collect(),
+        });
+        Ok(self)
+    }
+
+    /// Add every `X509 CRL` block of PEM input.
+    pub fn with_pem(mut self, mut pem: &[u8]) -> io::Result<Self> {
+        for der in rustls_pemfile::crls(&mut pem)? {
+            self = self.with_der(&der)?;
+        }
+        Ok(self)
+    }
+
+    /// Decide what happens when revocation data for a certificate is missing or stale.
+    /// Defaults to [`RevocationPolicy::HardFail`].
+    pub fn with_policy(mut self, policy: RevocationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub(crate) fn check(&self, chain: &[Certificate], now: SystemTime) -> Result<(), Error> {
+        for cert in chain {
+            let tbs = x509::tbs(&cert.0)
+                .ok_or_else(|| Error::InvalidCertificateData("malformed certificate".into()))?;
+            if tbs.issuer == tbs.subject {
+                continue;
+            }
+            let mut covered = false;
+            for crl in self.crls.iter().filter(|crl| crl.issuer == tbs.issuer) {
+                if crl.revoked.contains(tbs.serial) {
+                    return Err(Error::InvalidCertificateData("certificate revoked".into()));
+                }
+                covered |= crl.next_update.is_none_or(|next_update| now <= next_update);
+            }
+            if !covered && self.policy == RevocationPolicy::HardFail {
+                return Err(Error::InvalidCertificateData(
+                    "no current CRL covers the certificate".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Certificate Transparency.
+use std::{
+    io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ring::{digest, signature};
+use rustls_fork_shadow_tls::{Certificate, Error};
+
+use crate::x509;
+
+const OID_SCT_LIST: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+
+/// A Certificate Transparency log, known by its public key.
+#[derive(Clone, Debug)]
+pub struct CtLog {
+    id: [u8; 32],
+    key: Vec<u8>,
+}
+
+impl CtLog {
+    /// A log with the DER SubjectPublicKeyInfo `key`, as published in log lists.
+    pub fn new(key: &[u8]) -> io::Result<Self> {
+        if x509::public_key(key).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "malformed log key",
+            ));
+        }
+        let mut id = [0; 32];
+        id.copy_from_slice(digest::digest(&digest::SHA256, key).as_ref());
+        Ok(Self {
+            id,
+            key: key.to_vec(),
+        })
+    }
+
+    /// The log ID, the SHA-256 hash of its key.
+    pub fn id(&self) -> [u8; 32] {
+        self.id
+    }
+}
+
+/// A signed certificate timestamp which checked out against a known log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sct {
+    pub log_id: [u8; 32],
+    /// When the log promised to include the certificate by.
+    pub timestamp: SystemTime,
+}
+
+/// Certificate Transparency requirements on the server's certificate, set with
+/// `with_ct_policy` on a connector.
+///
+/// The certificate must embed SCTs from at least `min_scts` distinct known logs, each with a
+/// valid signature and a timestamp in the past. SCTs from unknown logs are ignored. No log
+/// list is bundled since logs come and go; load a current one, such as the lists browser
+/// vendors publish.
+#[derive(Clone, Debug)]
+pub struct CtPolicy {
+    logs: Vec<CtLog>,
+    min_scts: usize,
+}
+
+impl Default for CtPolicy {
+    fn default() -> Self {
+        Self {
+            logs: Vec::new(),
+            min_scts: 2,
+        }
+    }
+}
+
+impl CtPolicy {
+    /// A policy requiring two SCTs, from logs yet to be added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_log(mut self, log: CtLog) -> Self {
+        self.logs.push(log);
+        self
+    }
+
+    /// Require SCTs from at least `min_scts` distinct logs.
+    pub fn with_min_scts(mut self, min_scts: usize) -> Self {
+        self.min_scts = min_scts;
+        self
+    }
+
+    pub(crate) fn check(&self, chain: &[Certificate], now: SystemTime) -> Result<Vec<Sct>, Error> {
+        let fail = |reason: &str| {
+            Error::InvalidCertificateData(format!("certificate transparency: {reason}"))
+        };
+        let mut scts: Vec<Sct> = Vec::new();
+        if let ([cert, issuer, ..], Some(list)) = (chain, chain.first().and_then(embedded_scts)) {
+            let issuer_key = x509::tbs(&issuer.0)
+                .map(|tbs| digest::digest(&digest::SHA256, tbs.spki))
+                .ok_or_else(|| fail("malformed issuer certificate"))?;
+            let tbs = x509::tbs_without_extension(&cert.0, OID_SCT_LIST)
+                .ok_or_else(|| fail("malformed certificate"))?;
+            for sct in list {
+                let Some(sct) = self.verify(sct, issuer_key.as_ref(), &tbs) else {
+                    continue;
+                };
+                if sct.timestamp <= now && scts.iter().all(|seen| seen.log_id != sct.log_id) {
+                    scts.push(sct);
+                }
+            }
+        }
+        if scts.len() < self.min_scts {
+            return Err(fail(&format!(
+                "{} valid SCTs, {} required",
+                scts.len(),
+                self.min_scts
+            )));
+        }
+        Ok(scts)
+    }
+
+    /// Check one SCT over the precertificate entry of `tbs`.
+    fn verify(&self, sct: &[u8], issuer_key_hash: &[u8], tbs: &[u8]) -> Option<Sct> {
+        let ([version], rest) = sct.split_at_checked(1)? else {
+            return None;
+        };
+        let (log_id, rest) = rest.split_at_checked(32)?;
+        let log = self.logs.iter().find(|log| log.id == log_id)?;
+        let (timestamp, rest) = rest.split_at_checked(8)?;
+        let (extensions, rest) = vector(rest)?;
+        let (&[hash, sig], rest) = rest.split_at_checked(2)? else {
+            return None;
+        };
+        let (signature, []) = vector(rest)? else {
+            return None;
+        };
+        let algorithm: &'static dyn signature::VerificationAlgorithm = match (version, hash, sig) {
+            (0, 4, 3) => &signature::ECDSA_P256_SHA256_ASN1,
+            (0, 4, 1) => &signature::RSA_PKCS1_2048_8192_SHA256,
+            _ => return None,
+        };
+
+        let tbs_len = (tbs.len() as u32).to_be_bytes();
+        let extensions_len = (extensions.len() as u16).to_be_bytes();
+        // version, certificate_timestamp, timestamp, precert_entry, the entry, extensions
+        let message = [
+            &[0, 0],
+            timestamp,
+            &[0, 1],
+            issuer_key_hash,
+            &tbs_len[1..],
+            tbs,
+            &extensions_len,
+            extensions,
+        ]
+        .concat();
+        signature::UnparsedPublicKey::new(algorithm, x509::public_key(&log.key)?)
+            .verify(&message, signature)
+            .ok()?;
+
+        let millis = u64::from_be_bytes(timestamp.try_into().ok()?);
+        Some(Sct {
+            log_id: log.id,
+            timestamp: UNIX_EPOCH + Duration::from_millis(millis),
+        })
+    }
+}
+
+/// The SCTs embedded in `cert`.
+fn embedded_scts(cert: &Certificate) -> Option<Vec<&[u8]>> {
+    let list = x509::tbs(&cert.0)?.extension(OID_SCT_LIST)?;
+    // An OCTET STRING holding a TLS encoded list of SCTs.
+    let (x509::TAG_OCTET_STRING, _, list, _) = x509::read_tlv(list)? else {
+        return None;
+    };
+    let (mut list, []) = vector(list)? else {
+        return None;
+    };
+    let mut scts = Vec::new();
+    while !list.is_empty() {
+        let (sct, rest) = vector(list)?;
+        scts.push(sct);
+        list = rest;
+    }
+    Some(scts)
+}
+
+/// Splits a TLS vector with a two byte length off `input`.
+fn vector(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = input.split_at_checked(2)?;
+    rest.split_at_checked(u16::from_be_bytes([len[0], len[1]]) as usize)
+}
@@ -0,0 +1,75 @@
+//! Throwaway certificates for local development.
+use std::{io, sync::Arc};
+
+use rustls_fork_shadow_tls::{
+    sign::{self, CertifiedKey},
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig,
+};
+
+/// A freshly generated CA and a certificate it issued, see [`generate_self_signed`].
+#[derive(Clone)]
+pub struct SelfSigned {
+    /// The CA certificate clients need to trust.
+    pub ca: Certificate,
+    /// The end-entity certificate followed by the CA certificate.
+    pub chain: Vec<Certificate>,
+    /// The private key of the end-entity certificate, as PKCS#8.
+    pub key: PrivateKey,
+    /// A server config presenting `chain`.
+    pub server_config: ServerConfig,
+    /// `chain` with its signing key, e.g. for a custom `ResolvesServerCert`.
+    pub certified_key: Arc<CertifiedKey>,
+}
+
+impl SelfSigned {
+    /// A client config trusting only `ca`.
+    pub fn client_config(&self) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&self.ca)
+            .expect("generated CA certificate is well-formed");
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth()
+    }
+}
+
+/// Generate a CA and a certificate for `names`, which are DNS names or IP addresses. Each call
+/// generates new keys, so nothing is trusted beyond the process which trusts [`SelfSigned::ca`].
+///
+/// Never use these outside of development and tests.
+pub fn generate_self_signed(names: &[&str]) -> io::Result<SelfSigned> {
+    let invalid = |e: rcgen::Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let ca_key = rcgen::KeyPair::generate().map_err(invalid)?;
+    let mut ca_params = rcgen::CertificateParams::default();
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "tokio-rustls development CA");
+    let ca = ca_params.self_signed(&ca_key).map_err(invalid)?;
+
+    let key = rcgen::KeyPair::generate().map_err(invalid)?;
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    let cert = rcgen::CertificateParams::new(names)
+        .and_then(|params| params.signed_by(&key, &ca, &ca_key))
+        .map_err(invalid)?;
+
+    let ca = Certificate(ca.der().to_vec());
+    let chain = vec![Certificate(cert.der().to_vec()), ca.clone()];
+    let key = PrivateKey(key.serialize_der());
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid private key"))?;
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(chain.clone(), key.clone())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(SelfSigned {
+        certified_key: Arc::new(CertifiedKey::new(chain.clone(), signing_key)),
+        ca,
+        chain,
+        key,
+        server_config,
+    })
+}
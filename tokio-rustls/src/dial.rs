@@ -0,0 +1,81 @@
+//! TCP dialing with Happy Eyeballs (RFC 8305).
+use std::{io, net::SocketAddr, time::Duration};
+
+use tokio::{
+    net::{self, TcpStream},
+    task::JoinSet,
+};
+
+/// Delay before racing the next address while an attempt is still in flight.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Split `addr` into host and port, accepting `host:port` and `[v6]:port`.
+pub(crate) fn split_host_port(addr: &str) -> io::Result<(&str, u16)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid socket address");
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    let port = port.parse().map_err(|_| invalid())?;
+    Ok((host, port))
+}
+
+/// Resolve `host` and connect to it, racing the resolved addresses.
+pub(crate) async fn connect_tcp(host: &str, port: u16) -> io::Result<TcpStream> {
+    let addrs = net::lookup_host((host, port)).await?.collect();
+    happy_eyeballs(addrs).await
+}
+
+/// Connect to the first reachable address.
+///
+/// Addresses are tried with families interleaved, starting with the family of the first
+/// resolved address. A new attempt is started every `CONNECTION_ATTEMPT_DELAY` or as soon as
+/// the previous one fails, and the first established connection wins.
+pub(crate) async fn happy_eyeballs(addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut pending = interleave(addrs).into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+    loop {
+        if let Some(addr) = pending.next() {
+            attempts.spawn(TcpStream::connect(addr));
+        }
+        if attempts.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            res = attempts.join_next() => match res {
+                Some(Ok(Ok(stream))) => return Ok(stream),
+                Some(Ok(Err(e))) => last_err = Some(e),
+                Some(Err(e)) => last_err = Some(io::Error::other(e)),
+                None => (),
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if pending.len() > 0 => (),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address resolved")
+    }))
+}
+
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let prefer_v6 = addrs.first().is_none_or(SocketAddr::is_ipv6);
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (mut first, mut second) = match prefer_v6 {
+        true => (v6.into_iter(), v4.into_iter()),
+        false => (v4.into_iter(), v6.into_iter()),
+    };
+
+    let mut out = Vec::with_capacity(first.len() + second.len());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => {
+                out.extend(a);
+                out.extend(b);
+            }
+        }
+    }
+    out
+}
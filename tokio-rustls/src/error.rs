@@ -1,6 +1,9 @@
-use std::io;
+use std::{fmt, io, net::SocketAddr};
 
 use thiserror::Error;
+use rustls_fork_shadow_tls::{AlertDescription, Error as RustlsError};
+
+use crate::negotiated::NotAllowed;
 
 #[derive(Error, Debug)]
 pub enum TlsError {
@@ -8,6 +11,182 @@ pub enum TlsError {
     Io(#[from] std::io::Error),
     #[error("rustls error")]
     Rustls(#[from] rustls_fork_shadow_tls::Error),
+    /// The peer rejected the handshake with this fatal alert, e.g. `UnknownCA` when it doesn't
+    /// trust the certificate, or `ProtocolVersion` when it supports none of the versions
+    /// offered.
+    #[error("handshake rejected by the peer with alert {0:?}")]
+    HandshakeAlert(AlertDescription),
+    /// A handshake failed with `source`, on the connection `context` describes. Returned by
+    /// connectors and acceptors set up `with_error_context`.
+    #[error("{source} ({context})")]
+    Context {
+        context: ErrorContext,
+        source: Box<TlsError>,
+    },
+}
+
+/// The connection a handshake failed on, see [`TlsError::Context`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The name the client connected to, or the SNI a client asked the server for.
+    pub server_name: Option<String>,
+    /// The peer's address, when the IO has one: the source of an accepted TCP connection or
+    /// the one a PROXY protocol header carried.
+    pub peer_addr: Option<SocketAddr>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.server_name, &self.peer_addr) {
+            (Some(name), Some(addr)) => write!(f, "server name {name}, peer {addr}"),
+            (Some(name), None) => write!(f, "server name {name}"),
+            (None, Some(addr)) => write!(f, "peer {addr}"),
+            (None, None) => f.write_str("no server name or peer address"),
+        }
+    }
+}
+
+/// Why a handshake failed, broadly, for alerting on categories rather than on error
+/// messages. See [`TlsError::failure_reason`].
+///
+/// rustls tells the parameters a peer is incompatible with apart only in the message of its
+/// error, so the handshakes it turns away for them are [`Other`](Self::Other) failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailureReason {
+    /// The handshake settled on a cipher suite or key exchange group the connector or
+    /// acceptor doesn't allow, or the peer found no secure enough parameters in common with
+    /// an `insufficient_security` alert.
+    NoSharedCipher,
+    /// The handshake settled on a protocol version the connector or acceptor doesn't allow,
+    /// or the peer supports none offered, with a `protocol_version` alert.
+    UnsupportedVersion,
+    /// The client's certificate was missing, malformed or not trusted.
+    BadClientCert,
+    /// The client sent a malformed message, or one out of place.
+    DecodeError,
+    /// The handshake took too long.
+    Timeout,
+    /// The connection failed or closed before the handshake was done, or could not be
+    /// accepted.
+    Transport,
+    /// The client gave up on the handshake with this fatal alert, e.g. `UnknownCA` when it
+    /// doesn't trust the server's certificate. Alerts of the categories above are reported
+    /// as those.
+    PeerAlert(AlertDescription),
+    /// Anything else, such as a connection turned away by the acceptor's own policy.
+    Other,
+}
+
+impl HandshakeFailureReason {
+    fn from_rustls(err: &RustlsError) -> Self {
+        match err {
+            RustlsError::AlertReceived(alert) => Self::from_alert(*alert),
+            RustlsError::NoCertificatesPresented
+            | RustlsError::InvalidCertificateEncoding
+            | RustlsError::InvalidCertificateSignatureType
+            | RustlsError::InvalidCertificateSignature
+            | RustlsError::InvalidCertificateData(_)
+            | RustlsError::InvalidSct(_) => Self::BadClientCert,
+            RustlsError::InappropriateMessage { .. }
+            | RustlsError::InappropriateHandshakeMessage { .. }
+            | RustlsError::CorruptMessage
+            | RustlsError::CorruptMessagePayload(_)
+            | RustlsError::PeerMisbehavedError(_) => Self::DecodeError,
+            _ => Self::Other,
+        }
+    }
+
+    fn from_alert(alert: AlertDescription) -> Self {
+        match alert {
+            AlertDescription::ProtocolVersion => Self::UnsupportedVersion,
+            AlertDescription::InsufficientSecurity => Self::NoSharedCipher,
+            alert => Self::PeerAlert(alert),
+        }
+    }
+}
+
+impl TlsError {
+    /// Why the handshake failed with this error, e.g. for an error from
+    /// `TlsListener::accept` or `TlsAcceptor::accept`. Failed TCP accepts are
+    /// [`Transport`](HandshakeFailureReason::Transport) errors, and handshakes failed by a
+    /// `TimedOut` error, as the listener's handshake timeout does, are
+    /// [`Timeout`](HandshakeFailureReason::Timeout)s.
+    pub fn failure_reason(&self) -> HandshakeFailureReason {
+        match self {
+            Self::Io(err) => {
+                if let Some(not_allowed) = err.get_ref().and_then(|e| e.downcast_ref()) {
+                    return match not_allowed {
+                        NotAllowed::Version(_) => HandshakeFailureReason::UnsupportedVersion,
+                        NotAllowed::CipherSuite(_) | NotAllowed::KxGroup(_) => {
+                            HandshakeFailureReason::NoSharedCipher
+                        }
+                    };
+                }
+                let rustls = err.get_ref().and_then(|e| e.downcast_ref::<RustlsError>());
+                match (rustls, err.kind()) {
+                    (Some(err), _) => HandshakeFailureReason::from_rustls(err),
+                    (None, io::ErrorKind::TimedOut) => HandshakeFailureReason::Timeout,
+                    (None, io::ErrorKind::InvalidInput | io::ErrorKind::ConnectionAborted) => {
+                        HandshakeFailureReason::Other
+                    }
+                    (None, _) => HandshakeFailureReason::Transport,
+                }
+            }
+            Self::Rustls(err) => HandshakeFailureReason::from_rustls(err),
+            Self::HandshakeAlert(alert) => HandshakeFailureReason::from_alert(*alert),
+            Self::Context { source, .. } => source.failure_reason(),
+        }
+    }
+
+    /// The error of a failed handshake, telling an alert from the peer apart.
+    pub(crate) fn from_handshake(err: io::Error) -> Self {
+        let alert = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<rustls_fork_shadow_tls::Error>());
+        match alert {
+            Some(rustls_fork_shadow_tls::Error::AlertReceived(alert)) => {
+                Self::HandshakeAlert(*alert)
+            }
+            _ => Self::Io(err),
+        }
+    }
+
+    /// Where the failed handshake was going, when the error carries it.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context, to match on what went wrong.
+    pub fn without_context(&self) -> &TlsError {
+        match self {
+            Self::Context { source, .. } => source,
+            e => e,
+        }
+    }
+
+    /// Attach `context`, filling in what the error's context lacks when it has one.
+    pub(crate) fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            Self::Context {
+                context: mut known,
+                source,
+            } => {
+                known.server_name = known.server_name.or(context.server_name);
+                known.peer_addr = known.peer_addr.or(context.peer_addr);
+                Self::Context {
+                    context: known,
+                    source,
+                }
+            }
+            source => Self::Context {
+                context,
+                source: Box::new(source),
+            },
+        }
+    }
 }
 
 impl From<TlsError> for io::Error {
@@ -15,6 +194,19 @@ impl From<TlsError> for io::Error {
         match e {
             TlsError::Io(e) => e,
             TlsError::Rustls(e) => io::Error::new(io::ErrorKind::Other, e),
+            TlsError::HandshakeAlert(alert) => io::Error::new(
+                io::ErrorKind::InvalidData,
+                rustls_fork_shadow_tls::Error::AlertReceived(alert),
+            ),
+            // Keep the context in the message, under the kind of the error it wraps.
+            e @ TlsError::Context { .. } => {
+                let kind = match e.without_context() {
+                    TlsError::Io(e) => e.kind(),
+                    TlsError::Rustls(_) => io::ErrorKind::Other,
+                    _ => io::ErrorKind::InvalidData,
+                };
+                io::Error::new(kind, e)
+            }
         }
     }
 }
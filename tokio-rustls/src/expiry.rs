@@ -0,0 +1,107 @@
+//! Expiry monitoring of certificate chains.
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use rustls_fork_shadow_tls::{Certificate, ConnectionCommon, SideData};
+
+use crate::{probe::ProbeReport, stream::Stream, x509};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Validity of one certificate in a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertExpiry {
+    /// Position in the chain, the end-entity certificate being 0.
+    pub index: usize,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+    /// Whole days until `not_after`, negative once the certificate has expired.
+    pub days_left: i64,
+}
+
+impl CertExpiry {
+    /// The validity of each certificate in `chain` as of `now`. Certificates which can't be
+    /// parsed are left out.
+    pub fn for_chain(chain: &[Certificate], now: SystemTime) -> Vec<Self> {
+        chain
+            .iter()
+            .enumerate()
+            .filter_map(|(index, cert)| {
+                let (not_before, not_after) = x509::tbs(&cert.0)?.validity()?;
+                let days_left = match not_after.duration_since(now) {
+                    Ok(left) => (left.as_secs() / SECS_PER_DAY) as i64,
+                    Err(past) => -(past.duration().as_secs().div_ceil(SECS_PER_DAY) as i64),
+                };
+                Some(Self {
+                    index,
+                    not_before,
+                    not_after,
+                    days_left,
+                })
+            })
+            .collect()
+    }
+}
+
+type Alert = Arc<dyn Fn(&CertExpiry) + Send + Sync>;
+
+/// Callbacks for certificates getting close to expiry, each registered for a number of days
+/// left.
+#[derive(Clone, Default)]
+pub struct ExpiryAlerts {
+    /// Sorted by days, tightest first.
+    thresholds: Vec<(i64, Alert)>,
+}
+
+impl fmt::Debug for ExpiryAlerts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days: Vec<i64> = self.thresholds.iter().map(|(days, _)| *days).collect();
+        f.debug_struct("ExpiryAlerts").field("days", &days).finish()
+    }
+}
+
+impl ExpiryAlerts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `alert` for certificates with at most `days` left, expired ones included.
+    pub fn at(mut self, days: u32, alert: impl Fn(&CertExpiry) + Send + Sync + 'static) -> Self {
+        let days = i64::from(days);
+        let at = self.thresholds.partition_point(|(d, _)| *d <= days);
+        self.thresholds.insert(at, (days, Arc::new(alert)));
+        self
+    }
+
+    /// Run the alert of the tightest threshold each certificate of `expiry` falls under.
+    pub fn check(&self, expiry: &[CertExpiry]) {
+        for cert in expiry {
+            if let Some((_, alert)) = self.thresholds.iter().find(|(d, _)| cert.days_left <= *d) {
+                alert(cert);
+            }
+        }
+    }
+}
+
+impl ProbeReport {
+    /// The validity of each certificate in the server's chain, as of now.
+    pub fn expiry(&self) -> Vec<CertExpiry> {
+        CertExpiry::for_chain(&self.chain, SystemTime::now())
+    }
+}
+
+impl<IO, C, SD: SideData> Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    /// The validity of each certificate in the peer's chain, as of now. Empty when the peer
+    /// sent no certificate.
+    pub fn peer_chain_expiry(&self) -> Vec<CertExpiry> {
+        let chain = self.session.peer_certificates().unwrap_or_default();
+        CertExpiry::for_chain(chain, SystemTime::now())
+    }
+}
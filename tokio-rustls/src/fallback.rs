@@ -0,0 +1,43 @@
+//! Relaying unauthenticated clients to a real TLS server.
+use std::{fmt, io, sync::Arc};
+
+use tokio::io::AsyncRead;
+
+use crate::record::{self, ClientHello};
+
+type Authenticate = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// The backend unauthenticated clients are relayed to, and how clients are authenticated.
+#[derive(Clone)]
+pub(crate) struct Fallback {
+    pub(crate) backend: String,
+    pub(crate) authenticate: Authenticate,
+}
+
+impl fmt::Debug for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fallback")
+            .field("backend", &self.backend)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Fallback {
+    /// Read the ClientHello from `io`, after the `read` bytes already read, and authenticate
+    /// the client. `read` gets everything read, to replay to the backend when the client
+    /// isn't authenticated.
+    pub(crate) async fn authenticate<IO>(&self, io: &mut IO, read: &mut Vec<u8>) -> io::Result<bool>
+    where
+        IO: AsyncRead + Unpin,
+    {
+        let authenticated = match record::read_client_hello(io, read).await? {
+            ClientHello::Complete(hello) => (self.authenticate)(&hello),
+            ClientHello::Incomplete | ClientHello::Invalid => false,
+        };
+        #[cfg(feature = "tracing")]
+        if !authenticated {
+            tracing::debug!(backend = %self.backend, "unauthenticated client, relaying");
+        }
+        Ok(authenticated)
+    }
+}
@@ -0,0 +1,88 @@
+//! Raw fd and socket passthrough to the underlying IO.
+#[cfg(unix)]
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
+
+use crate::{
+    listener::TlsListener,
+    split::{ReadHalf, WriteHalf},
+    stream::Stream,
+};
+
+#[cfg(any(unix, windows))]
+impl<IO, C> ReadHalf<IO, C> {
+    fn io_ref(&self) -> &IO {
+        // Only shared access to the IO, which both halves can do at any time.
+        unsafe { &(*self.inner.get()).io }
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl<IO, C> WriteHalf<IO, C> {
+    fn io_ref(&self) -> &IO {
+        unsafe { &(*self.inner.get()).io }
+    }
+}
+
+macro_rules! impl_fd {
+    ($($ty:ident => $io:ident),*) => {$(
+        #[cfg(unix)]
+        impl<IO: AsRawFd, C> AsRawFd for $ty<IO, C> {
+            fn as_raw_fd(&self) -> RawFd {
+                self.$io().as_raw_fd()
+            }
+        }
+
+        #[cfg(unix)]
+        impl<IO: AsFd, C> AsFd for $ty<IO, C> {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.$io().as_fd()
+            }
+        }
+
+        #[cfg(windows)]
+        impl<IO: AsRawSocket, C> AsRawSocket for $ty<IO, C> {
+            fn as_raw_socket(&self) -> RawSocket {
+                self.$io().as_raw_socket()
+            }
+        }
+
+        #[cfg(windows)]
+        impl<IO: AsSocket, C> AsSocket for $ty<IO, C> {
+            fn as_socket(&self) -> BorrowedSocket<'_> {
+                self.$io().as_socket()
+            }
+        }
+    )*};
+}
+
+impl_fd!(Stream => get_ref, ReadHalf => io_ref, WriteHalf => io_ref);
+
+#[cfg(unix)]
+impl AsRawFd for TlsListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for TlsListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawSocket for TlsListener {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.listener.as_raw_socket()
+    }
+}
+
+#[cfg(windows)]
+impl AsSocket for TlsListener {
+    fn as_socket(&self) -> BorrowedSocket<'_> {
+        self.listener.as_socket()
+    }
+}
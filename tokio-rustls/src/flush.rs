@@ -0,0 +1,105 @@
+//! Flushes deferred to time slices shared by many streams.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::{Instant, Sleep};
+
+/// Plaintext a stream defers at most before writing through, whatever the slice.
+const MAX_DEFERRED: usize = 16 * 1024;
+
+/// Time slices which the flushes of many streams are lined up on, set with
+/// `TlsConnector::with_flush_scheduler` and `TlsAcceptor::with_flush_scheduler`.
+///
+/// A stream using it only encrypts what is written to it, and sends the records when it is
+/// flushed at the end of the current slice. Small writes then go out as fewer records and
+/// syscalls, and the streams flushing within a slice are woken together by one timer tick,
+/// which suits proxies relaying many low-rate streams. The price is up to a slice of added
+/// latency on every flush. A stream writes through once it holds 16 KiB, so a stream which
+/// is never flushed can't buffer without bound; such a stream keeps the rest of its data
+/// until it is flushed or shut down.
+#[derive(Debug, Clone)]
+pub struct FlushScheduler {
+    inner: Arc<Slices>,
+}
+
+#[derive(Debug)]
+struct Slices {
+    slice: Duration,
+    epoch: Instant,
+}
+
+impl FlushScheduler {
+    /// Flush at the end of slices of `slice`, e.g. a millisecond.
+    pub fn new(slice: Duration) -> Self {
+        Self {
+            inner: Arc::new(Slices {
+                slice: slice.max(Duration::from_micros(1)),
+                epoch: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn slice(&self) -> Duration {
+        self.inner.slice
+    }
+
+    /// The end of the slice `now` falls in.
+    fn slice_end(&self, now: Instant) -> Instant {
+        let Slices { slice, epoch } = *self.inner;
+        let slice = slice.as_nanos();
+        let end = (now.duration_since(epoch).as_nanos() / slice + 1) * slice;
+        epoch + Duration::from_nanos(end as u64)
+    }
+}
+
+/// The state of one stream on a [`FlushScheduler`].
+#[derive(Debug)]
+pub(crate) struct DeferredFlush {
+    scheduler: FlushScheduler,
+    /// Plaintext written since the last flush.
+    deferred: usize,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl DeferredFlush {
+    pub(crate) fn new(scheduler: FlushScheduler) -> Self {
+        Self {
+            scheduler,
+            deferred: 0,
+            sleep: None,
+        }
+    }
+
+    /// Whether the records of `n` more bytes of plaintext can wait for the flush.
+    pub(crate) fn defer(&mut self, n: usize) -> bool {
+        self.deferred += n;
+        self.deferred <= MAX_DEFERRED
+    }
+
+    /// Everything deferred has been written.
+    pub(crate) fn written(&mut self) {
+        self.deferred = 0;
+    }
+
+    /// Wait for the end of the current slice, when anything was deferred.
+    pub(crate) fn poll_slice(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.deferred == 0 {
+            return Poll::Ready(());
+        }
+        let scheduler = &self.scheduler;
+        let sleep = self.sleep.get_or_insert_with(|| {
+            Box::pin(tokio::time::sleep_until(
+                scheduler.slice_end(Instant::now()),
+            ))
+        });
+        ready!(sleep.as_mut().poll(cx));
+        self.sleep = None;
+        self.deferred = 0;
+        Poll::Ready(())
+    }
+}
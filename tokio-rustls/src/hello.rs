@@ -0,0 +1,339 @@
+//! Screening clients by their ClientHello before the handshake.
+use std::{fmt, io, sync::Arc};
+
+use ring::digest;
+use tokio::io::AsyncRead;
+
+use crate::record::{self, ClientHello};
+
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXTENSION_EC_POINT_FORMATS: u16 = 0x000b;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXTENSION_ALPN: u16 = 0x0010;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 0x002b;
+const SERVER_NAME_HOST: u8 = 0;
+
+pub(crate) type HelloPolicy = Arc<dyn Fn(&ClientHelloInfo) -> HelloAction + Send + Sync>;
+
+/// What an acceptor does with a client, decided from its ClientHello by the policy set with
+/// `TlsAcceptor::with_hello_policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HelloAction {
+    /// Go on with the handshake.
+    Accept,
+    /// Go on with the handshake as if the client asked for this server name, with the
+    /// virtual host set for it with `TlsAcceptor::with_sni_host`, or the acceptor's own
+    /// config when there is none.
+    Route(String),
+    /// Send a fatal handshake_failure alert and close, before any certificate is sent.
+    Reject,
+    /// Hold the connection as `TlsAcceptor::with_tarpit` says, then close it, to slow
+    /// scanners down.
+    Tarpit,
+}
+
+/// A ClientHello, parsed for policies to decide on. GREASE values are kept in the lists, as
+/// sent.
+///
+/// The known extensions whose content doesn't parse are listed in `malformed_extensions`, and
+/// their fields left empty, rather than failing the parse, so that a client can't slip past
+/// a policy by mangling them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ClientHelloInfo {
+    /// The version in the ClientHello itself, 0x0303 for TLS 1.2 and 1.3 clients.
+    pub legacy_version: u16,
+    pub random: [u8; 32],
+    pub session_id: Vec<u8>,
+    pub cipher_suites: Vec<u16>,
+    /// Extension types, in the order sent.
+    pub extensions: Vec<u16>,
+    /// The host name the client asked for, when it is UTF-8.
+    pub server_name: Option<String>,
+    /// The host name the client asked for, as sent.
+    pub server_name_raw: Option<Vec<u8>>,
+    pub alpn_protocols: Vec<Vec<u8>>,
+    pub supported_groups: Vec<u16>,
+    pub ec_point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+    pub supported_versions: Vec<u16>,
+    /// Known extensions whose content doesn't parse, in the order sent.
+    pub malformed_extensions: Vec<u16>,
+    /// The message, handshake header included.
+    pub raw: Vec<u8>,
+}
+
+impl fmt::Debug for ClientHelloInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientHelloInfo")
+            .field("server_name", &self.server_name)
+            .field("ja3", &self.ja3())
+            .field("ja4", &self.ja4())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientHelloInfo {
+    /// Parse a ClientHello message, handshake header included. `None` when the message or
+    /// its list of extensions doesn't parse.
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let mut r = Reader(message);
+        if r.u8()? != record::HANDSHAKE_TYPE_CLIENT_HELLO {
+            return None;
+        }
+        let len = r.u24()?;
+        let mut r = Reader(r.take(len)?);
+        let mut hello = Self {
+            legacy_version: r.u16()?,
+            random: r.take(32)?.try_into().ok()?,
+            session_id: r.vec8()?.to_vec(),
+            cipher_suites: Reader(r.vec16()?).u16s()?,
+            extensions: Vec::new(),
+            server_name: None,
+            server_name_raw: None,
+            alpn_protocols: Vec::new(),
+            supported_groups: Vec::new(),
+            ec_point_formats: Vec::new(),
+            signature_algorithms: Vec::new(),
+            supported_versions: Vec::new(),
+            malformed_extensions: Vec::new(),
+            raw: message.to_vec(),
+        };
+        let _compression_methods = r.vec8()?;
+        // Extensions are optional before TLS 1.3.
+        let mut extensions = Reader(if r.0.is_empty() { &[] } else { r.vec16()? });
+        while !extensions.0.is_empty() {
+            let kind = extensions.u16()?;
+            let data = Reader(extensions.vec16()?);
+            hello.extensions.push(kind);
+            if hello.parse_extension(kind, data).is_none() {
+                hello.malformed_extensions.push(kind);
+            }
+        }
+        Some(hello)
+    }
+
+    /// Fill in the fields of the extension `kind` from its content, `None` when it doesn't
+    /// parse.
+    fn parse_extension(&mut self, kind: u16, mut data: Reader<'_>) -> Option<()> {
+        match kind {
+            EXTENSION_SERVER_NAME => {
+                let mut names = Reader(data.vec16()?);
+                let mut host = None;
+                while !names.0.is_empty() {
+                    let kind = names.u8()?;
+                    let name = names.vec16()?;
+                    if kind == SERVER_NAME_HOST {
+                        host = Some(name.to_vec());
+                    }
+                }
+                self.server_name = host
+                    .as_ref()
+                    .and_then(|name| String::from_utf8(name.clone()).ok());
+                self.server_name_raw = host;
+            }
+            EXTENSION_SUPPORTED_GROUPS => {
+                self.supported_groups = Reader(data.vec16()?).u16s()?;
+            }
+            EXTENSION_EC_POINT_FORMATS => self.ec_point_formats = data.vec8()?.to_vec(),
+            EXTENSION_SIGNATURE_ALGORITHMS => {
+                self.signature_algorithms = Reader(data.vec16()?).u16s()?;
+            }
+            EXTENSION_ALPN => {
+                let mut protocols = Reader(data.vec16()?);
+                let mut alpn_protocols = Vec::new();
+                while !protocols.0.is_empty() {
+                    alpn_protocols.push(protocols.vec8()?.to_vec());
+                }
+                self.alpn_protocols = alpn_protocols;
+            }
+            EXTENSION_SUPPORTED_VERSIONS => {
+                self.supported_versions = Reader(data.vec8()?).u16s()?;
+            }
+            _ => (),
+        }
+        Some(())
+    }
+
+    /// The JA3 fingerprint, before hashing: the version, cipher suites, extensions, groups
+    /// and point formats, without GREASE values. JA3 databases key it by its MD5 digest.
+    pub fn ja3(&self) -> String {
+        let list = |values: &mut dyn Iterator<Item = u16>| {
+            values
+                .filter(|value| !is_grease(*value))
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+        };
+        format!(
+            "{},{},{},{},{}",
+            self.legacy_version,
+            list(&mut self.cipher_suites.iter().copied()),
+            list(&mut self.extensions.iter().copied()),
+            list(&mut self.supported_groups.iter().copied()),
+            list(&mut self.ec_point_formats.iter().map(|&format| format as u16)),
+        )
+    }
+
+    /// The JA4 fingerprint, e.g. `t13d1516h2_8daaf6152771_e5627efa2ab1`, for a client over
+    /// TCP.
+    pub fn ja4(&self) -> String {
+        let ciphers: Vec<u16> = self
+            .cipher_suites
+            .iter()
+            .copied()
+            .filter(|value| !is_grease(*value))
+            .collect();
+        let extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|value| !is_grease(*value))
+            .collect();
+        let version = self
+            .supported_versions
+            .iter()
+            .copied()
+            .filter(|value| !is_grease(*value))
+            .max()
+            .unwrap_or(self.legacy_version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            _ => "00",
+        };
+        let sni = match self.server_name_raw {
+            Some(_) => 'd',
+            None => 'i',
+        };
+        // The first and last characters of the first protocol, or of its hex when they
+        // aren't alphanumeric.
+        let alpn = match self.alpn_protocols.first() {
+            Some(protocol) if !protocol.is_empty() => {
+                let (first, last) = (protocol[0], protocol[protocol.len() - 1]);
+                if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+                    format!("{}{}", first as char, last as char)
+                } else {
+                    let hex = hex(protocol);
+                    format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+                }
+            }
+            _ => "00".to_owned(),
+        };
+
+        let mut sorted_ciphers = ciphers.clone();
+        sorted_ciphers.sort_unstable();
+        let mut sorted_extensions: Vec<u16> = extensions
+            .iter()
+            .copied()
+            .filter(|kind| *kind != EXTENSION_SERVER_NAME && *kind != EXTENSION_ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let mut extensions_part = hex_list(&sorted_extensions);
+        if !self.signature_algorithms.is_empty() {
+            extensions_part.push('_');
+            extensions_part.push_str(&hex_list(&self.signature_algorithms));
+        }
+        format!(
+            "t{version}{sni}{:02}{:02}{alpn}_{}_{}",
+            ciphers.len().min(99),
+            extensions.len().min(99),
+            truncated_sha256(&hex_list(&sorted_ciphers)),
+            truncated_sha256(&extensions_part),
+        )
+    }
+}
+
+/// Read the ClientHello from `io` into `read`, and ask `policy` what to do with the client.
+/// Clients sending anything else, or a ClientHello which doesn't parse, get `unparsable`.
+pub(crate) async fn screen<IO>(
+    policy: &HelloPolicy,
+    unparsable: &HelloAction,
+    io: &mut IO,
+    read: &mut Vec<u8>,
+) -> io::Result<HelloAction>
+where
+    IO: AsyncRead + Unpin,
+{
+    let hello = match record::read_client_hello(io, read).await? {
+        ClientHello::Complete(message) => ClientHelloInfo::parse(&message),
+        ClientHello::Incomplete | ClientHello::Invalid => None,
+    };
+    Ok(match hello {
+        Some(hello) => policy(&hello),
+        None => unparsable.clone(),
+    })
+}
+
+/// GREASE values (RFC 8701), which clients sprinkle in to keep servers tolerant.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_list(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|value| format!("{value:04x}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The first 12 hex digits of the SHA-256 of `input`, or zeros for an empty one.
+fn truncated_sha256(input: &str) -> String {
+    if input.is_empty() {
+        return "0".repeat(12);
+    }
+    hex(&digest::digest(&digest::SHA256, input.as_bytes()).as_ref()[..6])
+}
+
+/// Big-endian reader over a message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let [a, b, c] = self.take(3)?.try_into().ok()?;
+        Some(u32::from_be_bytes([0, a, b, c]) as usize)
+    }
+
+    /// A vector with a one-byte length.
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    /// A vector with a two-byte length.
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    /// The rest as a list of `u16`s.
+    fn u16s(mut self) -> Option<Vec<u16>> {
+        let mut values = Vec::with_capacity(self.0.len() / 2);
+        while !self.0.is_empty() {
+            values.push(self.u16()?);
+        }
+        Some(values)
+    }
+}
@@ -0,0 +1,129 @@
+//! hyper 1.x integration: `hyper::rt` IO traits for `Stream` and an https connector service.
+use std::{
+    future::Future,
+    io::{self, IoSlice},
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    rt::{Read, ReadBufCursor, Write},
+    Uri,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use rustls_fork_shadow_tls::{ConnectionCommon, SideData};
+
+use crate::{client::TlsStream, stream::Stream, TlsConnector, TlsError};
+
+impl<IO: AsyncRead + AsyncWrite, C, SD: SideData + 'static> Read for Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = unsafe {
+            let mut tbuf = ReadBuf::uninit(buf.as_mut());
+            match AsyncRead::poll_read(self, cx, &mut tbuf) {
+                Poll::Ready(Ok(())) => tbuf.filled().len(),
+                other => return other,
+            }
+        };
+        // Safety: tokio's ReadBuf has initialized the first n bytes.
+        unsafe { buf.advance(n) };
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: AsyncRead + AsyncWrite, C, SD: SideData + 'static> Write for Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_shutdown(self, cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        AsyncWrite::is_write_vectored(self)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write_vectored(self, cx, bufs)
+    }
+}
+
+/// A `tower::Service<Uri>` which dials the uri's authority and performs the TLS handshake.
+///
+/// The resulting stream implements hyper's IO traits, so it can be passed to
+/// `hyper::client::conn` handshakes directly. As the stream also implements tokio's IO
+/// traits, this is a `tower::MakeConnection<Uri>` as well.
+#[derive(Clone)]
+pub struct HttpsConnector {
+    connector: TlsConnector,
+}
+
+impl HttpsConnector {
+    pub fn new(connector: TlsConnector) -> Self {
+        Self { connector }
+    }
+}
+
+impl From<TlsConnector> for HttpsConnector {
+    fn from(connector: TlsConnector) -> Self {
+        Self::new(connector)
+    }
+}
+
+impl tower_service::Service<Uri> for HttpsConnector {
+    type Response = TlsStream<TcpStream>;
+    type Error = TlsError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.connector.clone();
+        Box::pin(async move {
+            if uri.scheme_str().is_some_and(|s| s != "https") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only https uri is supported",
+                )
+                .into());
+            }
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing host"))?;
+            let host = host
+                .strip_prefix('[')
+                .and_then(|h| h.strip_suffix(']'))
+                .unwrap_or(host);
+            let port = uri.port_u16().unwrap_or(443);
+            connector.connect_host(host, port).await
+        })
+    }
+}
@@ -0,0 +1,107 @@
+//! Parsed identity of the peer's certificate.
+use std::{
+    net::IpAddr,
+    ops::{Deref, DerefMut},
+    time::SystemTime,
+};
+
+use rustls_fork_shadow_tls::{Certificate, ConnectionCommon, SideData};
+
+use crate::{stream::Stream, x509};
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// The names, serial and validity of a certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// DNS names from the subject alternative names.
+    pub dns_names: Vec<String>,
+    /// IP addresses from the subject alternative names.
+    pub ip_addresses: Vec<IpAddr>,
+    /// The most specific common name of the subject.
+    pub common_name: Option<String>,
+    /// The serial number, as unsigned big-endian bytes.
+    pub serial: Vec<u8>,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+impl PeerIdentity {
+    /// Parse `cert`, or `None` when it is malformed.
+    pub fn from_certificate(cert: &Certificate) -> Option<Self> {
+        const DNS_NAME: u8 = 0x82;
+        const IP_ADDRESS: u8 = 0x87;
+        let tbs = x509::tbs(&cert.0)?;
+        let (not_before, not_after) = tbs.validity()?;
+
+        let mut dns_names = Vec::new();
+        let mut ip_addresses = Vec::new();
+        if let Some(names) = tbs.extension(x509::OID_SUBJECT_ALT_NAME) {
+            for (tag, _, name) in x509::sequence(names)? {
+                match (tag, name.len()) {
+                    (DNS_NAME, _) => dns_names.push(String::from_utf8(name.to_vec()).ok()?),
+                    (IP_ADDRESS, 4) => ip_addresses.push(<[u8; 4]>::try_from(name).ok()?.into()),
+                    (IP_ADDRESS, 16) => {
+                        ip_addresses.push(<[u8; 16]>::try_from(name).ok()?.into())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Some(Self {
+            dns_names,
+            ip_addresses,
+            common_name: common_name(tbs.subject),
+            serial: match tbs.serial {
+                [0, rest @ ..] if !rest.is_empty() => rest.to_vec(),
+                serial => serial.to_vec(),
+            },
+            not_before,
+            not_after,
+        })
+    }
+}
+
+/// The last common name in `name`, an RDNSequence.
+fn common_name(name: &[u8]) -> Option<String> {
+    const UTF8_STRING: u8 = 0x0c;
+    const PRINTABLE_STRING: u8 = 0x13;
+    const IA5_STRING: u8 = 0x16;
+    const BMP_STRING: u8 = 0x1e;
+    let (tag, value) = x509::sequence(name)?
+        .flat_map(|(_, _, rdn)| x509::Elements(rdn))
+        .filter_map(|(_, attribute, _)| {
+            let mut fields = x509::sequence(attribute)?;
+            let (x509::TAG_OID, _, OID_COMMON_NAME) = fields.next()? else {
+                return None;
+            };
+            let (tag, _, value) = fields.next()?;
+            Some((tag, value))
+        })
+        .last()?;
+    match tag {
+        UTF8_STRING | PRINTABLE_STRING | IA5_STRING => String::from_utf8(value.to_vec()).ok(),
+        BMP_STRING => {
+            let units: Vec<u16> = value
+                .chunks_exact(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            String::from_utf16(&units).ok()
+        }
+        _ => None,
+    }
+}
+
+impl<IO, C, SD: SideData> Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    /// The identity in the peer's end-entity certificate, once the handshake is done.
+    ///
+    /// `None` when the peer sent no certificate, e.g. a client without client auth, or it
+    /// couldn't be parsed.
+    pub fn peer_identity(&self) -> Option<PeerIdentity> {
+        PeerIdentity::from_certificate(self.session.peer_certificates()?.first()?)
+    }
+}
@@ -0,0 +1,32 @@
+//! Conversions from the types of upstream rustls to those of the fork.
+//!
+//! Certificates, keys and root stores carry over as DER. Configs don't: their verifiers and
+//! resolvers are trait objects of the other crate, and the roots of a `rustls::ClientConfig`
+//! live in its verifier, out of reach. Build the fork's config from the parts instead, e.g.
+//! with [`TlsConnectorBuilder::with_rustls_roots`](crate::TlsConnectorBuilder::with_rustls_roots)
+//! and [`TlsConnectorBuilder::with_rustls_options`](crate::TlsConnectorBuilder::with_rustls_options).
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_fork_shadow_tls::{Certificate, OwnedTrustAnchor, PrivateKey, RootCertStore};
+
+/// The fork's certificate for an upstream one.
+pub fn certificate_from_rustls(cert: &CertificateDer<'_>) -> Certificate {
+    Certificate(cert.to_vec())
+}
+
+/// The fork's private key for an upstream one, of whichever encoding.
+pub fn private_key_from_rustls(key: &PrivateKeyDer<'_>) -> PrivateKey {
+    PrivateKey(key.secret_der().to_vec())
+}
+
+/// The fork's root store with the trust anchors of an upstream one.
+pub fn root_store_from_rustls(roots: &rustls::RootCertStore) -> RootCertStore {
+    let mut store = RootCertStore::empty();
+    store.add_server_trust_anchors(roots.roots.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject.to_vec(),
+            anchor.subject_public_key_info.to_vec(),
+            anchor.name_constraints.as_ref().map(|nc| nc.to_vec()),
+        )
+    }));
+    store
+}
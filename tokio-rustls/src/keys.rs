@@ -0,0 +1,87 @@
+//! Certificate chains with their private keys.
+use std::{fs, io, path::Path};
+
+use rustls_fork_shadow_tls::{Certificate, PrivateKey};
+
+/// A certificate chain and the private key of its end-entity certificate.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    /// Starts with the end-entity certificate, followed by its issuers.
+    pub chain: Vec<Certificate>,
+    /// PKCS#8, PKCS#1 or SEC1 DER.
+    pub key: PrivateKey,
+}
+
+impl Identity {
+    pub fn new(chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        Self { chain, key }
+    }
+
+    /// Load the chain from the `CERTIFICATE` blocks of `chain_pem`, end-entity certificate
+    /// first, and the key from the first PKCS#8, PKCS#1 or SEC1 key block of `key_pem`. Both
+    /// may be the same file.
+    pub fn from_pem(chain_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let chain: Vec<_> = rustls_pemfile::certs(&mut &chain_pem[..])?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        if chain.is_empty() {
+            return Err(invalid("no certificate in the PEM input"));
+        }
+        let key = rustls_pemfile::read_all(&mut &key_pem[..])?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or_else(|| invalid("no private key in the PEM input"))?;
+        Ok(Self { chain, key })
+    }
+
+    /// [`from_pem`](Self::from_pem) with the contents of the files at `chain_path` and
+    /// `key_path`.
+    pub fn from_pem_files(
+        chain_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        Self::from_pem(&fs::read(chain_path)?, &fs::read(key_path)?)
+    }
+
+    /// Load the key and certificate chain of a PKCS#12 (`.p12`/`.pfx`) bundle encrypted with
+    /// `password`. Both the PBES2/AES encryption current tools default to and the legacy
+    /// 3DES/RC2 schemes are supported.
+    #[cfg(feature = "pkcs12")]
+    pub fn from_pkcs12(der: &[u8], password: &str) -> io::Result<Self> {
+        let keystore = p12_keystore::KeyStore::from_pkcs12(der, password)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let (_, entry) = keystore.private_key_chain().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "no private key in the bundle")
+        })?;
+        Ok(Self {
+            chain: entry
+                .chain()
+                .iter()
+                .map(|cert| Certificate(cert.as_der().to_vec()))
+                .collect(),
+            key: PrivateKey(entry.key().to_vec()),
+        })
+    }
+
+    /// The chain and key of an upstream rustls identity, e.g. one loaded by another library.
+    #[cfg(feature = "rustls-interop")]
+    pub fn from_rustls(
+        chain: &[rustls::pki_types::CertificateDer<'_>],
+        key: &rustls::pki_types::PrivateKeyDer<'_>,
+    ) -> Self {
+        Self {
+            chain: chain
+                .iter()
+                .map(crate::interop::certificate_from_rustls)
+                .collect(),
+            key: crate::interop::private_key_from_rustls(key),
+        }
+    }
+}
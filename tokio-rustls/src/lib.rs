@@ -1,21 +1,150 @@
 #![allow(stable_features)]
 
+#[cfg(feature = "acme")]
+mod acme;
+mod artifacts;
+#[cfg(feature = "axum")]
+mod axum;
+mod base64;
+mod batch;
+mod blocking;
+mod builder;
+mod camouflage;
+#[cfg(feature = "test-util")]
+mod chaos;
 mod client;
+mod clock;
+#[cfg(feature = "codec")]
+mod codec;
+mod crl;
+mod ct;
+#[cfg(feature = "dev")]
+mod dev;
+mod dial;
 mod error;
+mod expiry;
+mod fallback;
+mod fd;
+mod flush;
+#[cfg(feature = "futures-io")]
+mod futures_io;
+mod hello;
+#[cfg(feature = "hyper")]
+mod hyper;
+#[cfg(feature = "peer_identity")]
+mod identity;
+#[cfg(feature = "rustls-interop")]
+mod interop;
+mod keys;
+mod listener;
+mod memory;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "monoio")]
+mod monoio;
+mod negotiated;
+mod observer;
+mod ocsp;
+#[cfg(feature = "pcap")]
+mod pcap;
+mod pin;
+mod probe;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod proxy_protocol;
+#[cfg(feature = "quic")]
+mod quic;
+mod record;
+mod relay;
+mod resilient;
+mod router;
 #[cfg(not(feature = "unsafe_io"))]
 mod safe_io;
 mod server;
+#[cfg(feature = "tower")]
+mod service;
+mod sni;
 mod split;
 mod stream;
+mod tap;
+#[cfg(feature = "test-util")]
+mod test_util;
+mod throttle;
+mod ticketer;
+mod timeout;
 #[cfg(feature = "unsafe_io")]
 mod unsafe_io;
+#[cfg(feature = "tokio-uring")]
+mod uring;
+#[cfg(feature = "dangerous_configuration")]
+mod verify;
+mod x509;
 
+#[cfg(feature = "acme")]
+pub use acme::{AcmeCertResolver, LETS_ENCRYPT, LETS_ENCRYPT_STAGING};
+pub use artifacts::HandshakeArtifacts;
+pub use batch::Handshakes;
+pub use blocking::BlockingTlsStream;
+pub use builder::{ClientAuth, TlsAcceptorBuilder, TlsConnectorBuilder};
+pub use camouflage::{FailureResponse, Relay, Tarpit};
+#[cfg(feature = "test-util")]
+pub use chaos::ChaosIo;
 pub use client::{
     TlsConnector, TlsStream as ClientTlsStream, TlsStreamReadHalf as ClientTlsStreamReadHalf,
     TlsStreamWriteHalf as ClientTlsStreamWriteHalf,
 };
-pub use error::TlsError;
+pub use clock::TimeProvider;
+#[cfg(feature = "codec")]
+pub use codec::{framed_read, framed_write, TlsFramed, TlsInfo};
+pub use crl::{CrlSet, RevocationPolicy};
+pub use ct::{CtLog, CtPolicy, Sct};
+#[cfg(feature = "dev")]
+pub use dev::{generate_self_signed, SelfSigned};
+pub use error::{ErrorContext, HandshakeFailureReason, TlsError};
+pub use expiry::{CertExpiry, ExpiryAlerts};
+pub use flush::FlushScheduler;
+pub use hello::{ClientHelloInfo, HelloAction};
+#[cfg(feature = "hyper")]
+pub use crate::hyper::HttpsConnector;
+#[cfg(feature = "peer_identity")]
+pub use identity::PeerIdentity;
+#[cfg(feature = "rustls-interop")]
+pub use interop::{certificate_from_rustls, private_key_from_rustls, root_store_from_rustls};
+pub use keys::Identity;
+pub use listener::{TlsListener, WhenFull};
+pub use memory::MemoryBudget;
+#[cfg(feature = "metrics")]
+pub use crate::metrics::{Metrics, Snapshot};
+#[cfg(feature = "monoio")]
+pub use crate::monoio::MonoioIo;
+pub use observer::HandshakeObserver;
+#[cfg(feature = "dangerous_configuration")]
+pub use ocsp::StaplePolicy;
+pub use ocsp::{fetch_ocsp_response, StapledCert};
+#[cfg(feature = "pcap")]
+pub use pcap::PcapCapture;
+pub use pin::{PinFailure, PinSet};
+pub use probe::ProbeReport;
+#[cfg(feature = "proxy")]
+pub use proxy::{ProxiedConnector, Proxy};
+pub use proxy_protocol::ProxyHeader;
+#[cfg(feature = "quic")]
+pub use quic::QuinnIo;
+pub use relay::{copy_bidirectional_with_budget, RelayBudget, RelayStats, TransferStats};
+pub use resilient::{ReconnectEvent, ResilientTlsStream};
+pub use router::AlpnRouter;
 pub use server::{
-    TlsAcceptor, TlsStream as ServerTlsStream, TlsStreamReadHalf as ServerTlsStreamReadHalf,
+    Accepted, TlsAcceptor, TlsStream as ServerTlsStream, TlsStreamReadHalf as ServerTlsStreamReadHalf,
     TlsStreamWriteHalf as ServerTlsStreamWriteHalf,
 };
+#[cfg(feature = "tower")]
+pub use service::TlsConnectService;
+pub use split::ReuniteError;
+pub use stream::{CloseOnDrop, HandshakeKind, HandshakeSummary, Stats};
+pub use tap::{Direction, Record, RecordTap};
+#[cfg(feature = "test-util")]
+pub use test_util::{tls_pair, TlsPairBuilder};
+pub use throttle::{RateLimit, ThrottledIo};
+pub use timeout::PartialTransfer;
+#[cfg(feature = "tokio-uring")]
+pub use uring::{UringIo, UringSocket};
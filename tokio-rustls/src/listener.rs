@@ -0,0 +1,286 @@
+//! TLS listener which accepts TCP connections and runs handshakes concurrently.
+use std::{future::Future, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+
+use crate::{
+    camouflage::{self, Relay},
+    error::ErrorContext,
+    record,
+    server::{Accepted, TlsStream},
+    TlsAcceptor, TlsError,
+};
+
+/// Default max number of handshakes in progress at the same time.
+const DEFAULT_MAX_HANDSHAKES: usize = 64;
+/// Pause after a failed TCP accept in `serve`, which would likely fail again right away, e.g.
+/// when out of file descriptors.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+type HandshakeResult = (Result<TlsStream<TcpStream>, TlsError>, SocketAddr);
+
+/// What a [`TlsListener`] does with new TCP connections while it holds as many connections
+/// as `with_max_connections` allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WhenFull {
+    /// Leave them in the kernel's accept backlog until a connection closes.
+    #[default]
+    Pause,
+    /// Accept them, send a fatal internal_error alert and close them right away.
+    Reject,
+}
+
+struct ConnectionLimit {
+    slots: Arc<Semaphore>,
+    when_full: WhenFull,
+}
+
+/// Wraps a `TcpListener` and a [`TlsAcceptor`].
+///
+/// Handshakes run in spawned tasks, so a slow client does not block accepting others.
+pub struct TlsListener {
+    pub(crate) listener: TcpListener,
+    acceptor: TlsAcceptor,
+    handshakes: JoinSet<HandshakeResult>,
+    max_handshakes: usize,
+    handshake_timeout: Option<Duration>,
+    limit: Option<ConnectionLimit>,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> Self {
+        Self {
+            listener,
+            acceptor,
+            handshakes: JoinSet::new(),
+            max_handshakes: DEFAULT_MAX_HANDSHAKES,
+            handshake_timeout: None,
+            limit: None,
+        }
+    }
+
+    /// Set max number of handshakes in progress at the same time. New TCP connections are
+    /// not accepted while the limit is reached.
+    ///
+    /// Connections the acceptor relays to a TLS server, with `TlsAcceptor::with_fallback` or
+    /// [`FailureResponse::Relay`](crate::FailureResponse::Relay), leave the handshakes once
+    /// the relay starts: it runs in a task of its own, which counts against
+    /// `with_max_connections` until it ends, but neither against this limit nor the handshake
+    /// timeout.
+    pub fn with_max_handshakes(mut self, max: usize) -> Self {
+        self.max_handshakes = max.max(1);
+        self
+    }
+
+    /// Fail handshakes which take longer than `timeout` with `TimedOut`. Unlimited by
+    /// default, which lets a client that never finishes its handshake hold a slot forever.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Hold at most `max` connections at the same time, counting handshakes in progress and
+    /// accepted streams until they are dropped; `when_full` sets what happens to connections
+    /// beyond that.
+    pub fn with_max_connections(mut self, max: usize, when_full: WhenFull) -> Self {
+        self.limit = Some(ConnectionLimit {
+            slots: Arc::new(Semaphore::new(max.max(1))),
+            when_full,
+        });
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+
+    /// Wait for the next completed handshake.
+    ///
+    /// Both failed TCP accepts and failed handshakes are returned as errors; the listener
+    /// stays usable after either. [`TlsError::failure_reason`] sorts them into categories.
+    pub async fn accept(&mut self) -> Result<(TlsStream<TcpStream>, SocketAddr), TlsError> {
+        loop {
+            let can_accept = self.handshakes.len() < self.max_handshakes;
+            tokio::select! {
+                res = accept_tcp(&self.listener, &self.limit), if can_accept => {
+                    let (stream, addr, permit) = res?;
+                    self.spawn_handshake(stream, addr, permit);
+                }
+                Some(res) = self.handshakes.join_next() => match res {
+                    Ok((Ok(stream), addr)) => return Ok((stream, addr)),
+                    Ok((Err(e), _)) => return Err(e),
+                    Err(e) => return Err(io::Error::other(e).into()),
+                },
+            }
+        }
+    }
+
+    /// Accept connections and run `handler` on each in its own task, forever. See
+    /// [`serve_with_shutdown`](Self::serve_with_shutdown).
+    pub async fn serve<F, Fut>(self, handler: F)
+    where
+        F: Fn(TlsStream<TcpStream>, SocketAddr) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.serve_with_shutdown(handler, std::future::pending())
+            .await
+    }
+
+    /// Accept connections and run `handler` on each in its own task, until `shutdown`
+    /// completes. Then stop accepting, abort handshakes in progress and wait for the running
+    /// handlers to return.
+    ///
+    /// Dropping the returned future aborts the running handlers, so wrapping it in
+    /// `tokio::time::timeout` bounds how long the shutdown waits. Failed accepts and
+    /// handshakes are skipped.
+    pub async fn serve_with_shutdown<F, Fut, S>(mut self, handler: F, shutdown: S)
+    where
+        F: Fn(TlsStream<TcpStream>, SocketAddr) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+        S: Future<Output = ()>,
+    {
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+        loop {
+            let can_accept = self.handshakes.len() < self.max_handshakes;
+            tokio::select! {
+                () = &mut shutdown => break,
+                res = accept_tcp(&self.listener, &self.limit), if can_accept => match res {
+                    Ok((stream, addr, permit)) => self.spawn_handshake(stream, addr, permit),
+                    Err(_e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(%_e, "tcp accept failed");
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    }
+                },
+                Some(res) = self.handshakes.join_next() => match res {
+                    Ok((Ok(stream), addr)) => {
+                        connections.spawn(handler(stream, addr));
+                    }
+                    Ok((Err(_e), _addr)) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(
+                            %_addr,
+                            reason = ?_e.failure_reason(),
+                            ?_e,
+                            "tls handshake failed"
+                        );
+                    }
+                    Err(_e) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(%_e, "tls handshake task failed");
+                    }
+                },
+                Some(res) = connections.join_next() => {
+                    if let Err(_e) = res {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(%_e, "connection handler failed");
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(connections = connections.len(), "shutting down, draining");
+        self.handshakes.abort_all();
+        while connections.join_next().await.is_some() {}
+    }
+
+    fn spawn_handshake(
+        &mut self,
+        stream: TcpStream,
+        addr: SocketAddr,
+        permit: Option<OwnedSemaphorePermit>,
+    ) {
+        let acceptor = self.acceptor.clone();
+        let timeout = self.handshake_timeout;
+        let context = ErrorContext {
+            server_name: None,
+            peer_addr: Some(addr),
+        };
+        self.handshakes.spawn(async move {
+            let res = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, acceptor.accept_or_relay(stream))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(
+                            io::Error::new(io::ErrorKind::TimedOut, "tls handshake timed out")
+                                .into(),
+                        )
+                    }),
+                None => acceptor.accept_or_relay(stream).await,
+            };
+            let res = match res {
+                Ok(Accepted::Stream(mut stream)) => {
+                    stream.permit = permit;
+                    Ok(stream)
+                }
+                Ok(Accepted::Relayed(Relay {
+                    mut io,
+                    backend,
+                    sent,
+                    error,
+                })) => {
+                    // Off the handshake slot and timeout, holding the connection slot.
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        let _ = camouflage::relay(&mut io, &backend, &sent).await;
+                    });
+                    Err(error)
+                }
+                Err(e) => Err(e),
+            };
+            let res = res.map_err(|e| match acceptor.error_context {
+                true => e.with_context(context),
+                false => e,
+            });
+            (res, addr)
+        });
+    }
+}
+
+/// Accept the next TCP connection `limit` lets in, with its slot.
+async fn accept_tcp(
+    listener: &TcpListener,
+    limit: &Option<ConnectionLimit>,
+) -> io::Result<(TcpStream, SocketAddr, Option<OwnedSemaphorePermit>)> {
+    let Some(limit) = limit else {
+        let (stream, addr) = listener.accept().await?;
+        return Ok((stream, addr, None));
+    };
+    loop {
+        let permit = match limit.when_full {
+            WhenFull::Pause => Some(
+                limit
+                    .slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(io::Error::other)?,
+            ),
+            WhenFull::Reject => None,
+        };
+        let (stream, addr) = listener.accept().await?;
+        let permit = match permit {
+            Some(permit) => permit,
+            None => match limit.slots.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(%addr, "connection limit reached, rejecting");
+                    let _ = stream.try_write(&record::INTERNAL_ERROR_ALERT);
+                    continue;
+                }
+            },
+        };
+        return Ok((stream, addr, Some(permit)));
+    }
+}
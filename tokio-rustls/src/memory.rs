@@ -0,0 +1,118 @@
+//! A memory budget shared by many streams.
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// The buffers between the stream and its IO.
+#[cfg(not(feature = "unsafe_io"))]
+const IO_BUFFERS: usize = 2 * crate::safe_io::BUFFER_SIZE;
+#[cfg(feature = "unsafe_io")]
+const IO_BUFFERS: usize = 0;
+/// rustls' deframer buffer, which holds a record of the longest size allowed on the wire.
+const DEFRAMER: usize = 5 + 0x4000 + 2048;
+/// rustls' default limit on the plaintext and on the ciphertext queued for sending, each.
+const SEND_BUFFER_LIMIT: usize = 64 * 1024;
+/// Decrypted plaintext rustls holds until it is read, about a record.
+const RECEIVED_PLAINTEXT: usize = 0x4000;
+/// What a stream can hold in buffers at most, for budgeting purposes.
+const STREAM_FOOTPRINT: usize = IO_BUFFERS + DEFRAMER + 2 * SEND_BUFFER_LIMIT + RECEIVED_PLAINTEXT;
+
+/// A bound on the buffer memory of all the streams of the connectors and acceptors sharing
+/// it. Set with `TlsConnector::with_memory_budget` and `TlsAcceptor::with_memory_budget`.
+///
+/// Each stream is charged the most its buffers and rustls' can hold, a little under 200 KiB,
+/// for as long as it lives, a graceful close spawned on drop included. New connections which
+/// would take the budget over fail with `OutOfMemory` before their handshake starts; streams
+/// already established are not affected.
+/// Certificate chains and the sessions in caches are not counted.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Budget>,
+}
+
+struct Budget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Budget {
+                limit,
+                used: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Bytes charged to the streams alive now.
+    pub fn used(&self) -> usize {
+        self.inner.used.load(Ordering::Relaxed)
+    }
+
+    /// Bytes charged to each stream.
+    pub fn per_stream(&self) -> usize {
+        STREAM_FOOTPRINT
+    }
+
+    /// Charge a new stream, or fail with `OutOfMemory` when the budget can't take it.
+    pub(crate) fn reserve(&self) -> io::Result<MemoryCharge> {
+        let limit = self.inner.limit;
+        self.inner
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(STREAM_FOOTPRINT)
+                    .filter(|&used| used <= limit)
+            })
+            .map_err(|_| {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    limit,
+                    "tls memory budget exhausted, refusing the connection"
+                );
+                io::Error::new(io::ErrorKind::OutOfMemory, "tls memory budget exhausted")
+            })?;
+        Ok(MemoryCharge {
+            budget: self.inner.clone(),
+        })
+    }
+}
+
+/// The share of a budget a stream holds, given back on drop.
+pub(crate) struct MemoryCharge {
+    budget: Arc<Budget>,
+}
+
+impl fmt::Debug for MemoryCharge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryCharge")
+            .field("bytes", &STREAM_FOOTPRINT)
+            .finish()
+    }
+}
+
+impl Drop for MemoryCharge {
+    fn drop(&mut self) {
+        self.budget
+            .used
+            .fetch_sub(STREAM_FOOTPRINT, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,215 @@
+//! Connection metrics shared by the streams of a connector or acceptor.
+//!
+//! Counters are kept in memory and can be read with [`Metrics::snapshot`]. They are also
+//! reported to the `metrics` facade, labeled with the name given to [`Metrics::new`].
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the handshake latency histogram buckets.
+const LATENCY_BUCKETS: [Duration; 12] = [
+    Duration::from_millis(1),
+    Duration::from_millis(2),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+];
+
+#[derive(Debug, Default)]
+struct Counters {
+    handshakes_attempted: AtomicU64,
+    handshakes_succeeded: AtomicU64,
+    handshakes_failed: AtomicU64,
+    handshakes_resumed: AtomicU64,
+    /// One more than the buckets, the last one counts the rest.
+    handshake_latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    handshake_latency_sum_us: AtomicU64,
+    bytes_encrypted: AtomicU64,
+    bytes_decrypted: AtomicU64,
+    records_read: AtomicU64,
+    records_written: AtomicU64,
+    active_connections: AtomicU64,
+}
+
+/// Point-in-time copy of the counters of a [`Metrics`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub handshakes_attempted: u64,
+    pub handshakes_succeeded: u64,
+    pub handshakes_failed: u64,
+    /// Succeeded handshakes which resumed a previous session.
+    pub handshakes_resumed: u64,
+    /// Cumulative count of handshakes finished within each upper bound; the last entry
+    /// (`Duration::MAX`) counts all of them.
+    pub handshake_latency: Vec<(Duration, u64)>,
+    pub handshake_latency_sum: Duration,
+    /// Plaintext bytes written by the application.
+    pub bytes_encrypted: u64,
+    /// Plaintext bytes read by the application.
+    pub bytes_decrypted: u64,
+    pub records_read: u64,
+    pub records_written: u64,
+    pub active_connections: u64,
+}
+
+impl Snapshot {
+    /// Ratio of resumed to succeeded handshakes.
+    pub fn resumption_rate(&self) -> f64 {
+        match self.handshakes_succeeded {
+            0 => 0.0,
+            n => self.handshakes_resumed as f64 / n as f64,
+        }
+    }
+}
+
+/// Shared metrics handle, set with `with_metrics` on a connector or acceptor.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    name: Arc<str>,
+    counters: Arc<Counters>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new("default")
+    }
+}
+
+impl Metrics {
+    /// `name` is used as the `name` label of the metrics reported to the facade.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            counters: Default::default(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let c = &*self.counters;
+        let load = |v: &AtomicU64| v.load(Ordering::Relaxed);
+        let mut cumulative = 0;
+        let handshake_latency = LATENCY_BUCKETS
+            .iter()
+            .copied()
+            .chain(Some(Duration::MAX))
+            .zip(c.handshake_latency.iter())
+            .map(|(bound, count)| {
+                cumulative += load(count);
+                (bound, cumulative)
+            })
+            .collect();
+        Snapshot {
+            handshakes_attempted: load(&c.handshakes_attempted),
+            handshakes_succeeded: load(&c.handshakes_succeeded),
+            handshakes_failed: load(&c.handshakes_failed),
+            handshakes_resumed: load(&c.handshakes_resumed),
+            handshake_latency,
+            handshake_latency_sum: Duration::from_micros(load(&c.handshake_latency_sum_us)),
+            bytes_encrypted: load(&c.bytes_encrypted),
+            bytes_decrypted: load(&c.bytes_decrypted),
+            records_read: load(&c.records_read),
+            records_written: load(&c.records_written),
+            active_connections: load(&c.active_connections),
+        }
+    }
+
+    pub(crate) fn handshake_started(&self) -> Instant {
+        self.counters
+            .handshakes_attempted
+            .fetch_add(1, Ordering::Relaxed);
+        metrics::counter!("tls_handshakes_attempted_total", "name" => self.name.to_string())
+            .increment(1);
+        Instant::now()
+    }
+
+    pub(crate) fn handshake_failed(&self) {
+        self.counters.handshakes_failed.fetch_add(1, Ordering::Relaxed);
+        metrics::counter!("tls_handshakes_failed_total", "name" => self.name.to_string())
+            .increment(1);
+    }
+
+    pub(crate) fn handshake_succeeded(&self, start: Instant, resumed: bool) {
+        let c = &*self.counters;
+        let elapsed = start.elapsed();
+        c.handshakes_succeeded.fetch_add(1, Ordering::Relaxed);
+        if resumed {
+            c.handshakes_resumed.fetch_add(1, Ordering::Relaxed);
+        }
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| elapsed <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        c.handshake_latency[bucket].fetch_add(1, Ordering::Relaxed);
+        c.handshake_latency_sum_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        c.active_connections.fetch_add(1, Ordering::Relaxed);
+
+        let name = self.name.to_string();
+        metrics::counter!("tls_handshakes_succeeded_total", "name" => name.clone()).increment(1);
+        if resumed {
+            metrics::counter!("tls_handshakes_resumed_total", "name" => name.clone())
+                .increment(1);
+        }
+        metrics::histogram!("tls_handshake_duration_seconds", "name" => name.clone())
+            .record(elapsed.as_secs_f64());
+        metrics::gauge!("tls_active_connections", "name" => name).increment(1.0);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        self.counters
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+        metrics::gauge!("tls_active_connections", "name" => self.name.to_string()).decrement(1.0);
+    }
+
+    pub(crate) fn encrypted(&self, n: usize) {
+        self.counters
+            .bytes_encrypted
+            .fetch_add(n as u64, Ordering::Relaxed);
+        metrics::counter!("tls_bytes_encrypted_total", "name" => self.name.to_string())
+            .increment(n as u64);
+    }
+
+    pub(crate) fn decrypted(&self, n: usize) {
+        self.counters
+            .bytes_decrypted
+            .fetch_add(n as u64, Ordering::Relaxed);
+        metrics::counter!("tls_bytes_decrypted_total", "name" => self.name.to_string())
+            .increment(n as u64);
+    }
+
+    pub(crate) fn records(&self, read: u64, written: u64) {
+        let c = &*self.counters;
+        c.records_read.fetch_add(read, Ordering::Relaxed);
+        c.records_written.fetch_add(written, Ordering::Relaxed);
+        let name = self.name.to_string();
+        metrics::counter!("tls_records_read_total", "name" => name.clone()).increment(read);
+        metrics::counter!("tls_records_written_total", "name" => name).increment(written);
+    }
+}
+
+/// Per-stream handle; counts the connection as active from a successful handshake until drop.
+#[derive(Debug)]
+pub(crate) struct StreamMetrics {
+    pub(crate) metrics: Metrics,
+    pub(crate) active: bool,
+}
+
+impl Drop for StreamMetrics {
+    fn drop(&mut self) {
+        if self.active {
+            self.metrics.connection_closed();
+        }
+    }
+}
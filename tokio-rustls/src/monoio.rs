@@ -0,0 +1,160 @@
+//! Adapter running the TLS stream over monoio's owned-buffer IO.
+//!
+//! `MonoioIo` turns an `AsyncReadRent + AsyncWriteRent` transport (e.g. a monoio
+//! `TcpStream` on io_uring) into the `AsyncRead`/`AsyncWrite` pair `Stream` expects, so the
+//! same connector and acceptor work on monoio runtimes.
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use monoio::{
+    io::{AsyncReadRent, AsyncWriteRent},
+    BufResult,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+type LocalFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+/// Owned-buffer IO adapter.
+///
+/// An operation which returned `Pending` keeps running with the buffer it was started with;
+/// like with the `unsafe_io` feature, the caller must retry with the same data until it is
+/// ready. `Stream` always does so.
+pub struct MonoioIo<IO> {
+    // The in-flight futures reference `io` through a raw pointer, so they must be declared
+    // (and dropped) before it.
+    read_fut: Option<LocalFuture<BufResult<usize, Vec<u8>>>>,
+    write_fut: Option<LocalFuture<BufResult<usize, Vec<u8>>>>,
+    flush_fut: Option<LocalFuture<io::Result<()>>>,
+    shutdown_fut: Option<LocalFuture<io::Result<()>>>,
+    // Boxed so the address captured by the futures stays stable when the adapter moves.
+    io: Box<IO>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Option<Vec<u8>>,
+}
+
+impl<IO> MonoioIo<IO> {
+    pub fn new(io: IO) -> Self {
+        Self {
+            read_fut: None,
+            write_fut: None,
+            flush_fut: None,
+            shutdown_fut: None,
+            io: Box::new(io),
+            read_buf: Vec::with_capacity(BUFFER_SIZE),
+            read_pos: 0,
+            write_buf: Some(Vec::with_capacity(BUFFER_SIZE)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// Returns `None` while an operation is still in flight.
+    pub fn into_inner(self) -> Option<IO> {
+        let idle = self.read_fut.is_none()
+            && self.write_fut.is_none()
+            && self.flush_fut.is_none()
+            && self.shutdown_fut.is_none();
+        if idle {
+            Some(*self.io)
+        } else {
+            None
+        }
+    }
+}
+
+impl<IO: AsyncReadRent + 'static> AsyncRead for MonoioIo<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            // serve buffered data first
+            if this.read_pos < this.read_buf.len() {
+                let n = buf.remaining().min(this.read_buf.len() - this.read_pos);
+                buf.put_slice(&this.read_buf[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            let fut = this.read_fut.get_or_insert_with(|| {
+                let mut owned = std::mem::take(&mut this.read_buf);
+                owned.clear();
+                let io: *mut IO = &mut *this.io;
+                // Safety: the boxed io outlives the future, see the field order.
+                Box::pin(async move { unsafe { &mut *io }.read(owned).await })
+            });
+            let (res, owned) = match fut.as_mut().poll(cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.read_fut = None;
+            this.read_buf = owned;
+            this.read_pos = 0;
+            match res {
+                Ok(0) => return Poll::Ready(Ok(())),
+                Ok(_) => (),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+impl<IO: AsyncWriteRent + 'static> AsyncWrite for MonoioIo<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let fut = this.write_fut.get_or_insert_with(|| {
+            let mut owned = this.write_buf.take().unwrap_or_default();
+            owned.clear();
+            owned.extend_from_slice(&buf[..buf.len().min(BUFFER_SIZE)]);
+            let io: *mut IO = &mut *this.io;
+            // Safety: the boxed io outlives the future, see the field order.
+            Box::pin(async move { unsafe { &mut *io }.write(owned).await })
+        });
+        let (res, owned) = match fut.as_mut().poll(cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.write_fut = None;
+        this.write_buf = Some(owned);
+        Poll::Ready(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let fut = this.flush_fut.get_or_insert_with(|| {
+            let io: *mut IO = &mut *this.io;
+            // Safety: the boxed io outlives the future, see the field order.
+            Box::pin(async move { unsafe { &mut *io }.flush().await })
+        });
+        let res = std::task::ready!(fut.as_mut().poll(cx));
+        this.flush_fut = None;
+        Poll::Ready(res)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let fut = this.shutdown_fut.get_or_insert_with(|| {
+            let io: *mut IO = &mut *this.io;
+            // Safety: the boxed io outlives the future, see the field order.
+            Box::pin(async move { unsafe { &mut *io }.shutdown().await })
+        });
+        let res = std::task::ready!(fut.as_mut().poll(cx));
+        this.shutdown_fut = None;
+        Poll::Ready(res)
+    }
+}
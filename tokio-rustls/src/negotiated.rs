@@ -0,0 +1,127 @@
+//! Restricting what the handshake may settle on.
+use std::{
+    fmt, io,
+    ops::{Deref, DerefMut},
+};
+
+use rustls_fork_shadow_tls::{
+    CipherSuite, ConnectionCommon, NamedGroup, ProtocolVersion, SideData, ALL_CIPHER_SUITES,
+    ALL_KX_GROUPS,
+};
+
+use crate::stream::Stream;
+
+/// The versions, cipher suites and key exchange groups a connector or acceptor accepts, on
+/// top of those its config offers.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Allowed {
+    pub(crate) min_version: Option<ProtocolVersion>,
+    pub(crate) max_version: Option<ProtocolVersion>,
+    cipher_suites: Option<Vec<CipherSuite>>,
+    kx_groups: Option<Vec<NamedGroup>>,
+}
+
+impl Allowed {
+    pub(crate) fn set_cipher_suites(&mut self, suites: &[CipherSuite]) -> io::Result<()> {
+        let known = |suite: &CipherSuite| ALL_CIPHER_SUITES.iter().any(|s| s.suite() == *suite);
+        if let Some(suite) = suites.iter().find(|suite| !known(suite)) {
+            return Err(invalid(format!("unsupported cipher suite {suite:?}")));
+        }
+        if suites.is_empty() {
+            return Err(invalid("no cipher suite allowed".into()));
+        }
+        self.cipher_suites = Some(suites.to_vec());
+        Ok(())
+    }
+
+    pub(crate) fn set_kx_groups(&mut self, groups: &[NamedGroup]) -> io::Result<()> {
+        let known = |group: &NamedGroup| ALL_KX_GROUPS.iter().any(|g| g.name == *group);
+        if let Some(group) = groups.iter().find(|group| !known(group)) {
+            return Err(invalid(format!("unsupported key exchange group {group:?}")));
+        }
+        if groups.is_empty() {
+            return Err(invalid("no key exchange group allowed".into()));
+        }
+        self.kx_groups = Some(groups.to_vec());
+        Ok(())
+    }
+
+    fn allows_version(&self, version: ProtocolVersion) -> bool {
+        let version = version.get_u16();
+        self.min_version.is_none_or(|min| min.get_u16() <= version)
+            && self.max_version.is_none_or(|max| version <= max.get_u16())
+    }
+
+    fn allows_suite(&self, suite: CipherSuite) -> bool {
+        self.cipher_suites
+            .as_ref()
+            .is_none_or(|suites| suites.contains(&suite))
+    }
+
+    /// Fails when no allowed cipher suite is of an allowed version the config `supports`.
+    pub(crate) fn check_offered(
+        &self,
+        supports: impl Fn(ProtocolVersion) -> bool,
+    ) -> io::Result<()> {
+        let usable = ALL_CIPHER_SUITES.iter().any(|suite| {
+            let version = suite.version().version;
+            self.allows_suite(suite.suite()) && self.allows_version(version) && supports(version)
+        });
+        match usable {
+            true => Ok(()),
+            false => Err(invalid(
+                "the config supports no allowed protocol version and cipher suite".into(),
+            )),
+        }
+    }
+
+    /// Fails with a [`NotAllowed`] error when the handshake of `stream` settled on anything
+    /// not allowed.
+    pub(crate) fn check<IO, C, SD: SideData>(&self, stream: &Stream<IO, C>) -> io::Result<()>
+    where
+        C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+    {
+        let fail = |what: NotAllowed| Err(io::Error::new(io::ErrorKind::InvalidData, what));
+        if let Some(version) = stream.protocol_version() {
+            if !self.allows_version(version) {
+                return fail(NotAllowed::Version(version));
+            }
+        }
+        if let Some(suite) = stream.session().negotiated_cipher_suite() {
+            if !self.allows_suite(suite.suite()) {
+                return fail(NotAllowed::CipherSuite(suite.suite()));
+            }
+        }
+        // Without a group there was no (EC)DHE, as in a resumed TLS 1.2 session.
+        if let (Some(groups), Some(group)) = (&self.kx_groups, stream.kx_group()) {
+            if !groups.contains(&group) {
+                return fail(NotAllowed::KxGroup(group));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a handshake settled on that the connector or acceptor doesn't allow.
+#[derive(Debug)]
+pub(crate) enum NotAllowed {
+    Version(ProtocolVersion),
+    CipherSuite(CipherSuite),
+    KxGroup(NamedGroup),
+}
+
+impl fmt::Display for NotAllowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version(version) => write!(f, "negotiated {version:?}, which is not allowed"),
+            Self::CipherSuite(suite) => write!(f, "negotiated {suite:?}, which is not allowed"),
+            Self::KxGroup(group) => write!(f, "negotiated {group:?}, which is not allowed"),
+        }
+    }
+}
+
+impl std::error::Error for NotAllowed {}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
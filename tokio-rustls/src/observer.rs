@@ -0,0 +1,83 @@
+//! Handshake observer callbacks.
+use std::{fmt, sync::Arc, time::Duration};
+
+use rustls_fork_shadow_tls::{AlertDescription, Certificate, CommonState};
+
+use crate::record::{RecordScanner, HANDSHAKE_TYPE_CLIENT_HELLO};
+
+/// Callbacks on handshake progress, set with `with_handshake_observer` on a connector or
+/// acceptor.
+///
+/// Callbacks run inline on the task driving the stream, so they should return quickly. All
+/// of them default to doing nothing.
+pub trait HandshakeObserver: Send + Sync {
+    /// The ClientHello has been written to the IO.
+    fn client_hello_sent(&self) {}
+
+    /// A ClientHello has been read from the IO.
+    fn client_hello_received(&self) {}
+
+    /// The peer presented its certificate chain, end-entity first.
+    fn certificate_received(&self, _chain: &[Certificate]) {}
+
+    /// The handshake has finished successfully.
+    fn handshake_complete(&self, _state: &CommonState, _duration: Duration) {}
+
+    /// The peer sent an alert, which ends the connection.
+    fn alert_received(&self, _alert: AlertDescription) {}
+}
+
+/// An observer together with the events already reported for one stream.
+pub(crate) struct ObserverSlot {
+    observer: Arc<dyn HandshakeObserver>,
+    hello_sent: bool,
+    hello_received: bool,
+    certificate_received: bool,
+}
+
+impl fmt::Debug for ObserverSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObserverSlot").finish_non_exhaustive()
+    }
+}
+
+impl ObserverSlot {
+    pub(crate) fn new(observer: Arc<dyn HandshakeObserver>) -> Self {
+        Self {
+            observer,
+            hello_sent: false,
+            hello_received: false,
+            certificate_received: false,
+        }
+    }
+
+    pub(crate) fn after_write(&mut self, scanner: &RecordScanner) {
+        if !self.hello_sent && scanner.first_handshake_type == Some(HANDSHAKE_TYPE_CLIENT_HELLO) {
+            self.hello_sent = true;
+            self.observer.client_hello_sent();
+        }
+    }
+
+    pub(crate) fn after_read(&mut self, scanner: &RecordScanner, state: &CommonState) {
+        if !self.hello_received
+            && scanner.first_handshake_type == Some(HANDSHAKE_TYPE_CLIENT_HELLO)
+        {
+            self.hello_received = true;
+            self.observer.client_hello_received();
+        }
+        if !self.certificate_received {
+            if let Some(chain) = state.peer_certificates() {
+                self.certificate_received = true;
+                self.observer.certificate_received(chain);
+            }
+        }
+    }
+
+    pub(crate) fn alert_received(&self, alert: AlertDescription) {
+        self.observer.alert_received(alert);
+    }
+
+    pub(crate) fn handshake_complete(&self, state: &CommonState, duration: Duration) {
+        self.observer.handshake_complete(state, duration);
+    }
+}
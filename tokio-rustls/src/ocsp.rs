@@ -0,0 +1,413 @@
+//! OCSP stapling.
+use std::{
+    io,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use ring::digest;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+};
+use rustls_fork_shadow_tls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey, SigningKey},
+    Certificate, Error, PrivateKey,
+};
+
+use crate::x509::{self, TAG_ENUMERATED, TAG_INTEGER, TAG_OCTET_STRING, TAG_OID};
+
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+const OID_AD_OCSP: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+const OID_AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+#[cfg(feature = "dangerous_configuration")]
+const OID_TLS_FEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+
+/// Largest OCSP response accepted from a responder.
+const MAX_RESPONSE: u64 = 64 * 1024;
+
+/// How the client treats OCSP responses stapled by the server, set with `with_ocsp_stapling`
+/// on a connector.
+///
+/// A stapled response is always checked, whatever the policy: it must be signed by the
+/// issuer of the server's certificate or a responder the issuer delegated to, be current, and
+/// report the certificate as good.
+#[cfg(feature = "dangerous_configuration")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaplePolicy {
+    /// Servers may leave the response out.
+    Optional,
+    /// Servers must staple a response when their certificate carries the TLS feature
+    /// extension asking for it (must-staple).
+    MustStaple,
+    /// Servers must always staple a response.
+    Required,
+}
+
+#[cfg(feature = "dangerous_configuration")]
+impl StaplePolicy {
+    pub(crate) fn check(
+        self,
+        response: &[u8],
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        if response.is_empty() {
+            return match self {
+                StaplePolicy::Required => Err(Error::InvalidCertificateData(
+                    "server did not staple an OCSP response".into(),
+                )),
+                StaplePolicy::MustStaple if must_staple(end_entity) => {
+                    Err(Error::InvalidCertificateData(
+                        "must-staple certificate without an OCSP response".into(),
+                    ))
+                }
+                _ => Ok(()),
+            };
+        }
+        let issuer = intermediates.first().ok_or_else(|| {
+            Error::InvalidCertificateData("no issuer certificate to check the OCSP response".into())
+        })?;
+        check_response(response, end_entity, issuer, now)
+    }
+}
+
+/// Where a connection's stapled response is kept between the verifier and the stream.
+pub(crate) type StapleSlot = Arc<Mutex<Option<Vec<u8>>>>;
+
+/// Whether `cert` carries the TLS feature extension with `status_request`.
+#[cfg(feature = "dangerous_configuration")]
+fn must_staple(cert: &Certificate) -> bool {
+    const STATUS_REQUEST: &[u8] = &[5];
+    x509::tbs(&cert.0)
+        .and_then(|tbs| tbs.extension(OID_TLS_FEATURE))
+        .and_then(x509::sequence)
+        .is_some_and(|mut features| {
+            features.any(|(tag, _, feature)| tag == TAG_INTEGER && feature == STATUS_REQUEST)
+        })
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidCertificateData(format!("invalid OCSP response: {reason}"))
+}
+
+/// Check that `response` is a current, properly signed OCSP response reporting `cert`,
+/// issued by `issuer`, as good.
+pub(crate) fn check_response(
+    response: &[u8],
+    cert: &Certificate,
+    issuer: &Certificate,
+    now: SystemTime,
+) -> Result<(), Error> {
+    let malformed = || invalid("malformed");
+    let mut fields = x509::sequence(response).ok_or_else(malformed)?;
+    match fields.next() {
+        Some((TAG_ENUMERATED, _, [0])) => {}
+        _ => return Err(invalid("responder did not answer successfully")),
+    }
+    // responseBytes [0] EXPLICIT SEQUENCE { responseType, response OCTET STRING }
+    let (_, _, bytes) = fields.next().ok_or_else(malformed)?;
+    let mut bytes = x509::sequence(bytes).ok_or_else(malformed)?;
+    let (TAG_OID, _, OID_OCSP_BASIC) = bytes.next().ok_or_else(malformed)? else {
+        return Err(invalid("not a basic OCSP response"));
+    };
+    let (TAG_OCTET_STRING, _, basic) = bytes.next().ok_or_else(malformed)? else {
+        return Err(malformed());
+    };
+
+    let signed = x509::signed(basic).ok_or_else(malformed)?;
+    if !signed_by(&signed, &issuer.0) {
+        // A delegated responder, whose certificate the issuer signed for this purpose.
+        let delegated = signed
+            .rest
+            .clone()
+            .next()
+            .and_then(|(_, _, certs)| x509::sequence(certs))
+            .into_iter()
+            .flatten()
+            .any(|(_, responder, _)| {
+                delegated_by(responder, &issuer.0) && signed_by(&signed, responder)
+            });
+        if !delegated {
+            return Err(invalid("not signed by the issuer"));
+        }
+    }
+
+    let cert = x509::tbs(&cert.0).ok_or_else(malformed)?;
+    let issuer = x509::tbs(&issuer.0).ok_or_else(malformed)?;
+    let issuer_key = x509::public_key(issuer.spki).ok_or_else(malformed)?;
+    // ResponseData { [0] version OPTIONAL, responderID, producedAt, responses, ... }
+    let responses = x509::sequence(signed.data)
+        .ok_or_else(malformed)?
+        .find(|(tag, _, _)| *tag == x509::TAG_SEQUENCE)
+        .and_then(|(_, responses, _)| x509::sequence(responses))
+        .ok_or_else(malformed)?;
+    for (_, single, _) in responses {
+        let mut fields = x509::sequence(single).ok_or_else(malformed)?;
+        let (_, cert_id, _) = fields.next().ok_or_else(malformed)?;
+        if !matches_cert_id(cert_id, cert.serial, issuer.subject, issuer_key) {
+            continue;
+        }
+        match fields.next() {
+            Some((0x80, _, _)) => {}
+            Some((0xa1, _, _)) => {
+                return Err(Error::InvalidCertificateData("certificate revoked".into()))
+            }
+            _ => return Err(invalid("certificate status unknown")),
+        }
+        let this_update = match fields.next() {
+            Some((tag, _, time)) => x509::time(tag, time).ok_or_else(malformed)?,
+            None => return Err(malformed()),
+        };
+        let next_update = match fields.next() {
+            Some((0xa0, _, time)) => {
+                let (tag, _, time, _) = x509::read_tlv(time).ok_or_else(malformed)?;
+                Some(x509::time(tag, time).ok_or_else(malformed)?)
+            }
+            _ => None,
+        };
+        if now < this_update || next_update.is_some_and(|next_update| next_update < now) {
+            return Err(invalid("not current"));
+        }
+        return Ok(());
+    }
+    Err(invalid("no status for the certificate"))
+}
+
+fn matches_cert_id(cert_id: &[u8], serial: &[u8], issuer_name: &[u8], issuer_key: &[u8]) -> bool {
+    let Some(mut fields) = x509::sequence(cert_id) else {
+        return false;
+    };
+    let algorithm = match fields
+        .next()
+        .and_then(|(_, algorithm, _)| x509::sequence(algorithm))
+    {
+        Some(mut algorithm) => match algorithm.next() {
+            Some((TAG_OID, _, OID_SHA1)) => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            Some((TAG_OID, _, OID_SHA256)) => &digest::SHA256,
+            _ => return false,
+        },
+        None => return false,
+    };
+    let hashes_match = |field: Option<(u8, &[u8], &[u8])>, data: &[u8]| match field {
+        Some((TAG_OCTET_STRING, _, hash)) => hash == digest::digest(algorithm, data).as_ref(),
+        _ => false,
+    };
+    hashes_match(fields.next(), issuer_name)
+        && hashes_match(fields.next(), issuer_key)
+        && matches!(fields.next(), Some((TAG_INTEGER, _, id)) if id == serial)
+}
+
+/// Whether the certificate `signer` made the signature of `signed`.
+fn signed_by(signed: &x509::Signed<'_>, signer: &[u8]) -> bool {
+    const RSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+    const RSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+    const RSA_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+    const ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    const ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+    const ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+    let algorithms: &[&webpki::SignatureAlgorithm] = match signed.algorithm {
+        RSA_SHA256 => &[&webpki::RSA_PKCS1_2048_8192_SHA256],
+        RSA_SHA384 => &[&webpki::RSA_PKCS1_2048_8192_SHA384],
+        RSA_SHA512 => &[&webpki::RSA_PKCS1_2048_8192_SHA512],
+        ECDSA_SHA256 => &[&webpki::ECDSA_P256_SHA256, &webpki::ECDSA_P384_SHA256],
+        ECDSA_SHA384 => &[&webpki::ECDSA_P256_SHA384, &webpki::ECDSA_P384_SHA384],
+        ED25519 => &[&webpki::ED25519],
+        _ => return false,
+    };
+    let Ok(signer) = webpki::EndEntityCert::try_from(signer) else {
+        return false;
+    };
+    algorithms.iter().any(|algorithm| {
+        signer
+            .verify_signature(algorithm, signed.data, signed.signature)
+            .is_ok()
+    })
+}
+
+/// Whether `responder` is an OCSP signing certificate issued by `issuer`.
+fn delegated_by(responder: &[u8], issuer: &[u8]) -> bool {
+    let (Some(signed), Some(tbs)) = (x509::signed(responder), x509::tbs(responder)) else {
+        return false;
+    };
+    let ocsp_signing = tbs
+        .extension(OID_EXTENDED_KEY_USAGE)
+        .and_then(x509::sequence)
+        .is_some_and(|mut usages| usages.any(|(_, _, usage)| usage == OID_OCSP_SIGNING));
+    ocsp_signing && signed_by(&signed, issuer)
+}
+
+/// An OCSPRequest for `cert`, issued by `issuer`.
+fn request(cert: &[u8], issuer: &[u8]) -> Option<Vec<u8>> {
+    let cert = x509::tbs(cert)?;
+    let issuer = x509::tbs(issuer)?;
+    let sha1 = |data: &[u8]| digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, data);
+    let algorithm = [x509::der(TAG_OID, OID_SHA1), x509::der(x509::TAG_NULL, &[])].concat();
+    let cert_id = [
+        x509::der(x509::TAG_SEQUENCE, &algorithm),
+        x509::der(TAG_OCTET_STRING, sha1(issuer.subject).as_ref()),
+        x509::der(
+            TAG_OCTET_STRING,
+            sha1(x509::public_key(issuer.spki)?).as_ref(),
+        ),
+        x509::der(TAG_INTEGER, cert.serial),
+    ]
+    .concat();
+    // OCSPRequest { TBSRequest { requestList { Request { reqCert } } } }
+    let request = x509::der(x509::TAG_SEQUENCE, &x509::der(x509::TAG_SEQUENCE, &cert_id));
+    let request_list = x509::der(x509::TAG_SEQUENCE, &request);
+    let tbs_request = x509::der(x509::TAG_SEQUENCE, &request_list);
+    Some(x509::der(x509::TAG_SEQUENCE, &tbs_request))
+}
+
+/// The OCSP responder URL in the authority information access extension of `cert`.
+fn responder_url(cert: &[u8]) -> Option<String> {
+    const URI: u8 = 0x86;
+    let tbs = x509::tbs(cert)?;
+    x509::sequence(tbs.extension(OID_AUTHORITY_INFO_ACCESS)?)?.find_map(|(_, access, _)| {
+        let mut fields = x509::sequence(access)?;
+        let (TAG_OID, _, OID_AD_OCSP) = fields.next()? else {
+            return None;
+        };
+        let (URI, _, url) = fields.next()? else {
+            return None;
+        };
+        String::from_utf8(url.to_vec()).ok()
+    })
+}
+
+/// Ask the OCSP responder named in `cert` for the status of `cert`, issued by `issuer`.
+///
+/// Only plain `http://` responders are supported, as is the norm for OCSP. The response is
+/// returned as is; [`StapledCert::spawn_refresh`] checks it before stapling.
+pub async fn fetch_ocsp_response(cert: &Certificate, issuer: &Certificate) -> io::Result<Vec<u8>> {
+    let invalid_input = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let body = request(&cert.0, &issuer.0).ok_or_else(|| invalid_input("malformed certificate"))?;
+    let url = responder_url(&cert.0).ok_or_else(|| invalid_input("no OCSP responder"))?;
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid_input("OCSP responder is not http"))?;
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = if path.is_empty() { "/" } else { path };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            let port = port
+                .parse()
+                .map_err(|_| invalid_input("invalid OCSP responder port"))?;
+            (host, port)
+        }
+        _ => (authority, 80),
+    };
+
+    let mut stream = TcpStream::connect((host.trim_matches(['[', ']']), port)).await?;
+    let head = format!(
+        "POST {path} HTTP/1.0\r\nHost: {authority}\r\nContent-Type: application/ocsp-request\r\n\
+         Content-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    let mut response = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE)
+        .read_to_end(&mut response)
+        .await?;
+
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))?;
+    let status = response.split(|&b| b == b' ').nth(1);
+    if status != Some(b"200") {
+        return Err(io::Error::other("OCSP responder returned an HTTP error"));
+    }
+    Ok(response.split_off(split + 4))
+}
+
+/// A server certificate whose stapled OCSP response can be replaced while connections are
+/// accepted, set with `with_stapled_cert` on an acceptor.
+#[derive(Clone)]
+pub struct StapledCert {
+    key: Arc<RwLock<Arc<CertifiedKey>>>,
+}
+
+impl StapledCert {
+    /// `chain` starts with the end-entity certificate, followed by its issuer.
+    pub fn new(chain: Vec<Certificate>, key: &PrivateKey) -> Result<Self, Error> {
+        let key = sign::any_supported_type(key)
+            .map_err(|_| Error::General("invalid private key".into()))?;
+        Ok(Self::from_signing_key(chain, key))
+    }
+
+    /// Like [`new`](Self::new), with handshake signatures made by `key`, which may keep the
+    /// private key elsewhere, such as in an HSM.
+    pub fn from_signing_key(chain: Vec<Certificate>, key: Arc<dyn SigningKey>) -> Self {
+        Self {
+            key: Arc::new(RwLock::new(Arc::new(CertifiedKey::new(chain, key)))),
+        }
+    }
+
+    /// The response currently stapled.
+    pub fn ocsp_response(&self) -> Option<Vec<u8>> {
+        self.key.read().unwrap().ocsp.clone()
+    }
+
+    /// Staple `response` to new handshakes, or stop stapling with `None`.
+    pub fn set_ocsp_response(&self, response: Option<Vec<u8>>) {
+        let mut key = self.key.write().unwrap();
+        let mut updated = CertifiedKey::clone(&key);
+        updated.ocsp = response;
+        *key = Arc::new(updated);
+    }
+
+    /// Fetch a response from the certificate's OCSP responder now and then every
+    /// `interval`, stapling each one which checks out.
+    ///
+    /// Failed fetches keep the previous response, and are retried after a tenth of
+    /// `interval`. The chain must include the issuer. Abort the returned task to stop.
+    pub fn spawn_refresh(&self, interval: Duration) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = this.refresh().await;
+                #[cfg(feature = "tracing")]
+                if let Err(err) = &result {
+                    tracing::warn!(%err, "ocsp refresh failed");
+                }
+                let delay = match result {
+                    Ok(()) => interval,
+                    Err(_) => interval / 10,
+                };
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+
+    async fn refresh(&self) -> io::Result<()> {
+        let chain = self.key.read().unwrap().cert.clone();
+        let [cert, issuer, ..] = &chain[..] else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "certificate chain has no issuer",
+            ));
+        };
+        let response = fetch_ocsp_response(cert, issuer).await?;
+        check_response(&response, cert, issuer, SystemTime::now())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.set_ocsp_response(Some(response));
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for StapledCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
@@ -0,0 +1,278 @@
+//! pcap-ng captures of the records of streams, with their secrets, for Wireshark.
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rustls_fork_shadow_tls::KeyLog;
+
+use crate::{
+    record::HEADER_LEN,
+    tap::{Direction, Record, RecordTap},
+};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const DECRYPTION_SECRETS_BLOCK: u32 = 0x0A;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Packets start with their IPv4 header.
+const LINKTYPE_RAW: u16 = 101;
+/// Secrets in the NSS key log format.
+const SECRETS_TYPE_TLS: u32 = 0x544C_534B;
+
+/// The made up addresses of the packets: streams get a client port each.
+const CLIENT_ADDR: [u8; 4] = [10, 0, 0, 1];
+const SERVER_ADDR: [u8; 4] = [10, 0, 0, 2];
+const SERVER_PORT: u16 = 443;
+const FIRST_CLIENT_PORT: u16 = 49152;
+
+const IPV4_HEADER_LEN: usize = 20;
+const TCP_HEADER_LEN: usize = 20;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// A pcap-ng file the ciphertext of streams is written to, with the secrets to decrypt it,
+/// set with `TlsConnector::with_pcap` and `TlsAcceptor::with_pcap`. Opening the file in
+/// Wireshark shows the decrypted streams without a separate key log.
+///
+/// The IO under a stream is not necessarily TCP, so each stream is written as a made up TCP
+/// connection between 10.0.0.1 and 10.0.0.2:443, with a client port of its own, carrying one
+/// packet per record, timestamped when the record went through. The records are those on
+/// the wire, so streams are shown as the peer saw them, fragmentation included.
+///
+/// This is a debugging aid: the secrets in the file decrypt all the traffic in it, and
+/// records are written to the file from the tasks driving the streams, blocking them on the
+/// disk.
+#[derive(Clone)]
+pub struct PcapCapture {
+    inner: Arc<Capture>,
+}
+
+struct Capture {
+    sink: Mutex<Box<dyn Write + Send>>,
+    flows: AtomicU16,
+}
+
+impl fmt::Debug for PcapCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PcapCapture")
+            .field("flows", &self.inner.flows.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl PcapCapture {
+    /// Write to a new file at `path`, truncating it if it exists.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Write to `sink`, which is flushed after every packet.
+    pub fn new(sink: impl Write + Send + 'static) -> io::Result<Self> {
+        let mut sink: Box<dyn Write + Send> = Box::new(sink);
+        let mut section = Vec::with_capacity(16);
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        // The length of the section is not known up front.
+        section.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut sink, SECTION_HEADER_BLOCK, &section)?;
+        let mut interface = Vec::with_capacity(8);
+        interface.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        // No snapshot length limit.
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut sink, INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+        sink.flush()?;
+        Ok(Self {
+            inner: Arc::new(Capture {
+                sink: Mutex::new(sink),
+                flows: AtomicU16::new(0),
+            }),
+        })
+    }
+
+    /// The tap writing the records of a new stream, on the client side or not.
+    pub(crate) fn flow(&self, client: bool) -> PcapFlow {
+        let n = self.inner.flows.fetch_add(1, Ordering::Relaxed);
+        PcapFlow {
+            capture: self.clone(),
+            client_port: FIRST_CLIENT_PORT.wrapping_add(n % (u16::MAX - FIRST_CLIENT_PORT)),
+            client,
+            seqs: Mutex::new(None),
+        }
+    }
+
+    /// Write a block. A capture is best effort: failures are dropped, so they don't fail the
+    /// streams.
+    fn write(&self, block_type: u32, body: &[u8]) {
+        let mut sink = self.inner.sink.lock().unwrap();
+        let res = write_block(&mut *sink, block_type, body).and_then(|()| sink.flush());
+        if let Err(_e) = res {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(%_e, "writing to the pcap capture failed");
+        }
+    }
+
+    /// Write a packet stamped with the current time.
+    fn write_packet(&self, packet: &[u8]) {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut body = Vec::with_capacity(20 + packet.len());
+        // On the interface described first.
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(micros as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(packet);
+        self.write(ENHANCED_PACKET_BLOCK, &body);
+    }
+}
+
+impl KeyLog for PcapCapture {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let mut line =
+            String::with_capacity(label.len() + 2 * (client_random.len() + secret.len()) + 3);
+        line.push_str(label);
+        line.push(' ');
+        push_hex(&mut line, client_random);
+        line.push(' ');
+        push_hex(&mut line, secret);
+        line.push('\n');
+        let mut body = Vec::with_capacity(8 + line.len());
+        body.extend_from_slice(&SECRETS_TYPE_TLS.to_le_bytes());
+        body.extend_from_slice(&(line.len() as u32).to_le_bytes());
+        body.extend_from_slice(line.as_bytes());
+        self.write(DECRYPTION_SECRETS_BLOCK, &body);
+    }
+}
+
+/// Writes the records of one stream as the packets of a TCP connection.
+pub(crate) struct PcapFlow {
+    capture: PcapCapture,
+    client_port: u16,
+    /// Whether the stream is the client, which sends the outbound records.
+    client: bool,
+    /// The next sequence numbers of the client and of the server, once the connection has
+    /// been opened.
+    seqs: Mutex<Option<(u32, u32)>>,
+}
+
+impl PcapFlow {
+    fn packet(&self, from_client: bool, seq: u32, ack: u32, flags: u8, payload: &[&[u8]]) {
+        let (src, dst, src_port, dst_port) = match from_client {
+            true => (CLIENT_ADDR, SERVER_ADDR, self.client_port, SERVER_PORT),
+            false => (SERVER_ADDR, CLIENT_ADDR, SERVER_PORT, self.client_port),
+        };
+        let payload_len: usize = payload.iter().map(|p| p.len()).sum();
+        let tcp_len = TCP_HEADER_LEN + payload_len;
+        let mut packet = Vec::with_capacity(IPV4_HEADER_LEN + tcp_len);
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&((IPV4_HEADER_LEN + tcp_len) as u16).to_be_bytes());
+        // No id, don't fragment, a TTL of 64 and TCP.
+        packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        let checksum = checksum(&[&packet]);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        packet.extend_from_slice(&src_port.to_be_bytes());
+        packet.extend_from_slice(&dst_port.to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&ack.to_be_bytes());
+        packet.extend_from_slice(&[(TCP_HEADER_LEN as u8 / 4) << 4, flags, 0xff, 0xff]);
+        packet.extend_from_slice(&[0; 4]);
+        for part in payload {
+            packet.extend_from_slice(part);
+        }
+        let mut pseudo_header = [0; 12];
+        pseudo_header[..4].copy_from_slice(&src);
+        pseudo_header[4..8].copy_from_slice(&dst);
+        pseudo_header[9] = 6;
+        pseudo_header[10..].copy_from_slice(&(tcp_len as u16).to_be_bytes());
+        let checksum = checksum(&[&pseudo_header, &packet[IPV4_HEADER_LEN..]]);
+        packet[IPV4_HEADER_LEN + 16..IPV4_HEADER_LEN + 18].copy_from_slice(&checksum.to_be_bytes());
+        self.capture.write_packet(&packet);
+    }
+}
+
+impl RecordTap for PcapFlow {
+    fn wants_ciphertext(&self) -> bool {
+        true
+    }
+
+    fn record(&self, record: &Record<'_>) {
+        let mut seqs = self.seqs.lock().unwrap();
+        let (client_seq, server_seq) = seqs.get_or_insert_with(|| {
+            // Open the connection, so Wireshark follows it from the start.
+            self.packet(true, 0, 0, TCP_SYN, &[]);
+            self.packet(false, 0, 1, TCP_SYN | TCP_ACK, &[]);
+            self.packet(true, 1, 1, TCP_ACK, &[]);
+            (1, 1)
+        });
+        let mut header = [0; HEADER_LEN];
+        header[0] = record.content_type.get_u8();
+        header[1..3].copy_from_slice(&record.version.get_u16().to_be_bytes());
+        header[3..].copy_from_slice(&record.length.to_be_bytes());
+        let payload = record.ciphertext.unwrap_or_default();
+        let len = (HEADER_LEN + payload.len()) as u32;
+        let from_client = self.client == (record.direction == Direction::Outbound);
+        let flags = TCP_PSH | TCP_ACK;
+        if from_client {
+            self.packet(true, *client_seq, *server_seq, flags, &[&header, payload]);
+            *client_seq = client_seq.wrapping_add(len);
+        } else {
+            self.packet(false, *server_seq, *client_seq, flags, &[&header, payload]);
+            *server_seq = server_seq.wrapping_add(len);
+        }
+    }
+}
+
+/// Write a block of `block_type` around `body`, padded to 4 bytes.
+fn write_block(sink: &mut dyn Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total = (12 + body.len() + padding) as u32;
+    sink.write_all(&block_type.to_le_bytes())?;
+    sink.write_all(&total.to_le_bytes())?;
+    sink.write_all(body)?;
+    sink.write_all(&[0; 3][..padding])?;
+    sink.write_all(&total.to_le_bytes())
+}
+
+/// The internet checksum of `parts`, of which only the last may have an odd length.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        let mut words = part.chunks_exact(2);
+        for word in &mut words {
+            sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+        }
+        if let [last] = words.remainder() {
+            sum += (*last as u32) << 8;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for b in bytes {
+        out.push(DIGITS[(b >> 4) as usize] as char);
+        out.push(DIGITS[(b & 0xf) as usize] as char);
+    }
+}
@@ -0,0 +1,103 @@
+//! Public key pinning.
+use std::{collections::HashSet, fmt, io, sync::Arc};
+
+use rustls_fork_shadow_tls::{Certificate, Error, ServerName};
+
+use crate::{base64, x509};
+
+type Report = Arc<dyn Fn(&PinFailure) + Send + Sync>;
+
+/// SHA-256 hashes of DER SubjectPublicKeyInfos, one of which the server's chain must contain,
+/// set with `with_pins` on a connector.
+///
+/// Pins are matched against every certificate the server presents, so pinning an
+/// intermediate's key keeps working across leaf renewals. An empty set rejects every server.
+#[derive(Clone, Default)]
+pub struct PinSet {
+    pins: HashSet<[u8; 32]>,
+    report: Option<Report>,
+}
+
+impl fmt::Debug for PinSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PinSet")
+            .field("pins", &self.pins.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PinSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the SHA-256 hash of a DER SubjectPublicKeyInfo.
+    pub fn with_sha256(mut self, hash: [u8; 32]) -> Self {
+        self.pins.insert(hash);
+        self
+    }
+
+    /// Add a pin in the `sha256/<base64>` form used by HPKP and mobile TLS stacks. The hash
+    /// must be in standard, padded base64: a pin in any other form is an error rather than
+    /// read leniently.
+    pub fn with_pin(self, pin: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid sha256 pin");
+        let encoded = pin.strip_prefix("sha256/").ok_or_else(invalid)?;
+        let hash = base64::decode(encoded)
+            .and_then(|hash| hash.try_into().ok())
+            .ok_or_else(invalid)?;
+        Ok(self.with_sha256(hash))
+    }
+
+    /// Add the public key of `cert`.
+    pub fn with_certificate(self, cert: &Certificate) -> io::Result<Self> {
+        let hash = Self::spki_sha256(cert).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "malformed certificate")
+        })?;
+        Ok(self.with_sha256(hash))
+    }
+
+    /// Call `report` whenever a server's chain matches no pin, before the connection fails.
+    pub fn with_failure_report(
+        mut self,
+        report: impl Fn(&PinFailure) + Send + Sync + 'static,
+    ) -> Self {
+        self.report = Some(Arc::new(report));
+        self
+    }
+
+    /// SHA-256 hash of the SubjectPublicKeyInfo of `cert`, the value pins are made of.
+    pub fn spki_sha256(cert: &Certificate) -> Option<[u8; 32]> {
+        let spki = x509::spki(&cert.0)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, spki);
+        digest.as_ref().try_into().ok()
+    }
+
+    pub(crate) fn check(
+        &self,
+        server_name: &ServerName,
+        chain: &[Certificate],
+    ) -> Result<(), Error> {
+        let presented: Vec<[u8; 32]> = chain.iter().filter_map(Self::spki_sha256).collect();
+        if presented.iter().any(|hash| self.pins.contains(hash)) {
+            return Ok(());
+        }
+        if let Some(report) = &self.report {
+            report(&PinFailure {
+                server_name: server_name.clone(),
+                presented,
+            });
+        }
+        Err(Error::InvalidCertificateData(
+            "no certificate in the chain matches a pinned key".into(),
+        ))
+    }
+}
+
+/// A server chain which matched no pin.
+#[derive(Debug, Clone)]
+pub struct PinFailure {
+    pub server_name: ServerName,
+    /// Key hashes of the presented chain, end-entity first.
+    pub presented: Vec<[u8; 32]>,
+}
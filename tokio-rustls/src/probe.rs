@@ -0,0 +1,48 @@
+//! Handshake-only connections for monitoring.
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use rustls_fork_shadow_tls::{Certificate, CipherSuite, NamedGroup, ProtocolVersion};
+
+use crate::{client::TlsStream, stream::HandshakeSummary};
+
+/// What a server presented and negotiated in a handshake, see
+/// [`TlsConnector::probe`](crate::TlsConnector::probe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// The server's certificate chain, end-entity first; for a resumed session, the one it
+    /// presented originally.
+    pub chain: Vec<Certificate>,
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    pub kx_group: Option<NamedGroup>,
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The stapled OCSP response, when the connector checks staples.
+    pub ocsp_response: Option<Vec<u8>>,
+    pub handshake: HandshakeSummary,
+}
+
+impl ProbeReport {
+    /// Capture the report of `stream` and close it with a close_notify. Failing to close is
+    /// ignored, the server has answered by then.
+    pub(crate) async fn capture<IO>(mut stream: TlsStream<IO>) -> Self
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let session = stream.session();
+        let report = Self {
+            chain: session
+                .peer_certificates()
+                .map(<[Certificate]>::to_vec)
+                .unwrap_or_default(),
+            protocol_version: session.protocol_version(),
+            cipher_suite: session.negotiated_cipher_suite().map(|s| s.suite()),
+            kx_group: stream.kx_group(),
+            alpn_protocol: session.alpn_protocol().map(<[u8]>::to_vec),
+            ocsp_response: stream.ocsp_response().map(<[u8]>::to_vec),
+            handshake: stream
+                .handshake_summary()
+                .expect("handshake finished before probing"),
+        };
+        let _ = stream.shutdown().await;
+        report
+    }
+}
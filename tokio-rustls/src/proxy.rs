@@ -0,0 +1,254 @@
+//! Tunnel through an HTTP CONNECT or SOCKS5 proxy before the TLS handshake.
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use rustls_fork_shadow_tls::ServerName;
+
+use crate::{base64, client::TlsStream, dial, TlsConnector, TlsError};
+
+/// Max length of the HTTP CONNECT response header we are willing to read.
+const MAX_HTTP_RESPONSE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    Http,
+    Socks5,
+}
+
+/// A proxy server used by [`ProxiedConnector`].
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    kind: ProxyKind,
+    addr: String,
+    auth: Option<(String, String)>,
+}
+
+impl Proxy {
+    /// An HTTP proxy at `addr` (`host:port`), tunneling with `CONNECT`.
+    pub fn http(addr: impl Into<String>) -> Self {
+        Self {
+            kind: ProxyKind::Http,
+            addr: addr.into(),
+            auth: None,
+        }
+    }
+
+    /// A SOCKS5 proxy at `addr` (`host:port`).
+    pub fn socks5(addr: impl Into<String>) -> Self {
+        Self {
+            kind: ProxyKind::Socks5,
+            addr: addr.into(),
+            auth: None,
+        }
+    }
+
+    /// Authenticate to the proxy with username and password.
+    ///
+    /// HTTP proxies receive it as `Proxy-Authorization: Basic`, SOCKS5 proxies through the
+    /// username/password method (RFC 1929).
+    pub fn with_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Ask the proxy on `io` to open a tunnel to `host:port`.
+    pub async fn tunnel<IO>(&self, io: &mut IO, host: &str, port: u16) -> io::Result<()>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.kind {
+            ProxyKind::Http => http_connect(io, host, port, self.auth.as_ref()).await,
+            ProxyKind::Socks5 => socks5_connect(io, host, port, self.auth.as_ref()).await,
+        }
+    }
+}
+
+/// A [`TlsConnector`] which reaches the server through a [`Proxy`].
+#[derive(Clone)]
+pub struct ProxiedConnector {
+    connector: TlsConnector,
+    proxy: Proxy,
+}
+
+impl ProxiedConnector {
+    pub fn new(connector: TlsConnector, proxy: Proxy) -> Self {
+        Self { connector, proxy }
+    }
+
+    /// Dial the proxy, open a tunnel to `addr` (`host:port`) and perform the handshake over it.
+    ///
+    /// The host part is used as the server name.
+    pub async fn connect(&self, addr: &str) -> Result<TlsStream<TcpStream>, TlsError> {
+        let (host, port) = dial::split_host_port(addr)?;
+        let domain = ServerName::try_from(host)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let (proxy_host, proxy_port) = dial::split_host_port(&self.proxy.addr)?;
+        let mut stream = dial::connect_tcp(proxy_host, proxy_port).await?;
+        self.proxy.tunnel(&mut stream, host, port).await?;
+        self.connector.connect(domain, stream).await
+    }
+}
+
+async fn http_connect<IO>(
+    io: &mut IO,
+    host: &str,
+    port: u16,
+    auth: Option<&(String, String)>,
+) -> io::Result<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let authority = match host.parse::<Ipv6Addr>() {
+        Ok(_) => format!("[{host}]:{port}"),
+        Err(_) => format!("{host}:{port}"),
+    };
+    let mut req = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some((user, pass)) = auth {
+        let credential = base64::encode(format!("{user}:{pass}").as_bytes());
+        req.push_str(&format!("Proxy-Authorization: Basic {credential}\r\n"));
+    }
+    req.push_str("\r\n");
+    io.write_all(req.as_bytes()).await?;
+    io.flush().await?;
+
+    // Read byte by byte so nothing after the header is consumed.
+    let mut resp = Vec::with_capacity(128);
+    while !resp.ends_with(b"\r\n\r\n") {
+        if resp.len() >= MAX_HTTP_RESPONSE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proxy response too large",
+            ));
+        }
+        resp.push(io.read_u8().await?);
+    }
+
+    let status = resp
+        .split(|&b| b == b'\r')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(code) => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy CONNECT failed with status {code}"),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid proxy response",
+        )),
+    }
+}
+
+async fn socks5_connect<IO>(
+    io: &mut IO,
+    host: &str,
+    port: u16,
+    auth: Option<&(String, String)>,
+) -> io::Result<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let invalid = |msg: &'static str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    // greeting
+    match auth {
+        Some(_) => io.write_all(&[0x05, 0x02, 0x00, 0x02]).await?,
+        None => io.write_all(&[0x05, 0x01, 0x00]).await?,
+    }
+    io.flush().await?;
+    let mut reply = [0; 2];
+    io.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 {
+        return Err(invalid("invalid socks5 version"));
+    }
+    match (reply[1], auth) {
+        (0x00, _) => (),
+        (0x02, Some((user, pass))) => {
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "socks5 credential too long",
+                ));
+            }
+            let mut req = Vec::with_capacity(3 + user.len() + pass.len());
+            req.push(0x01);
+            req.push(user.len() as u8);
+            req.extend_from_slice(user.as_bytes());
+            req.push(pass.len() as u8);
+            req.extend_from_slice(pass.as_bytes());
+            io.write_all(&req).await?;
+            io.flush().await?;
+            io.read_exact(&mut reply).await?;
+            if reply[1] != 0x00 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "socks5 authentication failed",
+                ));
+            }
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "no acceptable socks5 authentication method",
+            ))
+        }
+    }
+
+    // connect request
+    let mut req = vec![0x05, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(0x01);
+            req.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            req.push(0x04);
+            req.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "socks5 host name too long",
+                ));
+            }
+            req.push(0x03);
+            req.push(host.len() as u8);
+            req.extend_from_slice(host.as_bytes());
+        }
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    io.write_all(&req).await?;
+    io.flush().await?;
+
+    let mut head = [0; 4];
+    io.read_exact(&mut head).await?;
+    if head[0] != 0x05 {
+        return Err(invalid("invalid socks5 version"));
+    }
+    if head[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("socks5 connect failed with reply {}", head[1]),
+        ));
+    }
+    // skip the bound address and port
+    let addr_len = match head[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => io.read_u8().await? as usize,
+        _ => return Err(invalid("invalid socks5 address type")),
+    };
+    let mut bound = [0; 255 + 2];
+    io.read_exact(&mut bound[..addr_len + 2]).await?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! PROXY protocol v1/v2 header parsing for the acceptor.
+//! See https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Max length of a v1 header including the CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Original connection addresses carried by a PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// Address of the client which connected to the proxy.
+    pub source: SocketAddr,
+    /// Address the client connected to on the proxy.
+    pub destination: SocketAddr,
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read a PROXY protocol header from `io` without consuming anything after it.
+///
+/// Returns `None` for headers which carry no address, like v1 `UNKNOWN` or v2 `LOCAL`.
+pub(crate) async fn read_header<IO: AsyncRead + Unpin>(
+    io: &mut IO,
+) -> io::Result<Option<ProxyHeader>> {
+    // Both versions are at least 12 bytes long.
+    let mut prefix = [0; 12];
+    io.read_exact(&mut prefix).await?;
+    if prefix == V2_SIGNATURE {
+        return read_v2(io).await;
+    }
+    if !prefix.starts_with(b"PROXY ") {
+        return Err(invalid("missing proxy protocol header"));
+    }
+
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("proxy protocol v1 header too long"));
+        }
+        line.push(io.read_u8().await?);
+    }
+    parse_v1(&line[..line.len() - 2])
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("invalid proxy protocol v1 header"))?;
+    let mut parts = line.split(' ').skip(1);
+    let v4 = match parts.next() {
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        // Anything may follow UNKNOWN, up to the CRLF.
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unsupported proxy protocol v1 family")),
+    };
+    let mut next = || parts.next().ok_or_else(|| invalid("truncated proxy protocol v1 header"));
+    let parse_ip = |s: &str| match s.parse::<IpAddr>() {
+        Ok(ip) if ip.is_ipv4() == v4 => Ok(ip),
+        _ => Err(invalid("invalid proxy address")),
+    };
+    let parse_port = |s: &str| s.parse::<u16>().map_err(|_| invalid("invalid proxy port"));
+    let src_ip = parse_ip(next()?)?;
+    let dst_ip = parse_ip(next()?)?;
+    let src_port = parse_port(next()?)?;
+    let dst_port = parse_port(next()?)?;
+    if parts.next().is_some() {
+        return Err(invalid("trailing data in proxy protocol v1 header"));
+    }
+    Ok(Some(ProxyHeader {
+        source: SocketAddr::new(src_ip, src_port),
+        destination: SocketAddr::new(dst_ip, dst_port),
+    }))
+}
+
+async fn read_v2<IO: AsyncRead + Unpin>(io: &mut IO) -> io::Result<Option<ProxyHeader>> {
+    let mut head = [0; 4];
+    io.read_exact(&mut head).await?;
+    let (ver_cmd, family) = (head[0], head[1]);
+    let len = u16::from_be_bytes([head[2], head[3]]) as usize;
+    let mut payload = vec![0; len];
+    io.read_exact(&mut payload).await?;
+
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported proxy protocol version"));
+    }
+    match ver_cmd & 0x0f {
+        0x00 => return Ok(None),
+        0x01 => (),
+        _ => return Err(invalid("unsupported proxy protocol command")),
+    }
+
+    let port = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]);
+    match family >> 4 {
+        // AF_INET
+        0x1 if len >= 12 => {
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&payload[0..4]).unwrap());
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&payload[4..8]).unwrap());
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src.into(), port(&payload[8..10])),
+                destination: SocketAddr::new(dst.into(), port(&payload[10..12])),
+            }))
+        }
+        // AF_INET6
+        0x2 if len >= 36 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[0..16]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[16..32]).unwrap());
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src.into(), port(&payload[32..34])),
+                destination: SocketAddr::new(dst.into(), port(&payload[34..36])),
+            }))
+        }
+        // AF_UNSPEC and AF_UNIX carry no socket address
+        0x0 | 0x3 => Ok(None),
+        _ => Err(invalid("invalid proxy protocol v2 address")),
+    }
+}
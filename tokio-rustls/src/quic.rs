@@ -0,0 +1,73 @@
+//! Adapter running the TLS stream over a QUIC bidirectional stream.
+//!
+//! `QuinnIo` joins the two halves of a quinn bidirectional stream into the
+//! `AsyncRead`/`AsyncWrite` pair `Stream` expects, so TLS-framed protocols can be tunneled
+//! inside QUIC streams with the same connector and acceptor.
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use quinn::{Connection, RecvStream, SendStream};
+
+/// A quinn bidirectional stream as one IO. Shutting it down finishes the sending half; the
+/// receiving half is stopped when the adapter is dropped.
+#[derive(Debug)]
+pub struct QuinnIo {
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuinnIo {
+    pub fn new(send: SendStream, recv: RecvStream) -> Self {
+        Self { send, recv }
+    }
+
+    /// Open a new bidirectional stream on `connection`. The peer only sees it once something
+    /// is written, which the TLS client does first.
+    pub async fn open(connection: &Connection) -> io::Result<Self> {
+        let (send, recv) = connection.open_bi().await?;
+        Ok(Self::new(send, recv))
+    }
+
+    /// Accept the next bidirectional stream the peer opens on `connection`.
+    pub async fn accept(connection: &Connection) -> io::Result<Self> {
+        let (send, recv) = connection.accept_bi().await?;
+        Ok(Self::new(send, recv))
+    }
+
+    pub fn into_inner(self) -> (SendStream, RecvStream) {
+        (self.send, self.recv)
+    }
+}
+
+impl AsyncRead for QuinnIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuinnIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,392 @@
+//! TLS record boundary tracking over the ciphertext passed between rustls and the IO.
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use rustls_fork_shadow_tls::{AlertDescription, NamedGroup, ProtocolVersion};
+
+use crate::tap::TapSlot;
+
+/// Length of a TLS record header: content type, version and payload length.
+pub(crate) const HEADER_LEN: usize = 5;
+const CONTENT_TYPE_CHANGE_CIPHER_SPEC: u8 = 20;
+const CONTENT_TYPE_ALERT: u8 = 21;
+pub(crate) const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const CONTENT_TYPE_APPLICATION_DATA: u8 = 23;
+const ALERT_LEVEL_WARNING: u8 = 1;
+const ALERT_LEVEL_FATAL: u8 = 2;
+pub(crate) const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_TYPE_SERVER_HELLO: u8 = 2;
+const HANDSHAKE_TYPE_CERTIFICATE: u8 = 11;
+const HANDSHAKE_TYPE_SERVER_KEY_EXCHANGE: u8 = 12;
+const EXTENSION_PRE_SHARED_KEY: u16 = 0x0029;
+const EXTENSION_KEY_SHARE: u16 = 0x0033;
+/// Longest handshake message kept for parsing; a server hello is far shorter.
+const MAX_CAPTURED: usize = 0x4000;
+/// Smallest record rustls agrees to fragment into.
+const MIN_FRAGMENT_SIZE: usize = 32;
+/// A full record: the header and 2^14 bytes of plaintext.
+const MAX_FRAGMENT_SIZE: usize = HEADER_LEN + 0x4000;
+/// Longest ClientHello waited for; real ones take a few KiB.
+const MAX_CLIENT_HELLO: usize = 64 * 1024;
+
+/// A fatal internal_error alert record, for clients turned away before their handshake.
+pub(crate) const INTERNAL_ERROR_ALERT: [u8; 7] = [CONTENT_TYPE_ALERT, 3, 3, 0, 2, 2, 80];
+
+/// An unprotected alert record for `description`, at the level it is usually sent at.
+pub(crate) fn plaintext_alert(description: AlertDescription) -> [u8; 7] {
+    let level = match description {
+        AlertDescription::CloseNotify | AlertDescription::UserCanceled => ALERT_LEVEL_WARNING,
+        _ => ALERT_LEVEL_FATAL,
+    };
+    [CONTENT_TYPE_ALERT, 3, 3, 0, 2, level, description.get_u8()]
+}
+
+/// Follows record headers in a ciphertext byte stream.
+#[derive(Debug, Default)]
+pub(crate) struct RecordScanner {
+    header: [u8; HEADER_LEN],
+    header_len: usize,
+    /// Payload bytes left in the current record.
+    remaining: usize,
+    /// Number of record headers seen.
+    pub(crate) records: u64,
+    /// Type of the first handshake message, if the stream starts with a handshake record.
+    pub(crate) first_handshake_type: Option<u8>,
+    pub(crate) handshake: HandshakeScanner,
+    pub(crate) taps: Vec<TapSlot>,
+}
+
+impl RecordScanner {
+    /// Bytes to the end of the current record's payload, or of its header while that is
+    /// incomplete.
+    pub(crate) fn record_left(&self) -> usize {
+        match self.remaining {
+            0 => HEADER_LEN - self.header_len,
+            remaining => remaining,
+        }
+    }
+
+    pub(crate) fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let first_payload_byte = self.records == 1
+                    && self.header[0] == CONTENT_TYPE_HANDSHAKE
+                    && self.first_handshake_type.is_none();
+                if first_payload_byte {
+                    self.first_handshake_type = Some(data[0]);
+                }
+                let n = self.remaining.min(data.len());
+                if self.header[0] == CONTENT_TYPE_HANDSHAKE {
+                    self.handshake.feed(&data[..n]);
+                }
+                self.remaining -= n;
+                for tap in &mut self.taps {
+                    tap.payload(&data[..n]);
+                    if self.remaining == 0 {
+                        tap.finish(&self.header);
+                    }
+                }
+                data = &data[n..];
+                continue;
+            }
+
+            let n = (HEADER_LEN - self.header_len).min(data.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+            self.header_len += n;
+            data = &data[n..];
+            if self.header_len == HEADER_LEN {
+                self.header_len = 0;
+                self.remaining = u16::from_be_bytes([self.header[3], self.header[4]]) as usize;
+                self.records += 1;
+                if let CONTENT_TYPE_CHANGE_CIPHER_SPEC | CONTENT_TYPE_APPLICATION_DATA =
+                    self.header[0]
+                {
+                    // Handshake records from here on are encrypted.
+                    self.handshake.done = true;
+                }
+                for tap in &mut self.taps {
+                    tap.start();
+                    if self.remaining == 0 {
+                        tap.finish(&self.header);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Follows the plaintext handshake messages at the start of a stream, picking out the hello
+/// randoms and session ids, the key exchange group the server settled on and whether a
+/// session was resumed.
+#[derive(Debug, Default)]
+pub(crate) struct HandshakeScanner {
+    header: [u8; 4],
+    header_len: usize,
+    /// Body bytes left in the current message.
+    remaining: usize,
+    /// Body of the current message, when it is one to parse.
+    body: Option<Vec<u8>>,
+    /// A ChangeCipherSpec or protected record went by: the rest is encrypted.
+    pub(crate) done: bool,
+    pub(crate) kx_group: Option<NamedGroup>,
+    /// A ServerHello selected a pre-shared key, i.e. a TLS 1.3 session was resumed.
+    pub(crate) psk_selected: bool,
+    /// A Certificate message went by in plaintext, as in a full TLS 1.2 handshake.
+    pub(crate) certificate_seen: bool,
+    /// Random of the last ClientHello or ServerHello.
+    pub(crate) hello_random: Option<[u8; 32]>,
+    /// Legacy session id of the last ClientHello or ServerHello.
+    pub(crate) hello_session_id: Option<Vec<u8>>,
+}
+
+impl HandshakeScanner {
+    fn feed(&mut self, mut data: &[u8]) {
+        while !self.done {
+            if self.header_len < self.header.len() {
+                let n = (self.header.len() - self.header_len).min(data.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&data[..n]);
+                self.header_len += n;
+                data = &data[n..];
+                if self.header_len < self.header.len() {
+                    return;
+                }
+                let [kind, len @ ..] = self.header;
+                self.remaining = u32::from_be_bytes([0, len[0], len[1], len[2]]) as usize;
+                if kind == HANDSHAKE_TYPE_CERTIFICATE {
+                    self.certificate_seen = true;
+                }
+                self.body = match kind {
+                    HANDSHAKE_TYPE_CLIENT_HELLO
+                    | HANDSHAKE_TYPE_SERVER_HELLO
+                    | HANDSHAKE_TYPE_SERVER_KEY_EXCHANGE
+                        if self.remaining <= MAX_CAPTURED =>
+                    {
+                        Some(Vec::with_capacity(self.remaining))
+                    }
+                    _ => None,
+                };
+            }
+
+            let n = self.remaining.min(data.len());
+            if let Some(body) = &mut self.body {
+                body.extend_from_slice(&data[..n]);
+            }
+            self.remaining -= n;
+            data = &data[n..];
+            if self.remaining > 0 {
+                return;
+            }
+            if let Some(body) = self.body.take() {
+                self.parse(self.header[0], &body);
+            }
+            self.header_len = 0;
+            if data.is_empty() {
+                return;
+            }
+        }
+    }
+
+    fn parse(&mut self, kind: u8, body: &[u8]) {
+        if let HANDSHAKE_TYPE_CLIENT_HELLO | HANDSHAKE_TYPE_SERVER_HELLO = kind {
+            if let Some((random, session_id)) = hello_random_and_session_id(body) {
+                self.hello_random = Some(random);
+                self.hello_session_id = Some(session_id.to_vec());
+            }
+        }
+        if kind == HANDSHAKE_TYPE_CLIENT_HELLO {
+            return;
+        }
+        if kind == HANDSHAKE_TYPE_SERVER_HELLO
+            && server_hello_extension(body, EXTENSION_PRE_SHARED_KEY).is_some()
+        {
+            self.psk_selected = true;
+        }
+        let group = match kind {
+            HANDSHAKE_TYPE_SERVER_HELLO => {
+                server_hello_extension(body, EXTENSION_KEY_SHARE).and_then(|data| data.get(..2))
+            }
+            // ECParameters of named_curve type come first.
+            _ => match body {
+                [3, group @ ..] => group.get(..2),
+                _ => None,
+            },
+        };
+        if let Some(&[high, low]) = group {
+            self.kx_group = Some(NamedGroup::from(u16::from_be_bytes([high, low])));
+        }
+    }
+}
+
+/// The random and the legacy session id of a ClientHello or ServerHello.
+fn hello_random_and_session_id(body: &[u8]) -> Option<([u8; 32], &[u8])> {
+    let (random, rest) = body.get(2..)?.split_at_checked(32)?;
+    let (&session_id_len, rest) = rest.split_first()?;
+    let session_id = rest.get(..session_id_len as usize)?;
+    Some((random.try_into().ok()?, session_id))
+}
+
+/// The data of the `extension` of a ServerHello. The key_share extension starts with the
+/// selected group.
+fn server_hello_extension(body: &[u8], extension: u16) -> Option<&[u8]> {
+    // version, random
+    let (_, rest) = body.split_at_checked(34)?;
+    let (&session_id_len, rest) = rest.split_first()?;
+    // session id, cipher suite, compression method
+    let (_, rest) = rest.split_at_checked(session_id_len as usize + 3)?;
+    let (len, rest) = rest.split_at_checked(2)?;
+    let (mut extensions, _) = rest.split_at_checked(u16::from_be_bytes([len[0], len[1]]) as usize)?;
+    while let Some((header, rest)) = extensions.split_at_checked(4) {
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let (data, rest) = rest.split_at_checked(len)?;
+        if u16::from_be_bytes([header[0], header[1]]) == extension {
+            return Some(data);
+        }
+        extensions = rest;
+    }
+    None
+}
+
+/// Whether the handshake scanned in both directions resumed a session rather than
+/// authenticating the server with its certificate.
+pub(crate) fn resumed(
+    read: &HandshakeScanner,
+    write: &HandshakeScanner,
+    version: Option<ProtocolVersion>,
+) -> bool {
+    match version {
+        Some(ProtocolVersion::TLSv1_3) => read.psk_selected || write.psk_selected,
+        // An abbreviated TLS 1.2 handshake goes straight from the ServerHello to the Finished.
+        Some(_) => !(read.certificate_seen || write.certificate_seen),
+        None => false,
+    }
+}
+
+/// The ClientHello at the start of a client's ciphertext.
+pub(crate) enum ClientHello {
+    /// More records are needed.
+    Incomplete,
+    /// The data doesn't start with a ClientHello.
+    Invalid,
+    /// The ClientHello message, handshake header included.
+    Complete(Vec<u8>),
+}
+
+/// Reassemble the ClientHello from the handshake records at the start of `data`.
+pub(crate) fn client_hello(data: &[u8]) -> ClientHello {
+    let mut message = Vec::new();
+    let mut rest = data;
+    while let Some((header, payload)) = rest.split_at_checked(HEADER_LEN) {
+        if header[0] != CONTENT_TYPE_HANDSHAKE {
+            return ClientHello::Invalid;
+        }
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let Some((payload, next)) = payload.split_at_checked(len) else {
+            break;
+        };
+        message.extend_from_slice(payload);
+        rest = next;
+        if let [kind, a, b, c, ..] = message[..] {
+            if kind != HANDSHAKE_TYPE_CLIENT_HELLO {
+                return ClientHello::Invalid;
+            }
+            let len = 4 + u32::from_be_bytes([0, a, b, c]) as usize;
+            if message.len() >= len {
+                message.truncate(len);
+                return ClientHello::Complete(message);
+            }
+        }
+    }
+    ClientHello::Incomplete
+}
+
+/// Read from `io` into `read` until it holds a whole ClientHello, or something else. EOF and
+/// a ClientHello longer than 64 KiB leave it incomplete.
+pub(crate) async fn read_client_hello<IO>(
+    io: &mut IO,
+    read: &mut Vec<u8>,
+) -> io::Result<ClientHello>
+where
+    IO: AsyncRead + Unpin,
+{
+    let mut buf = [0; 4096];
+    loop {
+        match client_hello(read) {
+            ClientHello::Incomplete if read.len() < MAX_CLIENT_HELLO => (),
+            hello => return Ok(hello),
+        }
+        let n = io.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(ClientHello::Incomplete);
+        }
+        read.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Fails unless rustls can fragment outgoing records into `size` bytes, header included.
+pub(crate) fn check_fragment_size(size: usize) -> io::Result<()> {
+    match (MIN_FRAGMENT_SIZE..=MAX_FRAGMENT_SIZE).contains(&size) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("record size {size} is not between {MIN_FRAGMENT_SIZE} and {MAX_FRAGMENT_SIZE}"),
+        )),
+    }
+}
+
+/// `io::Read` wrapper feeding everything read into a `RecordScanner`.
+pub(crate) struct TapRead<'a, R> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) scanner: &'a mut RecordScanner,
+    /// Where to keep a copy of everything read, if anywhere.
+    pub(crate) capture: Option<&'a mut Vec<u8>>,
+    /// Stop at the end of each record header and payload, so nothing past the record which
+    /// completes the handshake is read.
+    pub(crate) by_record: bool,
+}
+
+impl<R: io::Read> io::Read for TapRead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buf = match self.by_record {
+            true => {
+                let len = buf.len().min(self.scanner.record_left());
+                &mut buf[..len]
+            }
+            false => buf,
+        };
+        let n = self.inner.read(buf)?;
+        self.scanner.feed(&buf[..n]);
+        if let Some(capture) = self.capture.as_mut() {
+            capture.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// `io::Write` wrapper feeding everything written into a `RecordScanner`.
+pub(crate) struct TapWrite<'a, W> {
+    pub(crate) inner: &'a mut W,
+    pub(crate) scanner: &'a mut RecordScanner,
+}
+
+impl<W: io::Write> io::Write for TapWrite<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.scanner.feed(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        let mut left = n;
+        for buf in bufs {
+            let fed = left.min(buf.len());
+            self.scanner.feed(&buf[..fed]);
+            left -= fed;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,106 @@
+//! Relaying between two streams, with half-closes carried across.
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Bytes each direction of [`copy_bidirectional_with_budget`] may carry at most. Unlimited by
+/// default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayBudget {
+    pub a_to_b: Option<u64>,
+    pub b_to_a: Option<u64>,
+}
+
+/// What [`copy_bidirectional_with_budget`] carried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayStats {
+    pub a_to_b: TransferStats,
+    pub b_to_a: TransferStats,
+}
+
+/// What one direction of a relay carried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub bytes: u64,
+    /// Reads which returned data, each written on and flushed.
+    pub reads: u64,
+    /// The source ended without a close_notify, e.g. because its peer closed the TCP
+    /// connection.
+    pub truncated: bool,
+    /// The direction stopped after carrying its budget rather than at the end of the source.
+    pub budget_exhausted: bool,
+    /// From the start of the relay until the destination was shut down.
+    pub duration: Duration,
+}
+
+/// Copy data both ways between `a` and `b` until both directions are done, for relays
+/// between TLS streams or between a TLS stream and a plain one.
+///
+/// Each direction ends when its source does, with a close_notify or by the IO closing, or
+/// when it has carried its budget; the destination is then shut down, so TLS destinations
+/// send a close_notify while the other direction carries on. Unlike
+/// `tokio::io::copy_bidirectional`, a source ending without a close_notify is taken as its
+/// end, reported in [`TransferStats::truncated`], rather than failing the relay. Other errors
+/// fail it in both directions.
+pub async fn copy_bidirectional_with_budget<A, B>(
+    a: &mut A,
+    b: &mut B,
+    budget: RelayBudget,
+) -> io::Result<RelayStats>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let start = Instant::now();
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    let (a_to_b, b_to_a) = tokio::try_join!(
+        copy_half(&mut a_read, &mut b_write, budget.a_to_b, start),
+        copy_half(&mut b_read, &mut a_write, budget.b_to_a, start),
+    )?;
+    Ok(RelayStats { a_to_b, b_to_a })
+}
+
+/// Copy one direction, then shut its destination down.
+async fn copy_half<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    budget: Option<u64>,
+    start: Instant,
+) -> io::Result<TransferStats>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut stats = TransferStats::default();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let left = budget.map_or(u64::MAX, |budget| budget.saturating_sub(stats.bytes));
+        if left == 0 {
+            stats.budget_exhausted = true;
+            break;
+        }
+        let len = usize::try_from(left).unwrap_or(usize::MAX).min(buf.len());
+        let n = match reader.read(&mut buf[..len]).await {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                stats.truncated = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n]).await?;
+        writer.flush().await?;
+        stats.bytes += n as u64;
+        stats.reads += 1;
+    }
+    writer.shutdown().await?;
+    stats.duration = start.elapsed();
+    Ok(stats)
+}
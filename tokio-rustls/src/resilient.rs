@@ -0,0 +1,297 @@
+//! A client stream which reconnects when its transport dies.
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use rustls_fork_shadow_tls::ServerName;
+
+use crate::{client::TlsStream, stream::HandshakeKind, TlsConnector, TlsError};
+
+/// Reconnection attempts made after the transport dies, unless set with `with_max_attempts`.
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+/// Reconnections over the life of the stream, unless set with `with_max_reconnects`.
+const DEFAULT_MAX_RECONNECTS: u32 = 16;
+
+type Dial<IO> = Pin<Box<dyn Future<Output = Result<TlsStream<IO>, TlsError>> + Send>>;
+
+type EventCallback = Arc<dyn Fn(ReconnectEvent<'_>) + Send + Sync>;
+
+/// Progress of a [`ResilientTlsStream`] reconnecting.
+#[derive(Debug)]
+pub enum ReconnectEvent<'a> {
+    /// The transport failed with this error, reconnecting.
+    Disconnected(&'a io::Error),
+    /// Reconnection attempt `attempt`, counting from 1, failed.
+    AttemptFailed { attempt: u32, error: &'a TlsError },
+    /// Reconnected on attempt `attempt`, with a handshake of `kind`.
+    Reconnected { attempt: u32, kind: HandshakeKind },
+}
+
+enum State<IO> {
+    Connected(TlsStream<IO>),
+    Reconnecting { attempt: u32, dial: Dial<IO> },
+    /// Every attempt failed or the stream was shut down.
+    Closed,
+}
+
+/// A client TLS stream which, when its transport dies, dials a new one and handshakes again.
+///
+/// The connector's session cache is shared by every handshake, so a reconnection resumes the
+/// previous session when the server allows it. Data in flight when the transport died is
+/// lost: a failed write is retried on the new connection, but anything rustls already handed
+/// to the old transport is gone, so the protocol on top must tolerate that. A failed read
+/// returns its error while the stream reconnects, so the bytes of the new session aren't
+/// taken for the rest of the old one, unless enabled
+/// [`with_resumed_reads`](Self::with_resumed_reads).
+///
+/// Only transport failures (reset, aborted, broken pipe) trigger a reconnection; TLS errors,
+/// timeouts and the server closing, with or without a close_notify, are returned as they
+/// are.
+pub struct ResilientTlsStream<IO, F> {
+    connector: TlsConnector,
+    domain: ServerName,
+    dialer: F,
+    max_attempts: u32,
+    max_reconnects: u32,
+    reconnects: u32,
+    resumed_reads: bool,
+    on_event: Option<EventCallback>,
+    state: State<IO>,
+}
+
+impl<IO, F> fmt::Debug for ResilientTlsStream<IO, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state {
+            State::Connected(_) => "connected",
+            State::Reconnecting { .. } => "reconnecting",
+            State::Closed => "closed",
+        };
+        f.debug_struct("ResilientTlsStream")
+            .field("domain", &self.domain)
+            .field("state", &state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<IO, F, Fut> ResilientTlsStream<IO, F>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<IO>> + Send + 'static,
+{
+    /// Dial a transport with `dialer` and connect to `domain` over it.
+    pub async fn connect(
+        connector: TlsConnector,
+        domain: ServerName,
+        mut dialer: F,
+    ) -> Result<Self, TlsError> {
+        let io = dialer().await?;
+        let stream = connector.connect(domain.clone(), io).await?;
+        Ok(Self {
+            connector,
+            domain,
+            dialer,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            max_reconnects: DEFAULT_MAX_RECONNECTS,
+            reconnects: 0,
+            resumed_reads: false,
+            on_event: None,
+            state: State::Connected(stream),
+        })
+    }
+
+    /// Give up after `attempts` failed reconnection attempts in a row, returning the last
+    /// error. Defaults to 3.
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Stop reconnecting after `reconnects` reconnections over the life of the stream,
+    /// returning the transport error from then on. Defaults to 16.
+    pub fn with_max_reconnects(mut self, reconnects: u32) -> Self {
+        self.max_reconnects = reconnects;
+        self
+    }
+
+    /// Retry a read whose transport died on the new connection, rather than returning its
+    /// error, for protocols on top which tolerate the stream skipping from the old session
+    /// to the new one.
+    pub fn with_resumed_reads(mut self, enabled: bool) -> Self {
+        self.resumed_reads = enabled;
+        self
+    }
+
+    /// Report disconnections and reconnection attempts to `callback`, which runs inline on the
+    /// task driving the stream.
+    pub fn with_event_callback(
+        mut self,
+        callback: impl Fn(ReconnectEvent<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_event = Some(Arc::new(callback));
+        self
+    }
+
+    /// The current connection, unless the stream is reconnecting or closed.
+    pub fn get_ref(&self) -> Option<&TlsStream<IO>> {
+        match &self.state {
+            State::Connected(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn emit(&self, event: ReconnectEvent<'_>) {
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+
+    fn dial(&mut self) -> Dial<IO> {
+        let connector = self.connector.clone();
+        let domain = self.domain.clone();
+        let io = (self.dialer)();
+        Box::pin(async move { connector.connect(domain, io.await?).await })
+    }
+
+    /// Start reconnecting after `error`, unless the stream has reconnected as often as it
+    /// may. Returns whether it reconnects.
+    fn disconnected(&mut self, error: &io::Error) -> bool {
+        if self.reconnects >= self.max_reconnects {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(%error, "tls transport lost, out of reconnections");
+            self.state = State::Closed;
+            return false;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(%error, "tls transport lost, reconnecting");
+        self.emit(ReconnectEvent::Disconnected(error));
+        self.reconnects += 1;
+        let dial = self.dial();
+        self.state = State::Reconnecting { attempt: 1, dial };
+        true
+    }
+
+    /// Wait for a reconnection in progress to finish.
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut TlsStream<IO>>> {
+        loop {
+            let (attempt, dial) = match &mut self.state {
+                State::Connected(_) => break,
+                State::Reconnecting { attempt, dial } => (*attempt, dial),
+                State::Closed => return Poll::Ready(Err(io::ErrorKind::NotConnected.into())),
+            };
+            match ready!(dial.as_mut().poll(cx)) {
+                Ok(stream) => {
+                    let kind = stream.handshake_kind().unwrap_or(HandshakeKind::Full);
+                    self.emit(ReconnectEvent::Reconnected { attempt, kind });
+                    self.state = State::Connected(stream);
+                }
+                Err(error) => {
+                    self.emit(ReconnectEvent::AttemptFailed {
+                        attempt,
+                        error: &error,
+                    });
+                    if attempt >= self.max_attempts {
+                        self.state = State::Closed;
+                        return Poll::Ready(Err(error.into()));
+                    }
+                    let dial = self.dial();
+                    self.state = State::Reconnecting {
+                        attempt: attempt + 1,
+                        dial,
+                    };
+                }
+            }
+        }
+        match &mut self.state {
+            State::Connected(stream) => Poll::Ready(Ok(stream)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Run `op` on the connection, reconnecting when the transport fails, and running it
+    /// again on the new connection when `retry`.
+    fn poll_op<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        retry: bool,
+        mut op: impl FnMut(Pin<&mut TlsStream<IO>>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            let stream = ready!(self.poll_connected(cx))?;
+            match ready!(op(Pin::new(stream), cx)) {
+                Err(error) if is_disconnect(&error) => {
+                    if !self.disconnected(&error) || !retry {
+                        return Poll::Ready(Err(error));
+                    }
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+}
+
+impl<IO, F, Fut> AsyncRead for ResilientTlsStream<IO, F>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: FnMut() -> Fut + Unpin,
+    Fut: Future<Output = io::Result<IO>> + Send + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let retry = this.resumed_reads;
+        this.poll_op(cx, retry, |stream, cx| stream.poll_read(cx, buf))
+    }
+}
+
+impl<IO, F, Fut> AsyncWrite for ResilientTlsStream<IO, F>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: FnMut() -> Fut + Unpin,
+    Fut: Future<Output = io::Result<IO>> + Send + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_op(cx, true, |stream, cx| stream.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_op(cx, true, |stream, cx| stream.poll_flush(cx))
+    }
+
+    /// Shuts the current connection down without reconnecting; a reconnection in progress is
+    /// abandoned.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = match &mut this.state {
+            State::Connected(stream) => ready!(Pin::new(stream).poll_shutdown(cx)),
+            State::Reconnecting { .. } | State::Closed => Ok(()),
+        };
+        this.state = State::Closed;
+        Poll::Ready(res)
+    }
+}
+
+/// Whether `error` means the transport is gone, rather than the TLS session being broken.
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
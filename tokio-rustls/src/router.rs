@@ -0,0 +1,116 @@
+//! Dispatching accepted streams by their negotiated ALPN protocol.
+use std::{collections::HashMap, fmt, future::Future, pin::Pin, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::server::TlsStream;
+
+type Handler<IO> =
+    Arc<dyn Fn(TlsStream<IO>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Hands accepted streams to a handler picked by the ALPN protocol they negotiated, for
+/// servers speaking several protocols on one port.
+///
+/// The server only negotiates protocols it offers: set `ServerConfig::alpn_protocols` to
+/// [`alpn_protocols`](Self::alpn_protocols), which lists the routes in the order they were
+/// added, most preferred first.
+pub struct AlpnRouter<IO> {
+    routes: HashMap<Vec<u8>, Handler<IO>>,
+    order: Vec<Vec<u8>>,
+    fallback: Option<Handler<IO>>,
+}
+
+impl<IO> Clone for AlpnRouter<IO> {
+    fn clone(&self) -> Self {
+        Self {
+            routes: self.routes.clone(),
+            order: self.order.clone(),
+            fallback: self.fallback.clone(),
+        }
+    }
+}
+
+impl<IO> Default for AlpnRouter<IO> {
+    fn default() -> Self {
+        Self {
+            routes: HashMap::new(),
+            order: Vec::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<IO> fmt::Debug for AlpnRouter<IO> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let protocols: Vec<_> = self
+            .order
+            .iter()
+            .map(|p| String::from_utf8_lossy(p))
+            .collect();
+        f.debug_struct("AlpnRouter")
+            .field("protocols", &protocols)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+impl<IO> AlpnRouter<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand streams which negotiated `protocol`, such as `h2` or `http/1.1`, to `handler`.
+    /// Routing a protocol again replaces its handler.
+    pub fn route<F, Fut>(mut self, protocol: impl Into<Vec<u8>>, handler: F) -> Self
+    where
+        F: Fn(TlsStream<IO>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let protocol = protocol.into();
+        if !self.routes.contains_key(&protocol) {
+            self.order.push(protocol.clone());
+        }
+        self.routes
+            .insert(protocol, Arc::new(move |stream| Box::pin(handler(stream))));
+        self
+    }
+
+    /// Hand streams which negotiated no protocol, or one without a route, to `handler`.
+    /// Without it such streams are closed with a close_notify.
+    pub fn fallback<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(TlsStream<IO>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.fallback = Some(Arc::new(move |stream| Box::pin(handler(stream))));
+        self
+    }
+
+    /// The routed protocols, in the order they were added.
+    pub fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        self.order.clone()
+    }
+
+    /// Run the handler for the protocol `stream` negotiated until it returns.
+    pub async fn dispatch(&self, mut stream: TlsStream<IO>) {
+        let handler = stream
+            .session
+            .alpn_protocol()
+            .and_then(|protocol| self.routes.get(protocol))
+            .or(self.fallback.as_ref());
+        match handler {
+            Some(handler) => handler(stream).await,
+            None => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    alpn = ?stream.session.alpn_protocol().map(String::from_utf8_lossy),
+                    "no route for the negotiated protocol, closing"
+                );
+                let _ = stream.shutdown().await;
+            }
+        }
+    }
+}
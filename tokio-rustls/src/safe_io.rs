@@ -6,7 +6,7 @@ use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}
 };
 
-const BUFFER_SIZE: usize = 16 * 1024;
+pub(crate) const BUFFER_SIZE: usize = 16 * 1024;
 
 struct Buffer {
     read: usize,
@@ -49,6 +49,16 @@ impl Buffer {
     }
 }
 
+// The buffers hold ciphertext, which may still be decryptable with secrets lingering in
+// memory, and are wiped on drop for deployments which ask for it. rustls keeps its own
+// plaintext and key material, which this can't reach.
+#[cfg(feature = "zeroize")]
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.buf[..]);
+    }
+}
+
 pub(crate) struct SafeRead {
     // the option is only meant for temporary take, it always should be some
     buffer: Option<Buffer>,
@@ -110,6 +120,25 @@ impl SafeRead {
     }
 }
 
+impl SafeRead {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buffer.as_ref().expect("buffer ref expected").is_empty()
+    }
+
+    /// Take the data read but not consumed yet.
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        let buffer = self.buffer.as_mut().expect("buffer mut expected");
+        let data = buffer.buf[buffer.read..buffer.write].to_vec();
+        buffer.advance(buffer.len());
+        data
+    }
+
+    /// Forget the eof or error of the previous IO.
+    pub(crate) fn reset(&mut self) {
+        self.status = ReadStatus::Ok;
+    }
+}
+
 impl io::Read for SafeRead {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // if buffer is empty, return WoundBlock.
@@ -171,22 +200,42 @@ impl SafeWrite {
             return Ok(0);
         }
 
-        // buffer is not empty now. write it.
+        // buffer is not empty now. write it, recording progress after every write so a
+        // cancelled future does not send the same bytes again.
         let buffer = self.buffer.as_mut().expect("buffer ownership expected");
-        let buf = &buffer.buf.as_ref()[buffer.read..buffer.write];
-        let result = io.write_all(buf).await;
-        match result {
-            Ok(_) => {
-                let n = buf.len();
-                buffer.advance(n);
-                Ok(n)
-            }
-            Err(e) => {
-                let rerr = e.kind().into();
-                self.status = WriteStatus::Err(e);
-                Err(rerr)
+        let mut written = 0;
+        while !buffer.is_empty() {
+            let buf = &buffer.buf.as_ref()[buffer.read..buffer.write];
+            match io.write(buf).await {
+                Ok(0) => {
+                    self.status = WriteStatus::Err(io::ErrorKind::WriteZero.into());
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                Ok(n) => {
+                    buffer.advance(n);
+                    written += n;
+                }
+                Err(e) => {
+                    let rerr = e.kind().into();
+                    self.status = WriteStatus::Err(e);
+                    return Err(rerr);
+                }
             }
         }
+        Ok(written)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buffer.as_ref().expect("buffer ref expected").is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buffer.as_ref().expect("buffer ref expected").len()
+    }
+
+    /// Forget the error of the previous IO.
+    pub(crate) fn reset(&mut self) {
+        self.status = WriteStatus::Ok;
     }
 }
 
@@ -211,6 +260,25 @@ impl io::Write for SafeWrite {
         Ok(to_copy)
     }
 
+    // Copy as many buffers as fit, so records rustls queued together, such as the last
+    // handshake flight and the first application data, go out in one write.
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let mut written = 0;
+        for buf in bufs.iter().filter(|buf| !buf.is_empty()) {
+            match self.write(buf) {
+                Ok(n) => {
+                    written += n;
+                    if n < buf.len() {
+                        break;
+                    }
+                }
+                Err(e) if written == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(written)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         let buffer = self.buffer.as_mut().expect("buffer mut expected");
         if !matches!(self.status, WriteStatus::Ok) {
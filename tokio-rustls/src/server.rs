@@ -1,11 +1,34 @@
-use std::sync::Arc;
+use std::{io, pin::Pin, sync::Arc};
 
-use tokio::io::{AsyncRead, AsyncWrite};
-use rustls_fork_shadow_tls::{ServerConfig, ServerConnection};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use rustls_fork_shadow_tls::{
+    server::ClientHello, sign::SigningKey, AlertDescription, Certificate, CipherSuite, KeyLog,
+    NamedGroup, ProtocolVersion, ServerConfig, ServerConnection,
+};
 
+#[cfg(feature = "acme")]
+use crate::acme::{self, AcmeCertResolver};
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, StreamMetrics};
+#[cfg(feature = "pcap")]
+use crate::pcap::PcapCapture;
 use crate::{
+    builder::TlsAcceptorBuilder,
+    camouflage::{FailureResponse, Relay, Tarpit},
+    error::ErrorContext,
+    fallback::Fallback,
+    flush::{DeferredFlush, FlushScheduler},
+    hello::{self, ClientHelloInfo, HelloAction, HelloPolicy},
+    memory::MemoryBudget,
+    negotiated::Allowed,
+    observer::{HandshakeObserver, ObserverSlot},
+    ocsp::StapledCert,
+    proxy_protocol::{self, ProxyHeader},
+    record,
+    sni::{Host, SniHosts},
     split::{ReadHalf, WriteHalf},
     stream::Stream,
+    tap::RecordTap,
     TlsError,
 };
 
@@ -16,34 +39,672 @@ pub type TlsStreamReadHalf<IO> = ReadHalf<IO, ServerConnection>;
 /// TlsStream for write only.
 pub type TlsStreamWriteHalf<IO> = WriteHalf<IO, ServerConnection>;
 
+/// What [`TlsAcceptor::accept_or_relay`] made of a connection.
+pub enum Accepted<IO> {
+    /// The handshake completed.
+    Stream(TlsStream<IO>),
+    /// The connection is to be relayed to a TLS server, by the fallback of
+    /// [`TlsAcceptor::with_fallback`] or the [`FailureResponse::Relay`] failure response.
+    Relayed(Relay<IO>),
+}
+
 /// A wrapper around a `rustls::ServerConfig`, providing an async `accept` method.
 #[derive(Clone)]
 pub struct TlsAcceptor {
     inner: Arc<ServerConfig>,
+    proxy_protocol: bool,
+    pub(crate) error_context: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    observer: Option<Arc<dyn HandshakeObserver>>,
+    allowed: Allowed,
+    budget: Option<MemoryBudget>,
+    flush_scheduler: Option<FlushScheduler>,
+    record_tap: Option<Arc<dyn RecordTap>>,
+    #[cfg(feature = "pcap")]
+    pcap: Option<PcapCapture>,
+    #[cfg(feature = "offload")]
+    offload: bool,
+    failure: FailureResponse,
+    fallback: Option<Fallback>,
+    hello_policy: Option<HelloPolicy>,
+    unparsable_hello: Option<HelloAction>,
+    tarpit: Tarpit,
+    sni_hosts: Option<Arc<SniHosts>>,
+    #[cfg(feature = "acme")]
+    acme: bool,
 }
 
 impl From<Arc<ServerConfig>> for TlsAcceptor {
     fn from(inner: Arc<ServerConfig>) -> TlsAcceptor {
-        TlsAcceptor { inner }
+        TlsAcceptor {
+            inner,
+            proxy_protocol: false,
+            error_context: false,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            observer: None,
+            allowed: Allowed::default(),
+            budget: None,
+            flush_scheduler: None,
+            record_tap: None,
+            #[cfg(feature = "pcap")]
+            pcap: None,
+            #[cfg(feature = "offload")]
+            offload: false,
+            failure: FailureResponse::default(),
+            fallback: None,
+            hello_policy: None,
+            unparsable_hello: None,
+            tarpit: Tarpit::default(),
+            sni_hosts: None,
+            #[cfg(feature = "acme")]
+            acme: false,
+        }
     }
 }
 
 impl From<ServerConfig> for TlsAcceptor {
     fn from(inner: ServerConfig) -> TlsAcceptor {
-        TlsAcceptor {
-            inner: Arc::new(inner),
-        }
+        TlsAcceptor::from(Arc::new(inner))
     }
 }
 
 impl TlsAcceptor {
+    /// Build an acceptor from a certificate and common options, without the rustls config
+    /// builder.
+    pub fn builder() -> TlsAcceptorBuilder {
+        TlsAcceptorBuilder::new()
+    }
+
+    /// Log the TLS secrets of new connections to `key_log`.
+    pub fn with_key_log(mut self, key_log: Arc<dyn KeyLog>) -> Self {
+        Arc::make_mut(&mut self.inner).key_log = key_log;
+        self
+    }
+
+    /// Log the TLS secrets of new connections to the file named by the `SSLKEYLOGFILE`
+    /// environment variable, in the NSS key log format Wireshark reads. Nothing is logged when
+    /// the variable is unset.
+    #[cfg(feature = "key_log")]
+    pub fn with_key_log_file(self) -> Self {
+        self.with_key_log(Arc::new(rustls_fork_shadow_tls::KeyLogFile::new()))
+    }
+
+    /// Write the records of new connections to `capture`, along with their secrets. This
+    /// replaces the key log.
+    #[cfg(feature = "pcap")]
+    pub fn with_pcap(mut self, capture: PcapCapture) -> Self {
+        Arc::make_mut(&mut self.inner).key_log = Arc::new(capture.clone());
+        self.pcap = Some(capture);
+        self
+    }
+
+    /// Record the handshakes and traffic of new connections in `metrics`.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Present `cert` to clients, with the OCSP response it currently holds stapled.
+    pub fn with_stapled_cert(mut self, cert: StapledCert) -> Self {
+        Arc::make_mut(&mut self.inner).cert_resolver = Arc::new(cert);
+        self
+    }
+
+    /// Present `chain` to clients, signing handshakes with `key`.
+    ///
+    /// `key` can be backed by an HSM, TPM, PKCS#11 token or cloud KMS so the private key never
+    /// enters the process. Signing is synchronous and blocks the task driving the handshake.
+    pub fn with_signing_key(self, chain: Vec<Certificate>, key: Arc<dyn SigningKey>) -> Self {
+        self.with_stapled_cert(StapledCert::from_signing_key(chain, key))
+    }
+
+    /// Present the certificate `resolver` obtains, and answer its TLS-ALPN-01 challenges.
+    ///
+    /// Challenge handshakes negotiate `acme-tls/1` and then fail `accept` with
+    /// `ConnectionAborted`; the protocol is not offered to other clients.
+    #[cfg(feature = "acme")]
+    pub fn with_acme(mut self, resolver: AcmeCertResolver) -> Self {
+        Arc::make_mut(&mut self.inner).cert_resolver = Arc::new(resolver);
+        self.acme = true;
+        self
+    }
+
+    /// Charge the buffers of new connections to `budget`, failing those it can't take with
+    /// `OutOfMemory`.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Process the ClientHello of new connections on the blocking thread pool of the runtime.
+    ///
+    /// That step does the expensive part of a full handshake on the server: the key exchange
+    /// and the signature with the certificate key. Running it off the task driving `accept`
+    /// keeps the runtime's event loop responsive under handshake floods, most of all on a
+    /// current-thread runtime. Later handshake messages, and the ACME validation handshakes
+    /// of `with_acme`, are still processed in place.
+    #[cfg(feature = "offload")]
+    pub fn with_handshake_offload(mut self, enabled: bool) -> Self {
+        self.offload = enabled;
+        self
+    }
+
+    /// Defer the writes of new connections to the time slices of `scheduler`.
+    pub fn with_flush_scheduler(mut self, scheduler: FlushScheduler) -> Self {
+        self.flush_scheduler = Some(scheduler);
+        self
+    }
+
+    /// Report every record new connections read or write to `tap`.
+    pub fn with_record_tap(mut self, tap: Arc<dyn RecordTap>) -> Self {
+        self.record_tap = Some(tap);
+        self
+    }
+
+    /// Report the handshake progress of new connections to `observer`.
+    pub fn with_handshake_observer(mut self, observer: Arc<dyn HandshakeObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Only accept clients which negotiate TLS 1.3.
+    pub fn tls13_only(self) -> Self {
+        self.min_version(ProtocolVersion::TLSv1_3)
+    }
+
+    /// Only accept clients which negotiate TLS 1.2. Clients offering TLS 1.3 still negotiate
+    /// it when the config supports it and are then turned away; build the config with
+    /// `with_protocol_versions(&[&version::TLS12])` to make them fall back instead.
+    pub fn tls12_only(mut self) -> Self {
+        self.allowed.min_version = Some(ProtocolVersion::TLSv1_2);
+        self.allowed.max_version = Some(ProtocolVersion::TLSv1_2);
+        self
+    }
+
+    /// Turn away clients which negotiate a version older than `version`. Accepting fails up
+    /// front when the config supports none which is recent enough.
+    pub fn min_version(mut self, version: ProtocolVersion) -> Self {
+        self.allowed.min_version = Some(version);
+        self.allowed.max_version = None;
+        self
+    }
+
+    /// Turn away clients which negotiate a cipher suite other than `suites`. Errors when a
+    /// suite isn't implemented by rustls.
+    ///
+    /// The config still picks from the suites it was built with; build it with the same
+    /// restriction so clients offering an allowed suite get it.
+    pub fn with_cipher_suites(mut self, suites: &[CipherSuite]) -> io::Result<Self> {
+        self.allowed.set_cipher_suites(suites)?;
+        Ok(self)
+    }
+
+    /// Turn away clients whose key exchange used a group other than `groups`. Errors when a
+    /// group isn't implemented by rustls.
+    pub fn with_kx_groups(mut self, groups: &[NamedGroup]) -> io::Result<Self> {
+        self.allowed.set_kx_groups(groups)?;
+        Ok(self)
+    }
+
+    /// Accept clients asking for `server_name` with the config of `host`, so its certificate,
+    /// ALPN protocols, client authentication and protocol versions, and turn them away on the
+    /// versions, cipher suites and groups `host` does. `server_name` can start with `*.` to
+    /// match any one label in its place; clients asking for a name without a host, or for
+    /// none, get this acceptor's config.
+    ///
+    /// The host is picked once the ClientHello is read, before any of the handshake is
+    /// processed, so `with_handshake_offload` has no effect.
+    /// Everything else comes from this acceptor, e.g. metrics, the PROXY protocol or the
+    /// failure response. Errors when `host` could never complete a handshake, as `accept`
+    /// would.
+    pub fn with_sni_host(mut self, server_name: &str, host: TlsAcceptor) -> io::Result<Self> {
+        host.allowed
+            .check_offered(|version| host.inner.supports_version(version))?;
+        let host = Host {
+            config: host.inner,
+            allowed: host.allowed,
+        };
+        Arc::make_mut(self.sni_hosts.get_or_insert_with(Default::default))
+            .insert(server_name, host);
+        Ok(self)
+    }
+
+    /// Fragment outgoing records into at most `size` bytes, header and overhead included.
+    /// Errors unless `size` is between 32 and 16389.
+    ///
+    /// Clients can't ask for a smaller limit: the rustls fork ignores the `record_size_limit`
+    /// and `max_fragment_length` extensions, so a constrained client is served records of this
+    /// size whatever it offered.
+    pub fn with_max_fragment_size(mut self, size: usize) -> io::Result<Self> {
+        record::check_fragment_size(size)?;
+        Arc::make_mut(&mut self.inner).max_fragment_size = Some(size);
+        Ok(self)
+    }
+
+    fn new_stream<IO>(&self, io: IO, session: ServerConnection) -> TlsStream<IO> {
+        let mut stream = Stream::new(io, session);
+        #[cfg(feature = "metrics")]
+        {
+            stream.metrics = self.metrics.clone().map(|metrics| StreamMetrics {
+                metrics,
+                active: false,
+            });
+        }
+        stream.observer = self.observer.clone().map(ObserverSlot::new);
+        stream.deferred_flush = self.flush_scheduler.clone().map(DeferredFlush::new);
+        if let Some(tap) = &self.record_tap {
+            stream.add_record_tap(tap.clone());
+        }
+        #[cfg(feature = "pcap")]
+        if let Some(capture) = &self.pcap {
+            stream.add_record_tap(Arc::new(capture.flow(false)));
+        }
+        stream.send_alerts = self.failure.sends_alert();
+        if self.failure.captures() {
+            stream.capture = Some(Vec::new());
+        }
+        stream
+    }
+
+    /// Respond to clients whose handshake fails according to `response`, instead of sending
+    /// them an alert right away. `accept` still fails.
+    pub fn with_failure_response(mut self, response: FailureResponse) -> Self {
+        self.failure = response;
+        self
+    }
+
+    /// Authenticate clients by their ClientHello before the handshake, and relay those which
+    /// fail to the TLS server at `backend` (`host:port`), byte for byte, instead of answering
+    /// them. This is how shadow-tls keeps active probes from telling the relay apart from the
+    /// server it imitates.
+    ///
+    /// `authenticate` gets the ClientHello message, handshake header included, e.g. to check
+    /// the HMAC a shadow-tls client puts in the session id. Clients sending anything else
+    /// than a ClientHello are relayed as well. `accept` fails with `ConnectionAborted` once
+    /// the relay ends; [`accept_or_relay`](Self::accept_or_relay) returns the relay instead,
+    /// for the caller to run off its accept path.
+    pub fn with_fallback(
+        mut self,
+        backend: impl Into<String>,
+        authenticate: impl Fn(&[u8]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.fallback = Some(Fallback {
+            backend: backend.into(),
+            authenticate: Arc::new(authenticate),
+        });
+        self
+    }
+
+    /// Decide what to do with each client from its ClientHello, with `policy`, before any of
+    /// the handshake is answered: go on, turn it away, hold it in a tarpit, or route it to a
+    /// virtual host set with [`with_sni_host`](Self::with_sni_host). `policy` gets the
+    /// parsed message and its JA3 and JA4 fingerprints, e.g. to filter bots or to gate
+    /// shadow-tls clients. It runs before the fallback of
+    /// [`with_fallback`](Self::with_fallback) authenticates the client.
+    ///
+    /// Clients turned away or tarpitted fail `accept` with `ConnectionAborted`. Clients which
+    /// don't send a ClientHello which parses never reach the policy, and are handled as
+    /// [`with_unparsable_hello`](Self::with_unparsable_hello) says. Clients the policy lets
+    /// through whose ClientHello rustls rejects get the
+    /// [failure response](Self::with_failure_response), as any failed handshake does.
+    pub fn with_hello_policy(
+        mut self,
+        policy: impl Fn(&ClientHelloInfo) -> HelloAction + Send + Sync + 'static,
+    ) -> Self {
+        self.hello_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// What to do with clients which don't send a ClientHello which parses, when there is a
+    /// [ClientHello policy](Self::with_hello_policy). They are rejected by default, or
+    /// accepted with a [fallback](Self::with_fallback), which relays them to its backend.
+    pub fn with_unparsable_hello(mut self, action: HelloAction) -> Self {
+        self.unparsable_hello = Some(action);
+        self
+    }
+
+    /// Hold the clients the ClientHello policy tarpits as `tarpit` says, instead of stalling
+    /// them for 30 seconds. Use [`FailureResponse::Tarpit`] to hold clients whose handshake
+    /// fails as well.
+    pub fn with_tarpit(mut self, tarpit: Tarpit) -> Self {
+        self.tarpit = tarpit;
+        self
+    }
+
+    /// Expect a PROXY protocol (v1 or v2) header before the TLS records.
+    ///
+    /// Connections without a valid header are rejected. The carried addresses are available
+    /// through [`TlsStream::proxy_header`].
+    pub fn with_proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Return handshake errors as [`TlsError::Context`], carrying the SNI the client asked
+    /// for and the client's address, when known: from the PROXY protocol header, or from the
+    /// TCP connection when accepted by a `TlsListener`.
+    pub fn with_error_context(mut self, enabled: bool) -> Self {
+        self.error_context = enabled;
+        self
+    }
+
+    /// Accept a connection. Connections relayed to a TLS server, by the fallback of
+    /// [`with_fallback`](Self::with_fallback) or the [`FailureResponse::Relay`] failure
+    /// response, are relayed before `accept` fails, so it only returns once the relay ends.
     pub async fn accept<IO>(&self, stream: IO) -> Result<TlsStream<IO>, TlsError>
     where
         IO: AsyncRead + AsyncWrite + Unpin,
     {
-        let session = ServerConnection::new(self.inner.clone())?;
-        let mut stream = Stream::new(stream, session);
-        stream.handshake().await?;
-        Ok(stream)
+        match self.accept_or_relay(stream).await? {
+            Accepted::Stream(stream) => Ok(stream),
+            Accepted::Relayed(mut relay) => {
+                let _ = relay.relay().await;
+                Err(relay.error)
+            }
+        }
+    }
+
+    /// Accept a connection, or return the relay of a connection handed to a TLS server
+    /// without running it, e.g. for the caller to spawn it so it doesn't count as a
+    /// handshake in progress. [`Relay::error`] is the error `accept` would fail with.
+    pub async fn accept_or_relay<IO>(&self, stream: IO) -> Result<Accepted<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut context = ErrorContext::default();
+        let res = self.accept_inner(stream, &mut context).await;
+        match (self.error_context, res) {
+            (true, Ok(Accepted::Relayed(mut relay))) => {
+                relay.error = relay.error.with_context(context);
+                Ok(Accepted::Relayed(relay))
+            }
+            (true, res) => res.map_err(|e| e.with_context(context)),
+            (false, res) => res,
+        }
+    }
+
+    async fn accept_inner<IO>(
+        &self,
+        mut stream: IO,
+        context: &mut ErrorContext,
+    ) -> Result<Accepted<IO>, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.allowed
+            .check_offered(|version| self.inner.supports_version(version))?;
+        let memory = self
+            .budget
+            .as_ref()
+            .map(MemoryBudget::reserve)
+            .transpose()?;
+        let proxy_header = match self.proxy_protocol {
+            true => proxy_protocol::read_header(&mut stream).await?,
+            false => None,
+        };
+        context.peer_addr = proxy_header.map(|header| header.source);
+        let mut preread = Vec::new();
+        let mut route = None;
+        if let Some(policy) = &self.hello_policy {
+            let unparsable = match (&self.unparsable_hello, &self.fallback) {
+                (Some(action), _) => action,
+                (None, Some(_)) => &HelloAction::Accept,
+                (None, None) => &HelloAction::Reject,
+            };
+            match hello::screen(policy, unparsable, &mut stream, &mut preread).await? {
+                HelloAction::Accept => (),
+                HelloAction::Route(name) => route = Some(name),
+                HelloAction::Reject => {
+                    let alert = record::plaintext_alert(AlertDescription::HandshakeFailure);
+                    let _ = stream.write_all(&alert).await;
+                    let _ = stream.shutdown().await;
+                    return Err(turned_away("client turned away by the ClientHello policy"));
+                }
+                HelloAction::Tarpit => {
+                    self.tarpit.hold(&mut stream).await;
+                    return Err(turned_away("client tarpitted by the ClientHello policy"));
+                }
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            if !fallback.authenticate(&mut stream, &mut preread).await? {
+                let err = io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "unauthenticated client relayed to the fallback backend",
+                );
+                return Ok(Accepted::Relayed(Relay {
+                    io: stream,
+                    backend: fallback.backend.clone(),
+                    sent: preread,
+                    error: err.into(),
+                }));
+            }
+        }
+        let started = self
+            .start_session(&mut stream, preread, route.as_deref())
+            .await?;
+        let allowed = started.allowed;
+        let mut stream = self.new_stream(stream, started.session);
+        stream.memory = memory;
+        stream.account_read(&started.read);
+        stream.proxy_header = proxy_header;
+        let handshake = async {
+            match started.failed {
+                Some((err, alert)) => Err(Pin::new(&mut stream).fail_handshake(err, alert).await),
+                None => Pin::new(&mut stream).handshake().await,
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "tls_accept",
+            proxy_source = ?proxy_header.map(|h| h.source)
+        );
+        #[cfg(feature = "tracing")]
+        let res = tracing::Instrument::instrument(handshake, span).await;
+        #[cfg(not(feature = "tracing"))]
+        let res = handshake.await;
+        context.server_name = stream.session.sni_hostname().map(str::to_owned);
+        if let Err(err) = res {
+            return self.failure.respond(stream, err).await;
+        }
+        stream.capture = None;
+        if stream.session.early_data().is_some() {
+            stream.early_data_accepted();
+        }
+        #[cfg(feature = "acme")]
+        if self.acme && stream.session.alpn_protocol() == Some(acme::ACME_TLS_ALPN) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "answered an ACME TLS-ALPN-01 challenge",
+            )
+            .into());
+        }
+        allowed.check(&stream)?;
+        Ok(Accepted::Stream(stream))
+    }
+
+    /// A session for a connection which already sent `preread`. With the handshake offload,
+    /// the whole ClientHello is read first and processed on the blocking pool.
+    #[cfg_attr(not(feature = "offload"), allow(unused_variables))]
+    async fn new_session<IO>(&self, io: &mut IO, preread: Vec<u8>) -> Result<Started<'_>, TlsError>
+    where
+        IO: AsyncRead + Unpin,
+    {
+        let started = |(session, failed): (ServerConnection, Option<io::Error>), read| Started {
+            session,
+            read,
+            allowed: &self.allowed,
+            failed: failed.map(|err| (err, None)),
+        };
+        #[cfg(feature = "offload")]
+        if self.offload {
+            let mut read = preread;
+            if let record::ClientHello::Complete(_) =
+                record::read_client_hello(io, &mut read).await?
+            {
+                let config = self.inner.clone();
+                let (fed, read) = tokio::task::spawn_blocking(move || {
+                    feed_session(config, &read).map(|fed| (fed, read))
+                })
+                .await
+                .map_err(io::Error::other)??;
+                return Ok(started(fed, read));
+            }
+            return Ok(started(feed_session(self.inner.clone(), &read)?, read));
+        }
+        Ok(started(
+            feed_session(self.inner.clone(), &preread)?,
+            preread,
+        ))
+    }
+
+    /// The session for a connection which already sent `preread`, with the config picked
+    /// after reading its ClientHello when there are virtual hosts or ACME challenges to
+    /// answer. `route` overrides the server name the client asked for.
+    async fn start_session<IO>(
+        &self,
+        io: &mut IO,
+        preread: Vec<u8>,
+        route: Option<&str>,
+    ) -> Result<Started<'_>, TlsError>
+    where
+        IO: AsyncRead + Unpin,
+    {
+        #[cfg(feature = "acme")]
+        let picks_config = self.acme || self.sni_hosts.is_some();
+        #[cfg(not(feature = "acme"))]
+        let picks_config = self.sni_hosts.is_some();
+        if !picks_config {
+            return self.new_session(io, preread).await;
+        }
+        let mut acceptor = rustls_fork_shadow_tls::server::Acceptor::default();
+        let mut read = preread;
+        let mut fed = 0;
+        let mut buf = [0; 4096];
+        let accepted = loop {
+            let mut rest = &read[fed..];
+            while !rest.is_empty() {
+                if let Err(err) = acceptor.read_tls(&mut rest) {
+                    return self.failed_session(read, err);
+                }
+            }
+            fed = read.len();
+            match acceptor.accept() {
+                Ok(Some(accepted)) => break accepted,
+                Ok(None) => (),
+                Err(err) => return self.failed_session(read, invalid_data(err)),
+            }
+            let n = io.read(&mut buf).await?;
+            if n == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            read.extend_from_slice(&buf[..n]);
+        };
+        let (config, allowed) = self.pick_config(&accepted.client_hello(), route);
+        match accepted.into_connection(config) {
+            Ok(session) => Ok(Started {
+                session,
+                read,
+                allowed,
+                failed: None,
+            }),
+            Err(err) => self.failed_session(read, invalid_data(err)),
+        }
+    }
+
+    /// A session to fail the handshake with `err` on, for a connection whose ClientHello the
+    /// rustls acceptor gave up on after reading `read`. That session is gone, so the alert
+    /// is sent as plaintext.
+    fn failed_session(&self, read: Vec<u8>, err: io::Error) -> Result<Started<'_>, TlsError> {
+        let alert = match err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<rustls_fork_shadow_tls::Error>())
+        {
+            Some(rustls_fork_shadow_tls::Error::CorruptMessage)
+            | Some(rustls_fork_shadow_tls::Error::CorruptMessagePayload(_)) => {
+                AlertDescription::DecodeError
+            }
+            _ => AlertDescription::HandshakeFailure,
+        };
+        Ok(Started {
+            session: ServerConnection::new(self.inner.clone())?,
+            read,
+            allowed: &self.allowed,
+            failed: Some((err, Some(alert))),
+        })
+    }
+
+    /// The config for a client sending `hello`, or routed to the virtual host `route`, and
+    /// what its session may negotiate.
+    fn pick_config(
+        &self,
+        hello: &ClientHello<'_>,
+        route: Option<&str>,
+    ) -> (Arc<ServerConfig>, &Allowed) {
+        #[cfg(feature = "acme")]
+        if self.acme && acme::is_validation(hello) {
+            let mut config = (*self.inner).clone();
+            config.alpn_protocols = vec![acme::ACME_TLS_ALPN.to_vec()];
+            return (Arc::new(config), &self.allowed);
+        }
+        let host = route
+            .or(hello.server_name())
+            .and_then(|name| self.sni_hosts.as_ref()?.get(name));
+        match host {
+            Some(host) => (host.config.clone(), &host.allowed),
+            None => (self.inner.clone(), &self.allowed),
+        }
+    }
+}
+
+/// The session of an accepted connection, before its stream is built.
+struct Started<'a> {
+    session: ServerConnection,
+    /// All the ciphertext read.
+    read: Vec<u8>,
+    /// What the session may negotiate.
+    allowed: &'a Allowed,
+    /// The error the session failed on while reading `read`, and the alert to send when the
+    /// session doesn't hold one, for the handshake to fail with once the stream is built.
+    failed: Option<(io::Error, Option<AlertDescription>)>,
+}
+
+/// The error `accept` fails with for a client the acceptor turned away.
+fn turned_away(message: &str) -> TlsError {
+    io::Error::new(io::ErrorKind::ConnectionAborted, message).into()
+}
+
+/// A rustls error as the stream reports it.
+fn invalid_data(err: rustls_fork_shadow_tls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// A session for a connection which already sent `ciphertext`, and the error it failed on
+/// while reading it, if any.
+fn feed_session(
+    config: Arc<ServerConfig>,
+    ciphertext: &[u8],
+) -> Result<(ServerConnection, Option<io::Error>), TlsError> {
+    let mut session = ServerConnection::new(config)?;
+    let mut rest = ciphertext;
+    while !rest.is_empty() {
+        if let Err(err) = session.read_tls(&mut rest) {
+            return Ok((session, Some(err)));
+        }
+        if let Err(err) = session.process_new_packets() {
+            return Ok((session, Some(invalid_data(err))));
+        }
+    }
+    Ok((session, None))
+}
+
+impl<IO> TlsStream<IO> {
+    /// Original client and destination addresses from the PROXY protocol header, if the
+    /// acceptor expects one and the header carried addresses.
+    pub fn proxy_header(&self) -> Option<&ProxyHeader> {
+        self.proxy_header.as_ref()
     }
 }
@@ -0,0 +1,81 @@
+//! tower `Service` wrapper around `TlsConnector`.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
+use rustls_fork_shadow_tls::{ClientConfig, ServerName};
+
+use crate::{client::TlsStream, TlsConnector, TlsError};
+
+/// A `tower::Service<(ServerName, IO)>` which performs the client handshake over `IO`.
+///
+/// Created with [`TlsConnectService::with_reload`], the service picks up configs published
+/// on the watch channel in `poll_ready`, so every call after a reload uses the new config.
+#[derive(Clone)]
+pub struct TlsConnectService {
+    connector: TlsConnector,
+    reload: Option<watch::Receiver<Arc<ClientConfig>>>,
+}
+
+impl TlsConnectService {
+    pub fn new(connector: TlsConnector) -> Self {
+        Self {
+            connector,
+            reload: None,
+        }
+    }
+
+    /// Use the config in `reload` and follow its updates.
+    pub fn with_reload(
+        connector: TlsConnector,
+        mut reload: watch::Receiver<Arc<ClientConfig>>,
+    ) -> Self {
+        let config = reload.borrow_and_update().clone();
+        Self {
+            connector: connector.with_config(config),
+            reload: Some(reload),
+        }
+    }
+
+    pub fn connector(&self) -> &TlsConnector {
+        &self.connector
+    }
+}
+
+impl From<TlsConnector> for TlsConnectService {
+    fn from(connector: TlsConnector) -> Self {
+        Self::new(connector)
+    }
+}
+
+impl<IO> tower_service::Service<(ServerName, IO)> for TlsConnectService
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Response = TlsStream<IO>;
+    type Error = TlsError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Some(reload) = self.reload.as_mut() {
+            // A closed channel keeps the last published config.
+            if reload.has_changed().unwrap_or(false) {
+                let config = reload.borrow_and_update().clone();
+                self.connector = self.connector.with_config(config);
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, (domain, io): (ServerName, IO)) -> Self::Future {
+        let connector = self.connector.clone();
+        Box::pin(async move { connector.connect(domain, io).await })
+    }
+}
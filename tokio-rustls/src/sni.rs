@@ -0,0 +1,35 @@
+//! Virtual hosts picked by the SNI of the ClientHello.
+use std::{collections::HashMap, sync::Arc};
+
+use rustls_fork_shadow_tls::ServerConfig;
+
+use crate::negotiated::Allowed;
+
+/// The config and limits of one virtual host.
+#[derive(Clone)]
+pub(crate) struct Host {
+    pub(crate) config: Arc<ServerConfig>,
+    pub(crate) allowed: Allowed,
+}
+
+/// Virtual hosts by server name, lowercase. Names starting with `*.` match any one label in
+/// their place.
+#[derive(Clone, Default)]
+pub(crate) struct SniHosts {
+    hosts: HashMap<String, Host>,
+}
+
+impl SniHosts {
+    pub(crate) fn insert(&mut self, server_name: &str, host: Host) {
+        self.hosts.insert(server_name.to_ascii_lowercase(), host);
+    }
+
+    /// The host for `server_name`: the one of that name, or else of its wildcard.
+    pub(crate) fn get(&self, server_name: &str) -> Option<&Host> {
+        let name = server_name.to_ascii_lowercase();
+        self.hosts.get(&name).or_else(|| {
+            let (_, parent) = name.split_once('.')?;
+            self.hosts.get(&format!("*.{parent}"))
+        })
+    }
+}
@@ -3,28 +3,67 @@
 //! Though it is not a good assumption, it can really make it
 //! more efficient with less code. The read and write will not
 //! interfere each other.
+//!
+//! Shutting the write half down ends the read half as well: once the close_notify has been
+//! sent and the IO shut down, a read blocked on the read half is woken and every read returns
+//! `Ok(0)`, whatever the peer sends afterwards. A read half set up
+//! [`with_reads_after_shutdown`](ReadHalf::with_reads_after_shutdown) keeps reading instead,
+//! until the peer's close_notify or the end of the IO, for protocols where the peer answers a
+//! half-close.
+//!
+//! Each half keeps the waker of its pending operation in the shared stream, so progress made
+//! by one half wakes the other: a read which leaves ciphertext for the peer (a key update or
+//! an alert) wakes the write half, and a write which completes wakes the read half.
 use std::{
     cell::UnsafeCell,
-    future::Future,
     io::IoSlice,
     ops::{Deref, DerefMut},
     pin::Pin,
     rc::Rc,
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
 
-use tokio::{
-    pin,
-    io::{AsyncRead, AsyncWrite, ReadBuf}
-};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 use rustls_fork_shadow_tls::{ConnectionCommon, SideData};
 
 use crate::stream::Stream;
 
+/// Wakers of the operations the split halves are waiting on.
+#[derive(Debug, Default)]
+pub(crate) struct Wakers {
+    read: Option<Waker>,
+    write: Option<Waker>,
+}
+
+impl Wakers {
+    fn register(slot: &mut Option<Waker>, waker: &Waker) {
+        if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    fn wake(slot: &mut Option<Waker>) {
+        if let Some(waker) = slot.take() {
+            waker.wake();
+        }
+    }
+
+    /// Keep the waker of a pending write half operation, or wake the read half once one
+    /// completes.
+    fn after_write(&mut self, cx: &Context<'_>, pending: bool) {
+        match pending {
+            true => Self::register(&mut self.write, cx.waker()),
+            false => Self::wake(&mut self.read),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ReadHalf<IO, C> {
     pub(crate) inner: Rc<UnsafeCell<Stream<IO, C>>>,
+    /// Keep reading once the write half has shut down.
+    pub(crate) reads_after_shutdown: bool,
 }
 
 #[derive(Debug)]
@@ -43,13 +82,29 @@ where
         buf: &mut ReadBuf<'_>
     ) -> Poll<std::io::Result<()>> {
         let inner = unsafe { &mut *self.inner.get() };
-        let ex = inner.read_inner(buf, true);
-        pin!(ex);
-        ex.poll(cx)
+        if inner.shut_down && !self.reads_after_shutdown {
+            return Poll::Ready(Ok(()));
+        }
+        let res = inner.poll_read_inner(cx, buf, true);
+        if res.is_pending() {
+            Wakers::register(&mut inner.wakers.read, cx.waker());
+        }
+        // The read half never writes, the write half has to send what the read left behind.
+        if inner.session.wants_write() {
+            Wakers::wake(&mut inner.wakers.write);
+        }
+        res
     }
 }
 
 impl<IO, C> ReadHalf<IO, C> {
+    /// Keep reading what the peer sends after the write half has shut down, rather than
+    /// returning `Ok(0)` from then on.
+    pub fn with_reads_after_shutdown(mut self, enabled: bool) -> Self {
+        self.reads_after_shutdown = enabled;
+        self
+    }
+
     pub fn reunite(self, other: WriteHalf<IO, C>) -> Result<Stream<IO, C>, ReuniteError<IO, C>> {
         reunite(self, other)
     }
@@ -66,7 +121,9 @@ where
         buf: &[u8]
     ) -> Poll<std::io::Result<usize>> {
         let inner = unsafe { &mut *self.inner.get() };
-        Pin::new(inner).poll_write(cx, buf)
+        let res = Pin::new(&mut *inner).poll_write(cx, buf);
+        inner.wakers.after_write(cx, res.is_pending());
+        res
     }
 
     fn poll_write_vectored(
@@ -75,7 +132,9 @@ where
         bufs: &[IoSlice<'_>]
     ) -> Poll<std::io::Result<usize>> {
         let inner = unsafe { &mut *self.inner.get() };
-        Pin::new(inner).poll_write_vectored(cx, bufs)
+        let res = Pin::new(&mut *inner).poll_write_vectored(cx, bufs);
+        inner.wakers.after_write(cx, res.is_pending());
+        res
     }
 
     fn poll_flush(
@@ -83,7 +142,9 @@ where
         cx: &mut Context<'_>
     ) -> Poll<std::io::Result<()>> {
         let inner = unsafe { &mut *self.inner.get() };
-        Pin::new(inner).poll_flush(cx)
+        let res = Pin::new(&mut *inner).poll_flush(cx);
+        inner.wakers.after_write(cx, res.is_pending());
+        res
     }
 
     fn poll_shutdown(
@@ -91,7 +152,9 @@ where
         cx: &mut Context<'_>
     ) -> Poll<std::io::Result<()>> {
         let inner = unsafe { &mut *self.inner.get() };
-        Pin::new(inner).poll_shutdown(cx)
+        let res = Pin::new(&mut *inner).poll_shutdown(cx);
+        inner.wakers.after_write(cx, res.is_pending());
+        res
     }
 
     fn is_write_vectored(&self) -> bool {
@@ -124,16 +187,34 @@ pub(crate) fn reunite<IO, C>(
 
 /// Error indicating that two halves were not from the same socket, and thus could
 /// not be reunited.
-#[derive(Debug)]
 pub struct ReuniteError<IO, C>(pub ReadHalf<IO, C>, pub WriteHalf<IO, C>);
 
-impl<IO: std::fmt::Debug, C: std::fmt::Debug> std::fmt::Display for ReuniteError<IO, C> {
+impl<IO, C> ReuniteError<IO, C> {
+    /// Take back the halves which were passed to `reunite`.
+    pub fn into_halves(self) -> (ReadHalf<IO, C>, WriteHalf<IO, C>) {
+        (self.0, self.1)
+    }
+}
+
+impl<IO, C> std::fmt::Debug for ReuniteError<IO, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReuniteError")
+            .field("read_stream", &Rc::as_ptr(&self.0.inner))
+            .field("write_stream", &Rc::as_ptr(&self.1.inner))
+            .finish()
+    }
+}
+
+impl<IO, C> std::fmt::Display for ReuniteError<IO, C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "tried to reunite halves that are not from the same socket"
+            "tried to reunite halves that are not from the same socket (read half of stream \
+             {:p}, write half of stream {:p})",
+            Rc::as_ptr(&self.0.inner),
+            Rc::as_ptr(&self.1.inner),
         )
     }
 }
 
-impl<IO: std::fmt::Debug, C: std::fmt::Debug> std::error::Error for ReuniteError<IO, C> {}
+impl<IO, C> std::error::Error for ReuniteError<IO, C> {}
@@ -2,20 +2,116 @@ use std::{
     cell::UnsafeCell,
     future::Future,
     io::{IoSlice, Read, self, Write},
+    mem::ManuallyDrop,
     ops::{Deref, DerefMut},
     pin::Pin,
     rc::Rc,
-    task::{Context, Poll},
+    sync::Arc,
+    task::{ready, Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
 };
 
 use tokio::{
     pin,
-    io::{AsyncRead, AsyncWrite, ReadBuf}
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    runtime::Handle,
+    sync::OwnedSemaphorePermit,
 };
 
-use rustls_fork_shadow_tls::{ConnectionCommon, SideData};
+use pin_project::{pin_project, pinned_drop};
+use rustls_fork_shadow_tls::{
+    AlertDescription, ConnectionCommon, NamedGroup, ProtocolVersion, SideData,
+};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::StreamMetrics;
+use crate::{
+    flush::DeferredFlush,
+    memory::MemoryCharge,
+    observer::ObserverSlot,
+    proxy_protocol::ProxyHeader,
+    record::{self, RecordScanner, TapRead, TapWrite},
+    split::{ReadHalf, Wakers, WriteHalf},
+    tap::{Direction, RecordTap, TapSlot},
+    throttle::{RateLimit, RateLimiter},
+    timeout::IdleTimer,
+};
+
+/// How long a graceful close spawned on drop may take, when the stream has no write timeout.
+const GRACEFUL_CLOSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Passes through an IO loop without progress after which the IO is taken to be broken. One
+/// which is ready without transferring anything, e.g. returns `WouldBlock` where it should
+/// register a waker and return `Pending`, would otherwise spin forever.
+const MAX_SPINS: u32 = 64;
+
+/// Counts the passes through an IO loop which made no progress.
+#[derive(Default)]
+struct SpinGuard {
+    spins: u32,
+}
+
+impl SpinGuard {
+    /// Count a pass through `op` which transferred nothing, failing after `MAX_SPINS` of them.
+    /// Debug builds panic instead, as such an IO is a bug.
+    fn spin(&mut self, op: &str) -> io::Result<()> {
+        self.spins += 1;
+        debug_assert!(
+            self.spins < MAX_SPINS,
+            "tls {op} spinning: the IO is ready without transferring anything"
+        );
+        if self.spins < MAX_SPINS {
+            return Ok(());
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(op, "tls IO is ready without transferring anything");
+        Err(io::Error::other(format!(
+            "tls {op} made no progress in {MAX_SPINS} passes: the IO is ready without \
+             transferring anything, or returns WouldBlock instead of Pending"
+        )))
+    }
+}
+
+/// `io::Write` which takes nothing, measuring the ciphertext rustls offers it.
+#[derive(Default)]
+struct Measure(usize);
+
+impl Write for Measure {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.0 += bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The ciphertext `session` holds for the IO, up to the 64 records it hands out at once,
+/// leaving it all in place.
+fn tls_bytes_to_write<SD: SideData>(session: &mut ConnectionCommon<SD>) -> usize {
+    let mut measure = Measure::default();
+    let _ = session.write_tls(&mut measure);
+    measure.0
+}
+
+/// The IO of a projected stream, pinned along with it.
+fn pin_io<IO>(io: Pin<&mut ManuallyDrop<IO>>) -> Pin<&mut IO> {
+    // SAFETY: the IO is never moved out of a pinned stream unless it is `Unpin`.
+    unsafe { io.map_unchecked_mut(|io| &mut **io) }
+}
 
-use crate::split::{ReadHalf, WriteHalf};
+/// Wakes nothing, for the writes of a drop, which doesn't wait on them.
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
 
 #[derive(Debug)]
 enum WriteStatus {
@@ -23,10 +119,91 @@ enum WriteStatus {
     Pending(usize)
 }
 
+/// I/O statistics of a stream, see [`Stream::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Decrypted bytes returned to the application.
+    pub plaintext_read: u64,
+    /// Bytes accepted from the application for encryption.
+    pub plaintext_written: u64,
+    /// Bytes read from the underlying IO, including the handshake.
+    pub ciphertext_read: u64,
+    /// Bytes written to the underlying IO, including the handshake.
+    pub ciphertext_written: u64,
+    pub records_read: u64,
+    pub records_written: u64,
+    /// Time taken by the handshake, once it has finished.
+    pub handshake_duration: Option<Duration>,
+}
+
+/// How a completed handshake authenticated the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeKind {
+    /// A full handshake, with the server's certificate.
+    Full,
+    /// A previous session was resumed.
+    Resumed,
+    /// A TLS 1.3 session was resumed and the server accepted 0-RTT early data.
+    EarlyData,
+}
+
+/// What it took to complete a handshake, see [`Stream::handshake_summary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeSummary {
+    /// Ciphertext read from the IO during the handshake.
+    pub bytes_read: u64,
+    /// Ciphertext written to the IO during the handshake.
+    pub bytes_written: u64,
+    pub duration: Duration,
+    /// Whether a previous session was resumed, i.e. `kind` is not `Full`.
+    pub resumed: bool,
+    pub kind: HandshakeKind,
+}
+
+/// What dropping an established stream does about closing TLS, set with
+/// [`Stream::set_close_on_drop`]. Streams which were shut down, handed raw bytes or are still
+/// handshaking are left alone.
+#[derive(Debug, Clone, Default)]
+pub enum CloseOnDrop {
+    /// Drop the IO as is. The peer sees the connection end without a close_notify, which it
+    /// may take for a truncation.
+    #[default]
+    Nothing,
+    /// Send a close_notify, after the records still buffered, as far as the IO takes them
+    /// without waiting. The rest is dropped.
+    SendCloseNotifyBestEffort,
+    /// Move the IO and the connection to a task spawned on the runtime, which writes what is
+    /// buffered, sends a close_notify and shuts the IO down. The stream's share of the memory
+    /// budget and its connection slot go with them, until the task is done. The write timeout
+    /// of the stream still applies; without one, the task gives up after 30 seconds.
+    SpawnGracefulClose(Handle),
+}
+
+/// The [`CloseOnDrop`] of a stream, with the function applying it.
+struct OnDrop<IO, C> {
+    policy: CloseOnDrop,
+    /// Made by `set_close_on_drop`, where the bounds closing takes are known; the drop has
+    /// none.
+    close: fn(&mut StreamProj<'_, IO, C>, CloseOnDrop),
+}
+
+impl<IO, C> std::fmt::Debug for OnDrop<IO, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnDrop")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The stream is `Unpin` when the IO is; a pinned stream works over `!Unpin` IO as well. It
+/// is `Send` when the IO and the connection are.
+#[pin_project(PinnedDrop, project = StreamProj)]
 #[derive(Debug)]
 pub struct Stream<IO, C> {
-    pub(crate) io: IO,
-    pub(crate) session: C,
+    /// Dropped by hand, after the close on drop, unless moved out by `into_inner`.
+    #[pin]
+    pub(crate) io: ManuallyDrop<IO>,
+    pub(crate) session: ManuallyDrop<C>,
     #[cfg(not(feature = "unsafe_io"))]
     r_buffer: crate::safe_io::SafeRead,
     #[cfg(not(feature = "unsafe_io"))]
@@ -38,18 +215,100 @@ pub struct Stream<IO, C> {
     write_status: WriteStatus,
     flush_status: WriteStatus,
     close_status: WriteStatus,
+    pub(crate) proxy_header: Option<ProxyHeader>,
+    pub(crate) ocsp_response: Option<Vec<u8>>,
+    pub(crate) scts: Vec<crate::ct::Sct>,
+    stats: Stats,
+    pub(crate) handshake_summary: Option<HandshakeSummary>,
+    peer_closed: bool,
+    read_records: RecordScanner,
+    write_records: RecordScanner,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<StreamMetrics>,
+    pub(crate) observer: Option<ObserverSlot>,
+    rate_limiter: Option<RateLimiter>,
+    read_timer: IdleTimer,
+    write_timer: IdleTimer,
+    /// The close_notify has been sent and the IO shut down.
+    pub(crate) shut_down: bool,
+    /// Tasks of split halves waiting on the stream.
+    pub(crate) wakers: Wakers,
+    /// Copy of the ciphertext read, kept while a failed handshake may be relayed elsewhere.
+    pub(crate) capture: Option<Vec<u8>>,
+    /// Whether a fatal alert is sent when the peer's records are rejected.
+    pub(crate) send_alerts: bool,
+    /// Connection slot of the listener which accepted the stream, freed on drop.
+    pub(crate) permit: Option<OwnedSemaphorePermit>,
+    /// Share of the memory budget of the connector or acceptor, given back on drop.
+    pub(crate) memory: Option<MemoryCharge>,
+    /// Set when writes wait for a time slice of a `FlushScheduler` to go out.
+    pub(crate) deferred_flush: Option<DeferredFlush>,
+    /// Raw bytes were written after the records, so no record may follow.
+    raw_written: bool,
+    /// Reads stop at the peer's close_notify, for `stop_tls`.
+    stopping: bool,
+    /// Plaintext taken from rustls by `peek`, which reads return first.
+    peeked: Vec<u8>,
+    /// Plaintext rustls held for reading after it last processed records, less what the
+    /// stream has read from it since.
+    plaintext_unread: usize,
+    /// Plaintext accepted during the handshake which rustls holds back until it finishes.
+    plaintext_held: usize,
+    /// What dropping the stream does about the TLS close, with the function doing it.
+    on_drop: Option<OnDrop<IO, C>>,
+    /// The IO and the connection have been moved out, so the drop leaves them alone.
+    parts_taken: bool,
 }
 
+/// `Stream` is `Send` when its IO and connection are, so it can be moved into spawned tasks;
+/// this fails to compile when a field breaks that. The split halves share the stream through
+/// an `Rc` and are never `Send`.
+#[allow(dead_code)]
+const _: () = {
+    fn assert_send<T: Send>() {}
+    fn stream_is_send<IO: Send, C: Send>() {
+        assert_send::<Stream<IO, C>>();
+    }
+};
+
 impl<IO, C> Stream<IO, C> {
     pub fn new(io: IO, session: C) -> Self {
         Self {
-            io,
-            session,
+            io: ManuallyDrop::new(io),
+            session: ManuallyDrop::new(session),
             r_buffer: Default::default(),
             w_buffer: Default::default(),
             write_status: WriteStatus::Ok,
             flush_status: WriteStatus::Ok,
             close_status: WriteStatus::Ok,
+            proxy_header: None,
+            ocsp_response: None,
+            scts: Vec::new(),
+            stats: Stats::default(),
+            handshake_summary: None,
+            peer_closed: false,
+            read_records: Default::default(),
+            write_records: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            observer: None,
+            rate_limiter: None,
+            read_timer: IdleTimer::default(),
+            write_timer: IdleTimer::default(),
+            shut_down: false,
+            wakers: Wakers::default(),
+            capture: None,
+            send_alerts: true,
+            permit: None,
+            memory: None,
+            deferred_flush: None,
+            raw_written: false,
+            stopping: false,
+            peeked: Vec::new(),
+            plaintext_unread: 0,
+            plaintext_held: 0,
+            on_drop: None,
+            parts_taken: false,
         }
     }
 
@@ -58,13 +317,259 @@ impl<IO, C> Stream<IO, C> {
         (
             ReadHalf {
                 inner: shared.clone(),
+                reads_after_shutdown: false,
             },
             WriteHalf { inner: shared },
         )
     }
 
-    pub fn into_inner(self) -> (IO, C) {
-        (self.io, self.session)
+    pub fn into_inner(mut self) -> (IO, C) {
+        self.parts_taken = true;
+        // SAFETY: `parts_taken` keeps the drop from dropping them again. The stream is owned,
+        // so the IO is not pinned.
+        unsafe {
+            (
+                ManuallyDrop::take(&mut self.io),
+                ManuallyDrop::take(&mut self.session),
+            )
+        }
+    }
+
+    /// The underlying IO, e.g. to read the peer address or set socket options.
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// The underlying IO. Reading from or writing to it directly corrupts the TLS stream.
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
+
+    /// The rustls connection.
+    pub fn session(&self) -> &C {
+        &self.session
+    }
+
+    pub fn session_mut(&mut self) -> &mut C {
+        &mut self.session
+    }
+
+    /// Bytes and records transferred so far, and the handshake duration.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            records_read: self.read_records.records,
+            records_written: self.write_records.records,
+            ..self.stats
+        }
+    }
+
+    /// Bytes exchanged, time taken and kind of the handshake, once it has finished.
+    pub fn handshake_summary(&self) -> Option<HandshakeSummary> {
+        self.handshake_summary
+    }
+
+    /// Whether the handshake was full, resumed a session or had 0-RTT data accepted. `None`
+    /// until it has finished.
+    pub fn handshake_kind(&self) -> Option<HandshakeKind> {
+        self.handshake_summary.map(|summary| summary.kind)
+    }
+
+    /// Whether the finished handshake resumed a previous session.
+    pub fn is_handshake_resumed(&self) -> bool {
+        self.handshake_summary.is_some_and(|summary| summary.resumed)
+    }
+
+    /// Fail reads with `TimedOut` when they wait for longer than `timeout` for data. `None`,
+    /// the default, waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timer.set_timeout(timeout);
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timer.timeout()
+    }
+
+    /// Fail writes, flushes and shutdowns with `TimedOut` when the IO accepts nothing for
+    /// longer than `timeout`. `None`, the default, waits forever.
+    ///
+    /// Plaintext passed to a write which timed out may already be queued in the session; a
+    /// retry of the same write then reports it as written.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timer.set_timeout(timeout);
+    }
+
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timer.timeout()
+    }
+
+    /// Handle to limit the bandwidth of this stream. The stream is unlimited until rates are
+    /// set on the handle.
+    pub fn rate_limit(&mut self) -> RateLimit {
+        self.rate_limiter
+            .get_or_insert_with(|| RateLimiter::new(RateLimit::default()))
+            .handle()
+            .clone()
+    }
+
+    /// Settle a write abandoned after the session took its plaintext, which goes out with the
+    /// next write or flush. Returns the length of that plaintext.
+    pub(crate) fn take_pending_write(&mut self) -> usize {
+        let n = match self.write_status {
+            WriteStatus::Ok => 0,
+            WriteStatus::Pending(n) => n,
+        };
+        self.write_status = WriteStatus::Ok;
+        self.stats.plaintext_written += n as u64;
+        #[cfg(feature = "metrics")]
+        if let Some(m) = &self.metrics {
+            m.metrics.encrypted(n);
+        }
+        n
+    }
+
+    /// Mark the handshake as having had 0-RTT data accepted, which only the connection of
+    /// either side knows.
+    pub(crate) fn early_data_accepted(&mut self) {
+        if let Some(summary) = &mut self.handshake_summary {
+            summary.kind = HandshakeKind::EarlyData;
+        }
+    }
+
+    /// Count `plaintext` written to the session directly.
+    pub(crate) fn account_written(&mut self, plaintext: usize) {
+        self.stats.plaintext_written += plaintext as u64;
+    }
+
+    /// Count `plaintext` written to the session directly before the handshake, which rustls
+    /// holds back until it finishes.
+    pub(crate) fn account_held(&mut self, plaintext: usize) {
+        self.account_written(plaintext);
+        self.plaintext_held += plaintext;
+    }
+
+    /// Count `ciphertext` the session was fed before the stream was built.
+    pub(crate) fn account_read(&mut self, ciphertext: &[u8]) {
+        self.read_records.feed(ciphertext);
+        if let Some(capture) = self.capture.as_mut() {
+            capture.extend_from_slice(ciphertext);
+        }
+        self.stats.ciphertext_read += ciphertext.len() as u64;
+    }
+}
+
+/// The rustls connection, e.g. a `ClientConnection` or `ServerConnection`, for its APIs the
+/// stream doesn't wrap. Same as [`Stream::session`] and [`Stream::session_mut`].
+impl<IO, C> AsRef<C> for Stream<IO, C> {
+    fn as_ref(&self) -> &C {
+        &self.session
+    }
+}
+
+impl<IO, C> AsMut<C> for Stream<IO, C> {
+    fn as_mut(&mut self) -> &mut C {
+        &mut self.session
+    }
+}
+
+impl<IO, C, SD: SideData> Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    /// The protocol version the handshake settled on, or `None` before it got that far.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.session.protocol_version()
+    }
+
+    /// Swap the underlying IO for `io`, keeping the TLS session, e.g. to carry it over a
+    /// reconnected transport. Returns the previous IO.
+    ///
+    /// Only possible between reads and writes, with no ciphertext buffered in either direction
+    /// and no write, flush or shutdown left pending; otherwise `io` is given back as the
+    /// error. Records the peer sent on the previous IO which were not read are lost, and the
+    /// session breaks if the peer doesn't carry on from the same point.
+    pub fn replace_io(&mut self, io: IO) -> Result<IO, IO> {
+        let idle = matches!(
+            (&self.write_status, &self.flush_status, &self.close_status),
+            (WriteStatus::Ok, WriteStatus::Ok, WriteStatus::Ok)
+        );
+        #[cfg(not(feature = "unsafe_io"))]
+        let drained = self.r_buffer.is_empty() && self.w_buffer.is_empty();
+        #[cfg(feature = "unsafe_io")]
+        let drained = true;
+        if !idle || !drained || self.session.wants_write() {
+            return Err(io);
+        }
+        self.r_buffer.reset();
+        self.w_buffer.reset();
+        Ok(std::mem::replace(&mut *self.io, io))
+    }
+
+    /// Ciphertext not handed to the IO yet: records rustls has encrypted and, with the
+    /// `safe_io` buffers, bytes the IO hasn't taken. rustls hands out at most 64 records at
+    /// a time, so only the first 64 it holds are counted. Takes `&mut self` because rustls
+    /// only lends its records out mutably; none are taken.
+    pub fn ciphertext_buffered(&mut self) -> usize {
+        let session = tls_bytes_to_write(&mut self.session);
+        #[cfg(not(feature = "unsafe_io"))]
+        let buffered = self.w_buffer.len();
+        #[cfg(feature = "unsafe_io")]
+        let buffered = 0;
+        session + buffered
+    }
+
+    /// Plaintext rustls has decrypted and holds for reading, so a read returns it without
+    /// waiting on the IO. Ciphertext not decrypted yet is not counted, nor plaintext read
+    /// through [`session_mut`](Self::session_mut).
+    pub fn plaintext_available(&self) -> usize {
+        self.plaintext_unread + self.peeked.len()
+    }
+
+    /// Plaintext accepted by writes which rustls holds back, unencrypted, until the handshake
+    /// finishes.
+    pub fn plaintext_buffered(&self) -> usize {
+        match self.session.is_handshaking() {
+            true => self.plaintext_held,
+            false => 0,
+        }
+    }
+
+    /// Take the ciphertext read from the IO which rustls hasn't been given yet. During the
+    /// handshake rustls is given one record at a time, so right after it this holds whatever
+    /// the peer sent past its last handshake record, e.g. the first records of a shadow-tls
+    /// client to relay instead of decrypting. Reads then continue from the IO.
+    ///
+    /// Always empty with `unsafe_io`, which reads into rustls' buffer directly: the bytes
+    /// past the handshake are still in the IO.
+    pub fn take_buffered_ciphertext(&mut self) -> Vec<u8> {
+        #[cfg(not(feature = "unsafe_io"))]
+        let buffered = self.r_buffer.take();
+        #[cfg(feature = "unsafe_io")]
+        let buffered = Vec::new();
+        buffered
+    }
+
+    /// Report every record read or written from now on to `tap`, after the taps added before.
+    pub(crate) fn add_record_tap(&mut self, tap: Arc<dyn RecordTap>) {
+        self.read_records
+            .taps
+            .push(TapSlot::new(tap.clone(), Direction::Inbound));
+        self.write_records
+            .taps
+            .push(TapSlot::new(tap, Direction::Outbound));
+    }
+
+    /// The handshake messages scanned in the records read and in those written.
+    pub(crate) fn hellos(&self) -> (&record::HandshakeScanner, &record::HandshakeScanner) {
+        (&self.read_records.handshake, &self.write_records.handshake)
+    }
+
+    /// The group of the (EC)DHE key exchange, once the server has picked it. `None` for
+    /// resumed TLS 1.2 sessions, which have no key exchange.
+    pub fn kx_group(&self) -> Option<NamedGroup> {
+        self.read_records
+            .handshake
+            .kx_group
+            .or(self.write_records.handshake.kx_group)
     }
 }
 
@@ -72,35 +577,264 @@ impl<IO: AsyncRead + AsyncWrite + Unpin, C, SD: SideData> Stream<IO, C>
 where
     C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
 {
-    pub(crate) async fn read_io(&mut self, splitted: bool) -> io::Result<usize> {
+    pub(crate) fn poll_read_inner(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+        splitted: bool,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(self).project().poll_read_inner(cx, buf, splitted)
+    }
+
+    /// Close TLS in both directions and hand back the IO for plaintext, as FTPS does on
+    /// `CCC`: send a close_notify, then read up to the peer's. Returns the IO with the bytes
+    /// the peer already sent after its close_notify, which the plaintext protocol goes on
+    /// with.
+    ///
+    /// Fails with `InvalidData` when application data is left to read or arrives before the
+    /// close_notify. A close_notify read before the call may have taken the bytes after it
+    /// along, so call it before reading the peer's.
+    pub async fn stop_tls(mut self) -> io::Result<(IO, Vec<u8>)> {
+        Pin::new(&mut self).project().stop_tls().await?;
+        let rest = self.take_buffered_ciphertext();
+        let (io, _) = self.into_inner();
+        Ok((io, rest))
+    }
+}
+
+impl<IO, C, SD> Stream<IO, C>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>> + Send + 'static,
+    SD: SideData + 'static,
+{
+    /// What dropping the stream does about closing TLS. [`CloseOnDrop::Nothing`] by default.
+    pub fn set_close_on_drop(&mut self, policy: CloseOnDrop) {
+        self.on_drop = match policy {
+            CloseOnDrop::Nothing => None,
+            policy => Some(OnDrop {
+                policy,
+                close: close_on_drop,
+            }),
+        };
+    }
+}
+
+/// Close a dropped stream as `policy` says.
+fn close_on_drop<IO, C, SD>(stream: &mut StreamProj<'_, IO, C>, policy: CloseOnDrop)
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>> + Send + 'static,
+    SD: SideData + 'static,
+{
+    if *stream.shut_down
+        || *stream.raw_written
+        || *stream.stopping
+        || stream.session.is_handshaking()
+    {
+        return;
+    }
+    match policy {
+        CloseOnDrop::Nothing => (),
+        CloseOnDrop::SendCloseNotifyBestEffort => {
+            stream.session.send_close_notify();
+            let waker = Waker::from(Arc::new(NoopWaker));
+            let mut cx = Context::from_waker(&waker);
+            while stream.wants_write() {
+                let write = stream.write_io();
+                pin!(write);
+                match write.poll(&mut cx) {
+                    Poll::Ready(Ok(n)) if n > 0 => (),
+                    // The IO would block or is broken.
+                    _ => break,
+                }
+            }
+        }
+        CloseOnDrop::SpawnGracefulClose(handle) => {
+            *stream.parts_taken = true;
+            // SAFETY: `parts_taken` keeps the drop from dropping them again. The IO is `Unpin`,
+            // so it may move.
+            let (io, session) = unsafe {
+                (
+                    ManuallyDrop::take(Pin::get_mut(stream.io.as_mut())),
+                    ManuallyDrop::take(&mut *stream.session),
+                )
+            };
+            let mut closing = Stream::new(io, session);
+            closing.w_buffer = std::mem::take(&mut *stream.w_buffer);
+            closing.write_timer = std::mem::take(&mut *stream.write_timer);
+            closing.permit = stream.permit.take();
+            closing.memory = stream.memory.take();
+            let deadline = match closing.write_timer.timeout() {
+                Some(_) => None,
+                None => Some(GRACEFUL_CLOSE_TIMEOUT),
+            };
+            handle.spawn(async move {
+                let shutdown = tokio::io::AsyncWriteExt::shutdown(&mut closing);
+                match deadline {
+                    Some(deadline) => {
+                        let _ = tokio::time::timeout(deadline, shutdown).await;
+                    }
+                    None => {
+                        let _ = shutdown.await;
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[pinned_drop]
+impl<IO, C> PinnedDrop for Stream<IO, C> {
+    fn drop(self: Pin<&mut Self>) {
+        let mut this = self.project();
+        if *this.parts_taken {
+            return;
+        }
+        if let Some(OnDrop { policy, close }) = this.on_drop.take() {
+            close(&mut this, policy);
+        }
+        if !*this.parts_taken {
+            // SAFETY: the IO and the connection are dropped once, here, and the IO in place.
+            unsafe {
+                ManuallyDrop::drop(this.io.get_unchecked_mut());
+                ManuallyDrop::drop(this.session);
+            }
+        }
+    }
+}
+
+impl<IO: AsyncRead + AsyncWrite, C, SD: SideData> Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    /// Drive the handshake to completion. Connectors and acceptors do this already; it is
+    /// only needed for a stream built with `new`, e.g. over IO which is not `Unpin`.
+    pub async fn handshake(self: Pin<&mut Self>) -> io::Result<(usize, usize)> {
+        self.project().handshake().await
+    }
+
+    /// Fail the handshake with `err`, met on the ciphertext the session was fed before the
+    /// stream was built, as a handshake failing on its own would: the alert the session holds
+    /// is sent, or `alert` when the session which met the error is gone, and observers and
+    /// metrics are told. Returns the error the handshake fails with.
+    pub(crate) async fn fail_handshake(
+        self: Pin<&mut Self>,
+        err: io::Error,
+        alert: Option<AlertDescription>,
+    ) -> io::Error {
+        self.project().fail_handshake(err, alert).await
+    }
+
+    /// Send the alert `description`, e.g. `user_canceled` or a fatal alert telling the peer
+    /// why the connection is dropped, after the records rustls still holds. Drop the stream
+    /// afterwards, rather than shutting it down, which would send a close_notify.
+    ///
+    /// The rustls fork only builds close_notify alerts itself, so other alerts are written as
+    /// plaintext records. That is what the peer expects until records are protected: up to the
+    /// ServerHello in TLS 1.3, and until this side's ChangeCipherSpec in TLS 1.2. Later on they
+    /// fail with `Unsupported` without sending anything, and only `CloseNotify` can be sent.
+    pub async fn send_alert(self: Pin<&mut Self>, description: AlertDescription) -> io::Result<()> {
+        self.project().send_alert(description).await
+    }
+
+    /// Write `data` to the IO as is, outside any record, for relays which stop speaking TLS
+    /// after the handshake. The records rustls still holds are written first, so the peer gets
+    /// them all before the raw bytes.
+    ///
+    /// Fails with `InvalidInput` until the handshake has finished. Records can't follow raw
+    /// bytes, so once any have been written, writes to the stream fail, and shutting it down
+    /// only shuts the IO down, without a close_notify.
+    pub async fn write_raw(self: Pin<&mut Self>, data: &[u8]) -> io::Result<()> {
+        self.project().write_raw(data).await
+    }
+
+    /// Read plaintext into `buf` without consuming it: the next reads return it again, e.g. to
+    /// tell which protocol a client speaks before handing the stream to its handler. Waits
+    /// until some plaintext is available, then returns up to `buf.len()` bytes of what is
+    /// decrypted already, so fewer than a protocol needs may come back; peek again to wait for
+    /// more. Returns 0 once the peer has closed.
+    pub async fn peek(self: Pin<&mut Self>, buf: &mut [u8]) -> io::Result<usize> {
+        self.project().peek(buf).await
+    }
+}
+
+impl<IO: AsyncRead + AsyncWrite, C, SD: SideData> StreamProj<'_, IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    async fn read_io(&mut self, splitted: bool) -> io::Result<usize> {
+        #[cfg(feature = "metrics")]
+        let records = self.read_records.records;
         let n = loop {
-            match self.session.read_tls(&mut self.r_buffer) {
+            let mut reader = TapRead {
+                inner: &mut self.r_buffer,
+                scanner: self.read_records,
+                capture: self.capture.as_mut(),
+                by_record: self.session.is_handshaking() || *self.stopping,
+            };
+            match self.session.read_tls(&mut reader) {
                 Ok(n) => {
                     break n;
                 }
+                // The buffer is empty. Filling it reads, EOF included, or fails, so the loop
+                // can't spin.
                 Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
                 Err(err) => return Err(err),
             }
             #[allow(unused_unsafe)]
             unsafe {
-                self.r_buffer.do_io(&mut self.io).await?
+                self.r_buffer.do_io(pin_io(self.io.as_mut())).await?
             };
         };
 
-        let state = match self.session.process_new_packets() {
-            Ok(state) => state,
+        self.stats.ciphertext_read += n as u64;
+        #[cfg(feature = "metrics")]
+        if let Some(m) = &self.metrics {
+            m.metrics.records(self.read_records.records - records, 0);
+        }
+
+        let result = self.session.process_new_packets();
+        if let Some(observer) = self.observer.as_mut() {
+            observer.after_read(self.read_records, self.session);
+            if let Err(rustls_fork_shadow_tls::Error::AlertReceived(alert)) = result {
+                observer.alert_received(alert);
+            }
+        }
+        let state = match result {
+            Ok(state) => {
+                *self.plaintext_unread = state.plaintext_bytes_to_read();
+                state
+            }
             Err(err) => {
+                #[cfg(feature = "tracing")]
+                match err {
+                    rustls_fork_shadow_tls::Error::AlertReceived(alert) => {
+                        tracing::debug!(?alert, "tls alert received")
+                    }
+                    ref err => tracing::debug!(%err, "tls error, sending fatal alert"),
+                }
                 // When to write_io? If we do this in read call, the UnsafeWrite may crash
                 // when we impl split in an UnsafeCell way.
                 // Here we choose not to do write when read.
                 // User should manually shutdown it on error.
-                if !splitted {
+                if !splitted && *self.send_alerts {
                     let _ = self.write_io().await;
                 }
                 return Err(io::Error::new(io::ErrorKind::InvalidData, err));
             }
         };
 
+        if state.peer_has_closed() && !*self.peer_closed {
+            *self.peer_closed = true;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                read_bytes = self.stats.ciphertext_read,
+                written_bytes = self.stats.ciphertext_written,
+                "tls close_notify received"
+            );
+        }
+
         if state.peer_has_closed() && self.session.is_handshaking() {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
@@ -111,35 +845,296 @@ where
         Ok(n)
     }
 
-    pub(crate) async fn write_io(&mut self) -> io::Result<usize> {
+    async fn write_io(&mut self) -> io::Result<usize> {
+        #[cfg(feature = "metrics")]
+        let records = self.write_records.records;
+        let mut guard = SpinGuard::default();
         let n = loop {
-            match self.session.write_tls(&mut self.w_buffer) {
+            let mut writer = TapWrite {
+                inner: &mut self.w_buffer,
+                scanner: self.write_records,
+            };
+            match self.session.write_tls(&mut writer) {
                 Ok(n) => {
                     break n;
                 }
-                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => guard.spin("write")?,
                 Err(err) => return Err(err),
             }
             #[allow(unused_unsafe)]
             unsafe {
-                self.w_buffer.do_io(&mut self.io).await?
+                self.w_buffer.do_io(pin_io(self.io.as_mut())).await?
             };
         };
         // Flush buffered data, only needed for safe_io.
         #[cfg(not(feature = "unsafe_io"))]
-        self.w_buffer.do_io(&mut self.io).await?;
+        self.w_buffer.do_io(pin_io(self.io.as_mut())).await?;
 
+        self.stats.ciphertext_written += n as u64;
+        if let Some(observer) = self.observer.as_mut() {
+            observer.after_write(self.write_records);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(m) = &self.metrics {
+            m.metrics.records(0, self.write_records.records - records);
+        }
         Ok(n)
     }
 
-    pub(crate) async fn handshake(&mut self) -> io::Result<(usize, usize)> {
+    /// Write `data` to the IO as is, after the ciphertext rustls wrote.
+    async fn write_unprotected(&mut self, mut data: &[u8]) -> io::Result<()> {
+        let mut guard = SpinGuard::default();
+        while !data.is_empty() {
+            let mut writer = TapWrite {
+                inner: &mut self.w_buffer,
+                scanner: self.write_records,
+            };
+            match writer.write(data) {
+                Ok(0) => guard.spin("write")?,
+                Ok(n) => {
+                    data = &data[n..];
+                    self.stats.ciphertext_written += n as u64;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    guard.spin("write")?;
+                    #[allow(unused_unsafe)]
+                    unsafe {
+                        self.w_buffer.do_io(pin_io(self.io.as_mut())).await?
+                    };
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        #[cfg(not(feature = "unsafe_io"))]
+        self.w_buffer.do_io(pin_io(self.io.as_mut())).await?;
+        Ok(())
+    }
+
+    /// Whether records written now must be protected, which only rustls can do.
+    fn writes_protected(&self) -> bool {
+        match self.session.protocol_version() {
+            None => false,
+            // Both sides use handshake keys from the ServerHello on.
+            Some(ProtocolVersion::TLSv1_3) => true,
+            Some(_) => self.write_records.handshake.done,
+        }
+    }
+
+    async fn send_alert(&mut self, description: AlertDescription) -> io::Result<()> {
+        if description == AlertDescription::CloseNotify {
+            self.session.send_close_notify();
+        }
+        let mut guard = SpinGuard::default();
+        while self.wants_write() {
+            if self.write_io().await? == 0 {
+                guard.spin("alert")?;
+            }
+        }
+        if description == AlertDescription::CloseNotify {
+            return Ok(());
+        }
+        if self.writes_protected() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "protected alerts other than close_notify can't be sent",
+            ));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?description, "sending tls alert");
+        self.write_unprotected(&record::plaintext_alert(description)).await
+    }
+
+    async fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.session.is_handshaking() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "raw writes must wait for the tls handshake to finish",
+            ));
+        }
+        let mut guard = SpinGuard::default();
+        while self.wants_write() {
+            if self.write_io().await? == 0 {
+                guard.spin("write")?;
+            }
+        }
+        *self.raw_written = true;
+        self.write_unprotected(data).await
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.peeked.len() < buf.len() {
+            let start = self.peeked.len();
+            self.peeked.resize(buf.len(), 0);
+            let read = self.session.reader().read(&mut self.peeked[start..]);
+            let n = *read.as_ref().unwrap_or(&0);
+            *self.plaintext_unread = self.plaintext_unread.saturating_sub(n);
+            self.peeked.truncate(start + n);
+            match read {
+                // The peer has closed.
+                Ok(0) => break,
+                Ok(_) => (),
+                // Return what is there rather than wait for more.
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock && start > 0 => break,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if self.read_io(false).await? == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "tls raw stream eof",
+                        ));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let n = buf.len().min(self.peeked.len());
+        buf[..n].copy_from_slice(&self.peeked[..n]);
+        Ok(n)
+    }
+
+    async fn stop_tls(&mut self) -> io::Result<()> {
+        if self.session.is_handshaking() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tls can only be stopped after the handshake",
+            ));
+        }
+        self.session.send_close_notify();
+        let mut guard = SpinGuard::default();
+        while self.wants_write() {
+            if self.write_io().await? == 0 {
+                guard.spin("close")?;
+            }
+        }
+        // Read a record at a time, so the plaintext after the close_notify stays buffered.
+        *self.stopping = true;
+        loop {
+            let unread = self
+                .session
+                .process_new_packets()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+                .plaintext_bytes_to_read();
+            *self.plaintext_unread = unread;
+            if unread > 0 || !self.peeked.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "tls application data left while stopping tls",
+                ));
+            }
+            if *self.peer_closed {
+                return Ok(());
+            }
+            if self.read_io(false).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "eof before the tls close_notify",
+                ));
+            }
+        }
+    }
+
+    /// Whether rustls or the write buffer still holds ciphertext for the IO. With safe_io a
+    /// cancelled `write_io` may leave ciphertext in the buffer.
+    fn wants_write(&self) -> bool {
+        #[cfg(not(feature = "unsafe_io"))]
+        if !self.w_buffer.is_empty() {
+            return true;
+        }
+        self.session.wants_write()
+    }
+
+    async fn handshake(&mut self) -> io::Result<(usize, usize)> {
+        #[cfg(feature = "metrics")]
+        let start = self.metrics.as_ref().map(|m| m.metrics.handshake_started());
+        let handshake_start = Instant::now();
+        let res = self.handshake_inner().await;
+        if res.is_ok() {
+            let duration = handshake_start.elapsed();
+            self.stats.handshake_duration = Some(duration);
+            let resumed = record::resumed(
+                &self.read_records.handshake,
+                &self.write_records.handshake,
+                self.session.protocol_version(),
+            );
+            *self.handshake_summary = Some(HandshakeSummary {
+                bytes_read: self.stats.ciphertext_read,
+                bytes_written: self.stats.ciphertext_written,
+                duration,
+                resumed,
+                kind: match resumed {
+                    true => HandshakeKind::Resumed,
+                    false => HandshakeKind::Full,
+                },
+            });
+            if let Some(observer) = &self.observer {
+                observer.handshake_complete(self.session, duration);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if let (Some(m), Some(start)) = (self.metrics.as_mut(), start) {
+            match res {
+                Ok(_) => {
+                    let resumed = self.handshake_summary.is_some_and(|s| s.resumed);
+                    m.metrics.handshake_succeeded(start, resumed);
+                    m.active = true;
+                }
+                Err(_) => m.metrics.handshake_failed(),
+            }
+        }
+        res
+    }
+
+    async fn fail_handshake(
+        &mut self,
+        err: io::Error,
+        alert: Option<AlertDescription>,
+    ) -> io::Error {
+        #[cfg(feature = "metrics")]
+        if let Some(m) = &self.metrics {
+            m.metrics.handshake_started();
+            m.metrics.handshake_failed();
+        }
+        let received = match err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<rustls_fork_shadow_tls::Error>())
+        {
+            Some(rustls_fork_shadow_tls::Error::AlertReceived(alert)) => Some(*alert),
+            _ => None,
+        };
+        if let Some(observer) = self.observer.as_mut() {
+            observer.after_read(self.read_records, self.session);
+            if let Some(alert) = received {
+                observer.alert_received(alert);
+            }
+        }
+        #[cfg(feature = "tracing")]
+        match received {
+            Some(alert) => tracing::debug!(?alert, "tls alert received"),
+            None => tracing::debug!(%err, "tls error, sending fatal alert"),
+        }
+        if *self.send_alerts && received.is_none() {
+            let _ = match alert {
+                Some(description) => self.send_alert(description).await,
+                None => self.write_io().await.map(drop),
+            };
+        }
+        err
+    }
+
+    async fn handshake_inner(&mut self) -> io::Result<(usize, usize)> {
         let mut wrlen = 0;
         let mut rdlen = 0;
         let mut eof = false;
+        let mut guard = SpinGuard::default();
+        #[cfg(feature = "tracing")]
+        tracing::debug!("tls handshake started");
 
         loop {
             while self.session.wants_write() && self.session.is_handshaking() {
-                wrlen += self.write_io().await?;
+                let n = self.write_io().await?;
+                if n == 0 {
+                    guard.spin("handshake")?;
+                }
+                wrlen += n;
             }
             while !eof && self.session.wants_read() && self.session.is_handshaking() {
                 let n = self.read_io(false).await?;
@@ -163,13 +1158,41 @@ where
 
         // flush buffer
         while self.session.wants_write() {
-            wrlen += self.write_io().await?;
+            let n = self.write_io().await?;
+            if n == 0 {
+                guard.spin("handshake")?;
+            }
+            wrlen += n;
         }
 
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            version = ?self.session.protocol_version(),
+            cipher_suite = ?self.session.negotiated_cipher_suite().map(|s| s.suite()),
+            alpn = ?self.session.alpn_protocol().map(String::from_utf8_lossy),
+            read_bytes = rdlen,
+            written_bytes = wrlen,
+            "tls handshake finished"
+        );
         Ok((rdlen, wrlen))
     }
 
-    pub(crate) async fn read_inner(
+    /// Poll a read, subject to the read timeout.
+    fn poll_read_inner(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+        splitted: bool,
+    ) -> Poll<std::io::Result<()>> {
+        let res = {
+            let read = self.read_inner(buf, splitted);
+            pin!(read);
+            read.poll(cx)
+        };
+        self.read_timer.check(cx, res, self.stats.ciphertext_read)
+    }
+
+    async fn read_inner(
         &mut self,
         buf: &mut ReadBuf<'_>,
         splitted: bool,
@@ -177,12 +1200,39 @@ where
         if buf.remaining() == 0 {
             return Ok(());
         }
-        let slice = buf.initialize_unfilled();
+        let mut limit = buf.remaining();
+        if let Some(limiter) = self.rate_limiter.as_mut() {
+            limit = std::future::poll_fn(|cx| limiter.poll_read(cx, limit)).await;
+        }
+        let slice = &mut buf.initialize_unfilled()[..limit];
         loop {
-            // read from rustls to buffer
-            match self.session.reader().read(slice) {
+            // read from what was peeked, then from rustls, to buffer
+            let read = match self.peeked.is_empty() {
+                true => {
+                    let read = self.session.reader().read(slice);
+                    if let Ok(n) = read {
+                        *self.plaintext_unread = self.plaintext_unread.saturating_sub(n);
+                    }
+                    read
+                }
+                false => {
+                    let n = slice.len().min(self.peeked.len());
+                    slice[..n].copy_from_slice(&self.peeked[..n]);
+                    self.peeked.drain(..n);
+                    Ok(n)
+                }
+            };
+            match read {
                 Ok(n) => {
                     buf.advance(n);
+                    if let Some(limiter) = self.rate_limiter.as_mut() {
+                        limiter.consume_read(n);
+                    }
+                    self.stats.plaintext_read += n as u64;
+                    #[cfg(feature = "metrics")]
+                    if let Some(m) = &self.metrics {
+                        m.metrics.decrypted(n);
+                    }
                     return Ok(());
                 }
                 // we need more data, read something.
@@ -209,43 +1259,46 @@ where
             }
         }
     }
-}
-
-impl<IO: AsyncRead + AsyncWrite + Unpin, C, SD: SideData + 'static> AsyncRead for Stream<IO, C>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<SD>> + Unpin,
-{
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>
-    ) -> Poll<std::io::Result<()>> {
-        let read = self.read_inner(buf, false);
-        pin!(read);
-        read.poll(cx)
-    }
-}
-
-impl<IO: AsyncRead + AsyncWrite + Unpin, C, SD: SideData + 'static> AsyncWrite for Stream<IO, C>
-where
-    C: DerefMut + Deref<Target = ConnectionCommon<SD>> + Unpin,
-{
-    fn poll_write(
-        mut self: Pin<&mut Self>,
+    fn poll_write_inner(
+        &mut self,
         cx: &mut Context<'_>,
         buf: &[u8]
     ) -> Poll<std::io::Result<usize>> {
+        if *self.raw_written {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tls writes after raw bytes",
+            )));
+        }
         // write buf to rustls
+        let mut deferred = false;
         if let WriteStatus::Ok = self.write_status {
-            let n = match self.session.writer().write(buf) {
+            let mut limit = buf.len();
+            if let Some(limiter) = self.rate_limiter.as_mut() {
+                limit = ready!(limiter.poll_write(cx, limit));
+            }
+            // rustls holds plaintext back until it may encrypt application data.
+            let handshaking = self.session.is_handshaking();
+            let before = match handshaking {
+                true => tls_bytes_to_write(self.session),
+                false => 0,
+            };
+            let n = match self.session.writer().write(&buf[..limit]) {
                 Ok(n) => n,
                 Err(e) => return Poll::Ready(Err(e)),
             };
-            self.write_status = WriteStatus::Pending(n);
+            if handshaking && tls_bytes_to_write(self.session) == before {
+                *self.plaintext_held += n;
+            }
+            if let Some(limiter) = self.rate_limiter.as_mut() {
+                limiter.consume_write(n);
+            }
+            *self.write_status = WriteStatus::Pending(n);
+            deferred = self.deferred_flush.as_mut().is_some_and(|flush| flush.defer(n));
         }
 
-        // write from rustls to connection
-        while self.session.wants_write() {
+        // write from rustls to connection, unless that waits for the next flush
+        while !deferred && self.session.wants_write() {
             let write = self.write_io();
             pin!(write);
             match write.poll(cx) {
@@ -260,81 +1313,147 @@ where
             }
         }
 
-        let n = match self.write_status {
+        if !deferred {
+            if let Some(flush) = self.deferred_flush.as_mut() {
+                flush.written();
+            }
+        }
+        let n = match *self.write_status {
             WriteStatus::Ok => 0,
             WriteStatus::Pending(n) => n,
         };
-        self.write_status = WriteStatus::Ok;
+        *self.write_status = WriteStatus::Ok;
+        self.stats.plaintext_written += n as u64;
+        #[cfg(feature = "metrics")]
+        if let Some(m) = &self.metrics {
+            m.metrics.encrypted(n);
+        }
         return Poll::Ready(Ok(n));
     }
 
-    fn poll_write_vectored(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        bufs: &[IoSlice<'_>]
-    ) -> Poll<std::io::Result<usize>> {
-        let buf = bufs
-            .iter()
-            .find(|b| !b.is_empty())
-            .map_or(&[][..], |b| &**b);
-        self.poll_write(cx, buf)
-    }
-
-    fn poll_flush(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>
-    ) -> Poll<std::io::Result<()>> {
+    fn poll_flush_inner(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
         if let WriteStatus::Ok = self.flush_status {
+            if let Some(flush) = self.deferred_flush.as_mut() {
+                ready!(flush.poll_slice(cx));
+            }
             self.session.writer().flush()?;
-            self.flush_status = WriteStatus::Pending(0);
+            *self.flush_status = WriteStatus::Pending(0);
         }
-        while self.session.wants_write() {
+        let mut guard = SpinGuard::default();
+        while self.wants_write() {
             let write = self.write_io();
             pin!(write);
             match write.poll(cx) {
+                Poll::Ready(Ok(0)) => guard.spin("flush")?,
                 Poll::Ready(Ok(_)) => (),
                 Poll::Pending => return Poll::Pending,
                 Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
             }
         }
-        let result = Pin::new(&mut self.io).poll_flush(cx);
+        let result = pin_io(self.io.as_mut()).poll_flush(cx);
         match result {
             Poll::Ready(Ok(_)) => (),
             Poll::Pending => return Poll::Pending,
             Poll::Ready(Err(_)) => (),
         }
-        self.flush_status = WriteStatus::Ok;
+        *self.flush_status = WriteStatus::Ok;
         return result;
     }
 
-    fn poll_shutdown(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>
-    ) -> Poll<std::io::Result<()>> {
+    fn poll_shutdown_inner(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
         if let WriteStatus::Ok = self.close_status {
-            self.session.send_close_notify();
-            self.close_status = WriteStatus::Pending(0);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                read_bytes = self.stats.ciphertext_read,
+                written_bytes = self.stats.ciphertext_written,
+                "tls close_notify sent"
+            );
+            if !*self.raw_written {
+                self.session.send_close_notify();
+            }
+            *self.close_status = WriteStatus::Pending(0);
         }
-        while self.session.wants_write() {
+        let mut guard = SpinGuard::default();
+        while self.wants_write() {
             let write = self.write_io();
             pin!(write);
             match write.poll(cx) {
+                Poll::Ready(Ok(0)) => guard.spin("shutdown")?,
                 Poll::Ready(Ok(_)) => (),
                 Poll::Pending => return Poll::Pending,
                 Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
             }
         }
-        let result = Pin::new(&mut self.io).poll_shutdown(cx);
+        let result = pin_io(self.io.as_mut()).poll_shutdown(cx);
         match result {
             Poll::Ready(Ok(_)) => (),
             Poll::Pending => return Poll::Pending,
             Poll::Ready(Err(_)) => (),
         }
-        self.close_status = WriteStatus::Ok;
+        *self.close_status = WriteStatus::Ok;
+        *self.shut_down = true;
         return result;
     }
+}
+
+impl<IO: AsyncRead + AsyncWrite, C, SD: SideData + 'static> AsyncRead for Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>
+    ) -> Poll<std::io::Result<()>> {
+        self.project().poll_read_inner(cx, buf, false)
+    }
+}
+
+impl<IO: AsyncRead + AsyncWrite, C, SD: SideData + 'static> AsyncWrite for Stream<IO, C>
+where
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<std::io::Result<usize>> {
+        let mut this = self.project();
+        let res = this.poll_write_inner(cx, buf);
+        this.write_timer.check(cx, res, this.stats.ciphertext_written)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>]
+    ) -> Poll<std::io::Result<usize>> {
+        let buf = bufs
+            .iter()
+            .find(|b| !b.is_empty())
+            .map_or(&[][..], |b| &**b);
+        self.poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<std::io::Result<()>> {
+        let mut this = self.project();
+        let res = this.poll_flush_inner(cx);
+        this.write_timer.check(cx, res, this.stats.ciphertext_written)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>
+    ) -> Poll<std::io::Result<()>> {
+        let mut this = self.project();
+        let res = this.poll_shutdown_inner(cx);
+        this.write_timer.check(cx, res, this.stats.ciphertext_written)
+    }
 
     fn is_write_vectored(&self) -> bool {
-        Pin::new(&self.io).is_write_vectored()
+        self.io.is_write_vectored()
     }
 }
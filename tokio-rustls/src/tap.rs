@@ -0,0 +1,94 @@
+//! Record tap callbacks.
+use std::{fmt, sync::Arc};
+
+use rustls_fork_shadow_tls::{ContentType, ProtocolVersion};
+
+use crate::record::HEADER_LEN;
+
+/// Which way a record went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Read from the IO.
+    Inbound,
+    /// Written to the IO.
+    Outbound,
+}
+
+/// A TLS record passed to a [`RecordTap`].
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub direction: Direction,
+    /// The type in the header; encrypted TLS 1.3 records all claim `ApplicationData`.
+    pub content_type: ContentType,
+    pub version: ProtocolVersion,
+    /// Payload length from the header.
+    pub length: u16,
+    /// The payload as it went over the wire, when the tap asks for it.
+    pub ciphertext: Option<&'a [u8]>,
+}
+
+/// Callback on every record a stream reads or writes, set with `with_record_tap` on a
+/// connector or acceptor, e.g. for debugging, traffic shaping analysis or computing a
+/// shadow-tls HMAC over given records.
+///
+/// Records are reported once their last byte has gone through, in order per direction. The
+/// callback runs inline on the task driving the stream, so it should return quickly.
+pub trait RecordTap: Send + Sync {
+    /// Whether records come with their ciphertext, which costs a copy of each. Asked once per
+    /// stream.
+    fn wants_ciphertext(&self) -> bool {
+        false
+    }
+
+    fn record(&self, record: &Record<'_>);
+}
+
+/// A tap together with the record being collected for it, for one direction of a stream.
+pub(crate) struct TapSlot {
+    tap: Arc<dyn RecordTap>,
+    direction: Direction,
+    /// Payload of the current record, when the tap wants ciphertext.
+    payload: Option<Vec<u8>>,
+}
+
+impl fmt::Debug for TapSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TapSlot")
+            .field("direction", &self.direction)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TapSlot {
+    pub(crate) fn new(tap: Arc<dyn RecordTap>, direction: Direction) -> Self {
+        let payload = tap.wants_ciphertext().then(Vec::new);
+        Self {
+            tap,
+            direction,
+            payload,
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        if let Some(payload) = &mut self.payload {
+            payload.clear();
+        }
+    }
+
+    pub(crate) fn payload(&mut self, data: &[u8]) {
+        if let Some(payload) = &mut self.payload {
+            payload.extend_from_slice(data);
+        }
+    }
+
+    /// Report the record with `header`, whose payload has all been seen.
+    pub(crate) fn finish(&mut self, header: &[u8; HEADER_LEN]) {
+        self.tap.record(&Record {
+            direction: self.direction,
+            content_type: ContentType::from(header[0]),
+            version: ProtocolVersion::from(u16::from_be_bytes([header[1], header[2]])),
+            length: u16::from_be_bytes([header[3], header[4]]),
+            ciphertext: self.payload.as_deref(),
+        });
+    }
+}
@@ -0,0 +1,92 @@
+//! In-memory client/server stream pairs for testing code built on TLS streams.
+use std::{io, sync::Arc};
+
+use tokio::io::{duplex, DuplexStream};
+use rustls_fork_shadow_tls::ServerName;
+
+use crate::{
+    generate_self_signed, ClientTlsStream, ServerTlsStream, TlsAcceptor, TlsConnector, TlsError,
+};
+
+/// Default capacity of each direction of the in-memory pipe.
+const DEFAULT_DUPLEX_SIZE: usize = 64 * 1024;
+
+/// Builds a connected client and server stream over `tokio::io::duplex`, with a freshly
+/// generated self-signed certificate.
+#[derive(Debug, Clone)]
+pub struct TlsPairBuilder {
+    server_name: String,
+    alpn_protocols: Vec<Vec<u8>>,
+    duplex_size: usize,
+}
+
+impl Default for TlsPairBuilder {
+    fn default() -> Self {
+        Self {
+            server_name: "localhost".to_owned(),
+            alpn_protocols: Vec::new(),
+            duplex_size: DEFAULT_DUPLEX_SIZE,
+        }
+    }
+}
+
+impl TlsPairBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the certificate is issued for and the client connects to. Defaults to `localhost`.
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = name.into();
+        self
+    }
+
+    /// ALPN protocols offered by the client and accepted by the server.
+    pub fn with_alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+
+    /// Capacity of each direction of the in-memory pipe.
+    pub fn with_duplex_size(mut self, size: usize) -> Self {
+        self.duplex_size = size;
+        self
+    }
+
+    /// Generate the certificate and run both handshakes.
+    pub async fn build(
+        self,
+    ) -> Result<(ClientTlsStream<DuplexStream>, ServerTlsStream<DuplexStream>), TlsError> {
+        let (connector, acceptor) = self.configs()?;
+        let domain = ServerName::try_from(self.server_name.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let (client_io, server_io) = duplex(self.duplex_size);
+        let (client, server) = tokio::join!(
+            connector.connect(domain, client_io),
+            acceptor.accept(server_io)
+        );
+        Ok((client?, server?))
+    }
+
+    /// The connector and acceptor `build` uses, for tests which drive the handshake
+    /// themselves.
+    pub fn configs(&self) -> Result<(TlsConnector, TlsAcceptor), TlsError> {
+        let generated = generate_self_signed(&[&self.server_name])?;
+        let mut client = generated.client_config();
+        client.alpn_protocols = self.alpn_protocols.clone();
+
+        let mut server = generated.server_config;
+        server.alpn_protocols = self.alpn_protocols.clone();
+
+        Ok((
+            TlsConnector::from(Arc::new(client)),
+            TlsAcceptor::from(Arc::new(server)),
+        ))
+    }
+}
+
+/// A connected client and server stream with default options, see [`TlsPairBuilder`].
+pub async fn tls_pair(
+) -> Result<(ClientTlsStream<DuplexStream>, ServerTlsStream<DuplexStream>), TlsError> {
+    TlsPairBuilder::new().build().await
+}
@@ -0,0 +1,397 @@
+//! Bandwidth and latency limits on an IO.
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep},
+};
+
+/// Stop reading ahead from the inner IO once this many bytes wait out their latency.
+const MAX_QUEUED: usize = 256 * 1024;
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Poll a lazily created sleep until `deadline`.
+fn poll_sleep(
+    sleep: &mut Option<Pin<Box<Sleep>>>,
+    deadline: Instant,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    let sleep = sleep.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+    if sleep.deadline() != deadline {
+        sleep.as_mut().reset(deadline);
+    }
+    sleep.as_mut().poll(cx)
+}
+
+/// Token bucket refilled at `rate` bytes per second, holding at most `burst` bytes.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: u64,
+    burst: u64,
+    tokens: f64,
+    last: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u64, burst: u64) -> Self {
+        let burst = burst.max(1);
+        Self {
+            rate: rate.max(1),
+            burst,
+            tokens: burst as f64,
+            last: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    pub(crate) fn set_rate(&mut self, rate: u64, burst: u64) {
+        self.refill();
+        self.rate = rate.max(1);
+        self.burst = burst.max(1);
+        self.tokens = self.tokens.min(self.burst as f64);
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+        self.last = now;
+    }
+
+    /// How many of `want` bytes may pass now, waiting until at least one may. Call `consume`
+    /// with the bytes that actually passed.
+    pub(crate) fn poll_acquire(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<usize> {
+        if want == 0 {
+            return Poll::Ready(0);
+        }
+        loop {
+            self.refill();
+            if self.tokens >= 1.0 {
+                return Poll::Ready((self.tokens as u64).min(want as u64) as usize);
+            }
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate as f64);
+            ready!(poll_sleep(&mut self.sleep, self.last + wait, cx));
+        }
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.tokens -= n as f64;
+    }
+}
+
+/// Wraps an IO to limit its bandwidth and add latency to incoming data.
+///
+/// Rates are in bytes per second, with `burst` bytes allowed through at once after the IO has
+/// been idle. Latency delays the moment data read from the inner IO is handed to the reader,
+/// without lowering throughput; wrap both ends of a connection to simulate a round trip.
+pub struct ThrottledIo<IO> {
+    inner: IO,
+    read_bucket: Option<TokenBucket>,
+    write_bucket: Option<TokenBucket>,
+    latency: Duration,
+    /// Data read ahead from the inner IO and when it may be handed out; empty data is EOF.
+    queue: VecDeque<(Instant, Vec<u8>)>,
+    queued: usize,
+    /// Bytes of the front entry already handed out.
+    front_pos: usize,
+    inner_eof: bool,
+    read_err: Option<io::Error>,
+    latency_sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<IO> ThrottledIo<IO> {
+    /// Wrap `inner` with no limits.
+    pub fn new(inner: IO) -> Self {
+        Self {
+            inner,
+            read_bucket: None,
+            write_bucket: None,
+            latency: Duration::ZERO,
+            queue: VecDeque::new(),
+            queued: 0,
+            front_pos: 0,
+            inner_eof: false,
+            read_err: None,
+            latency_sleep: None,
+        }
+    }
+
+    pub fn with_read_rate(mut self, bytes_per_sec: u64, burst: u64) -> Self {
+        self.read_bucket = Some(TokenBucket::new(bytes_per_sec, burst));
+        self
+    }
+
+    pub fn with_write_rate(mut self, bytes_per_sec: u64, burst: u64) -> Self {
+        self.write_bucket = Some(TokenBucket::new(bytes_per_sec, burst));
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn get_ref(&self) -> &IO {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.inner
+    }
+
+    /// Returns the inner IO. Data read ahead and still waiting out its latency is lost.
+    pub fn into_inner(self) -> IO {
+        self.inner
+    }
+}
+
+impl<IO: AsyncRead + Unpin> ThrottledIo<IO> {
+    /// Read ahead from the inner IO, stamping everything with when it may be handed out.
+    fn fill_queue(&mut self, cx: &mut Context<'_>) {
+        while self.queued < MAX_QUEUED && !self.inner_eof && self.read_err.is_none() {
+            let mut chunk = [0; READ_CHUNK];
+            let mut buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+                Poll::Pending => break,
+                Poll::Ready(Err(e)) => self.read_err = Some(e),
+                Poll::Ready(Ok(())) => {
+                    let data = buf.filled().to_vec();
+                    self.inner_eof = data.is_empty();
+                    self.queued += data.len();
+                    self.queue.push_back((Instant::now() + self.latency, data));
+                }
+            }
+        }
+    }
+
+    fn poll_read_delayed(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.fill_queue(cx);
+        let Some((deadline, data)) = self.queue.front() else {
+            return match self.read_err.take() {
+                Some(e) => Poll::Ready(Err(e)),
+                None => Poll::Pending,
+            };
+        };
+        ready!(poll_sleep(&mut self.latency_sleep, *deadline, cx));
+        if data.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut n = (data.len() - self.front_pos).min(buf.remaining());
+        if let Some(bucket) = self.read_bucket.as_mut() {
+            n = ready!(bucket.poll_acquire(cx, n));
+            bucket.consume(n);
+        }
+        let (_, data) = self.queue.front().expect("front entry checked above");
+        buf.put_slice(&data[self.front_pos..self.front_pos + n]);
+        self.front_pos += n;
+        self.queued -= n;
+        if self.front_pos == data.len() {
+            self.queue.pop_front();
+            self.front_pos = 0;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for ThrottledIo<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.latency.is_zero() {
+            return this.poll_read_delayed(cx, buf);
+        }
+
+        let limit = match this.read_bucket.as_mut() {
+            Some(bucket) => ready!(bucket.poll_acquire(cx, buf.remaining())),
+            None => buf.remaining(),
+        };
+        let mut limited = buf.take(limit);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let n = limited.filled().len();
+        // SAFETY: the inner read initialized the first `n` bytes of the unfilled part.
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+        if let Some(bucket) = this.read_bucket.as_mut() {
+            bucket.consume(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for ThrottledIo<IO> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(bucket) = this.write_bucket.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        let limit = ready!(bucket.poll_acquire(cx, buf.len()));
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..limit]))?;
+        bucket.consume(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Rates {
+    read: Option<(u64, u64)>,
+    write: Option<(u64, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    rates: Mutex<Rates>,
+    /// Bumped on every change, so streams only take the lock when there is something new.
+    version: AtomicU64,
+}
+
+/// Handle to adjust the bandwidth limits of a stream at runtime, returned by `rate_limit` on
+/// the stream.
+///
+/// Rates are in bytes per second of plaintext, with `burst` bytes allowed through at once
+/// after the stream has been idle. Changes take effect the next time the stream is polled.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit {
+    shared: Arc<Shared>,
+}
+
+impl RateLimit {
+    pub fn set_read_rate(&self, bytes_per_sec: u64, burst: u64) {
+        self.update(|rates| rates.read = Some((bytes_per_sec, burst)));
+    }
+
+    pub fn set_write_rate(&self, bytes_per_sec: u64, burst: u64) {
+        self.update(|rates| rates.write = Some((bytes_per_sec, burst)));
+    }
+
+    pub fn clear_read_rate(&self) {
+        self.update(|rates| rates.read = None);
+    }
+
+    pub fn clear_write_rate(&self) {
+        self.update(|rates| rates.write = None);
+    }
+
+    /// The current `(bytes_per_sec, burst)` read limit.
+    pub fn read_rate(&self) -> Option<(u64, u64)> {
+        self.shared.rates.lock().unwrap().read
+    }
+
+    /// The current `(bytes_per_sec, burst)` write limit.
+    pub fn write_rate(&self) -> Option<(u64, u64)> {
+        self.shared.rates.lock().unwrap().write
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Rates)) {
+        f(&mut self.shared.rates.lock().unwrap());
+        self.shared.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// The buckets of one stream, kept in sync with its `RateLimit` handle.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    handle: RateLimit,
+    version: u64,
+    read: Option<TokenBucket>,
+    write: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(handle: RateLimit) -> Self {
+        let mut limiter = Self {
+            handle,
+            version: 0,
+            read: None,
+            write: None,
+        };
+        limiter.load();
+        limiter
+    }
+
+    pub(crate) fn handle(&self) -> &RateLimit {
+        &self.handle
+    }
+
+    fn load(&mut self) {
+        fn apply(bucket: &mut Option<TokenBucket>, rate: Option<(u64, u64)>) {
+            match (bucket.as_mut(), rate) {
+                (Some(bucket), Some((rate, burst))) => bucket.set_rate(rate, burst),
+                (None, Some((rate, burst))) => *bucket = Some(TokenBucket::new(rate, burst)),
+                (_, None) => *bucket = None,
+            }
+        }
+
+        self.version = self.handle.shared.version.load(Ordering::Acquire);
+        let rates = self.handle.shared.rates.lock().unwrap();
+        apply(&mut self.read, rates.read);
+        apply(&mut self.write, rates.write);
+    }
+
+    fn sync(&mut self) {
+        if self.handle.shared.version.load(Ordering::Acquire) != self.version {
+            self.load();
+        }
+    }
+
+    /// How many of `want` plaintext bytes may be read now.
+    pub(crate) fn poll_read(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<usize> {
+        self.sync();
+        match self.read.as_mut() {
+            Some(bucket) => bucket.poll_acquire(cx, want),
+            None => Poll::Ready(want),
+        }
+    }
+
+    /// How many of `want` plaintext bytes may be written now.
+    pub(crate) fn poll_write(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<usize> {
+        self.sync();
+        match self.write.as_mut() {
+            Some(bucket) => bucket.poll_acquire(cx, want),
+            None => Poll::Ready(want),
+        }
+    }
+
+    pub(crate) fn consume_read(&mut self, n: usize) {
+        if let Some(bucket) = self.read.as_mut() {
+            bucket.consume(n);
+        }
+    }
+
+    pub(crate) fn consume_write(&mut self, n: usize) {
+        if let Some(bucket) = self.write.as_mut() {
+            bucket.consume(n);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Session ticket keys rotated on a lifetime of choice.
+use std::{
+    io,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use rustls_fork_shadow_tls::server::ProducesTickets;
+
+use crate::clock::Clock;
+
+/// Encrypts session tickets with ChaCha20-Poly1305 under a random key replaced every
+/// `lifetime`, like rustls' `Ticketer` does every 6 hours. Tickets of the previous key are
+/// accepted until the next rotation, so for up to twice the lifetime. Rotations follow
+/// `clock`.
+pub(crate) struct RotatingTicketer {
+    lifetime: u32,
+    clock: Clock,
+    rng: SystemRandom,
+    keys: Mutex<Keys>,
+}
+
+struct Keys {
+    current: LessSafeKey,
+    previous: Option<LessSafeKey>,
+    /// Seconds since the epoch.
+    next_rotation: u64,
+}
+
+impl RotatingTicketer {
+    pub(crate) fn new(lifetime: Duration, clock: Clock) -> io::Result<Self> {
+        let lifetime = lifetime.as_secs().clamp(1, u32::MAX as u64) as u32;
+        let rng = SystemRandom::new();
+        let keys = Keys {
+            current: new_key(&rng)?,
+            previous: None,
+            next_rotation: clock.unix_secs() + lifetime as u64,
+        };
+        Ok(Self {
+            lifetime,
+            clock,
+            rng,
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// The keys, rotated first when it is time. `None` when no new key could be made.
+    fn keys(&self) -> Option<MutexGuard<'_, Keys>> {
+        let mut keys = self.keys.lock().unwrap();
+        let now = self.clock.unix_secs();
+        if now >= keys.next_rotation {
+            let key = new_key(&self.rng).ok()?;
+            let previous = std::mem::replace(&mut keys.current, key);
+            // Skip the previous key when it is past its second lifetime already.
+            keys.previous = (now < keys.next_rotation + self.lifetime as u64).then_some(previous);
+            keys.next_rotation = now + self.lifetime as u64;
+        }
+        Some(keys)
+    }
+}
+
+impl ProducesTickets for RotatingTicketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let keys = self.keys()?;
+        let mut nonce = [0; NONCE_LEN];
+        self.rng.fill(&mut nonce).ok()?;
+        let mut sealed = plain.to_vec();
+        keys.current
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .ok()?;
+        let mut ticket = nonce.to_vec();
+        ticket.extend_from_slice(&sealed);
+        Some(ticket)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let (nonce, sealed) = cipher.split_at_checked(NONCE_LEN)?;
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().ok()?;
+        let keys = self.keys()?;
+        std::iter::once(&keys.current)
+            .chain(keys.previous.as_ref())
+            .find_map(|key| {
+                let mut buf = sealed.to_vec();
+                key.open_in_place(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut buf)
+                    .ok()
+                    .map(|plain| plain.to_vec())
+            })
+    }
+}
+
+fn new_key(rng: &SystemRandom) -> io::Result<LessSafeKey> {
+    let mut key = [0; 32];
+    rng.fill(&mut key)
+        .map_err(|_| io::Error::other("no randomness for a ticket key"))?;
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &key)
+        .map_err(|_| io::Error::other("invalid ticket key"))?;
+    Ok(LessSafeKey::new(key))
+}
@@ -0,0 +1,151 @@
+//! Idle timeouts and deadlines on the reads and writes of a stream.
+use std::{
+    future::Future,
+    io,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::{self, Instant, Sleep},
+};
+use rustls_fork_shadow_tls::{ConnectionCommon, SideData};
+
+use crate::stream::Stream;
+
+/// A read or write which failed or ran out of time part way, see
+/// [`Stream::read_exact_timeout`] and [`Stream::write_all_timeout`].
+#[derive(Error, Debug)]
+#[error("{source} after transferring {transferred} bytes")]
+pub struct PartialTransfer {
+    /// Bytes read into or written from the start of the buffer before the failure.
+    pub transferred: usize,
+    /// `TimedOut` when the deadline passed.
+    #[source]
+    pub source: io::Error,
+}
+
+/// Fails an operation which moves no bytes for longer than the timeout.
+#[derive(Debug, Default)]
+pub(crate) struct IdleTimer {
+    timeout: Option<Duration>,
+    /// Armed when an operation first returns pending, dropped when one completes.
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// The bytes moved when the sleep was last pushed back.
+    progress: u64,
+}
+
+impl IdleTimer {
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.sleep = None;
+    }
+
+    /// Pass on the result of polling an operation, or `TimedOut` once it has been pending
+    /// for the whole timeout without `progress`, the count of bytes moved, going up.
+    pub(crate) fn check<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        res: Poll<io::Result<T>>,
+        progress: u64,
+    ) -> Poll<io::Result<T>> {
+        if let Poll::Ready(res) = res {
+            self.sleep = None;
+            return Poll::Ready(res);
+        }
+        let Some(timeout) = self.timeout else {
+            return Poll::Pending;
+        };
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+        if progress != self.progress {
+            self.progress = progress;
+            sleep.as_mut().reset(Instant::now() + timeout);
+        }
+        ready!(sleep.as_mut().poll(cx));
+        self.sleep = None;
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "tls stream idle timeout",
+        )))
+    }
+}
+
+impl<IO, C, SD: SideData + 'static> Stream<IO, C>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+    C: DerefMut + Deref<Target = ConnectionCommon<SD>>,
+{
+    /// Fill `buf` within `timeout`. On failure the error tells how much of `buf` was filled,
+    /// which `read_exact` wrapped in `tokio::time::timeout` loses.
+    pub async fn read_exact_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<(), PartialTransfer> {
+        let deadline = Instant::now() + timeout;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let source = match time::timeout_at(deadline, self.read(&mut buf[filled..])).await {
+                Ok(Ok(0)) => io::ErrorKind::UnexpectedEof.into(),
+                Ok(Ok(n)) => {
+                    filled += n;
+                    continue;
+                }
+                Ok(Err(e)) => e,
+                Err(_) => io::ErrorKind::TimedOut.into(),
+            };
+            return Err(PartialTransfer {
+                transferred: filled,
+                source,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write all of `buf` and flush it within `timeout`. On failure the error tells how much
+    /// of `buf` the session took; that much goes out with the next write or flush.
+    pub async fn write_all_timeout(
+        &mut self,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<(), PartialTransfer> {
+        let deadline = Instant::now() + timeout;
+        let mut written = 0;
+        let source = loop {
+            let step = match written < buf.len() {
+                true => time::timeout_at(deadline, self.write(&buf[written..])).await,
+                false => match time::timeout_at(deadline, self.flush()).await {
+                    Ok(Ok(())) => return Ok(()),
+                    res => res.map(|res| res.map(|()| 0)),
+                },
+            };
+            match step {
+                Ok(Ok(0)) => break io::ErrorKind::WriteZero.into(),
+                Ok(Ok(n)) => written += n,
+                // The session may have taken the plaintext of the failed write already.
+                Ok(Err(e)) => {
+                    written += self.take_pending_write();
+                    break e;
+                }
+                Err(_) => {
+                    written += self.take_pending_write();
+                    break io::ErrorKind::TimedOut.into();
+                }
+            }
+        };
+        Err(PartialTransfer {
+            transferred: written,
+            source,
+        })
+    }
+}
@@ -16,6 +16,10 @@ enum Status {
     Filled(usize),
 }
 
+// The recorded pointer always points into a heap buffer owned by the rustls connection of the
+// same stream, so it stays valid when the stream is moved to another thread.
+unsafe impl Send for Status {}
+
 impl Default for Status {
     fn default() -> Self {
         Status::WaitFill(None)
@@ -52,6 +56,11 @@ impl UnsafeRead {
             Status::WaitFill(None) => Err(io::ErrorKind::WouldBlock.into()),
         }
     }
+
+    /// Forget the destination and result of the previous IO.
+    pub(crate) fn reset(&mut self) {
+        self.status = Status::default();
+    }
 }
 
 impl io::Read for UnsafeRead {
@@ -97,6 +106,11 @@ impl UnsafeWrite {
             Status::WaitFill(None) => Err(io::ErrorKind::WouldBlock.into()),
         }
     }
+
+    /// Forget the source and result of the previous IO.
+    pub(crate) fn reset(&mut self) {
+        self.status = Status::default();
+    }
 }
 
 impl io::Write for UnsafeWrite {
@@ -0,0 +1,164 @@
+//! Adapter running the TLS stream over tokio-uring's owned-buffer IO.
+//!
+//! `UringIo` turns a tokio-uring `TcpStream` or `UnixStream` into the `AsyncRead`/`AsyncWrite`
+//! pair `Stream` expects, so the same connector and acceptor work on tokio-uring runtimes.
+//! Like `MonoioIo`, it copies between the stream's buffers and owned buffers of its own,
+//! which the kernel holds while an operation is in flight.
+use std::{
+    future::Future,
+    io,
+    net::Shutdown,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_uring::{
+    net::{TcpStream, UnixStream},
+    BufResult,
+};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+type LocalFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+/// A tokio-uring socket, read and written through owned buffers. Implemented for
+/// `TcpStream` and `UnixStream`.
+pub trait UringSocket: 'static {
+    /// Read into the spare capacity of `buf`.
+    fn read_owned(self: Rc<Self>, buf: Vec<u8>) -> LocalFuture<BufResult<usize, Vec<u8>>>;
+
+    fn write_owned(self: Rc<Self>, buf: Vec<u8>) -> LocalFuture<BufResult<usize, Vec<u8>>>;
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+macro_rules! impl_uring_socket {
+    ($ty:ty) => {
+        impl UringSocket for $ty {
+            fn read_owned(self: Rc<Self>, buf: Vec<u8>) -> LocalFuture<BufResult<usize, Vec<u8>>> {
+                Box::pin(async move { <$ty>::read(&self, buf).await })
+            }
+
+            fn write_owned(self: Rc<Self>, buf: Vec<u8>) -> LocalFuture<BufResult<usize, Vec<u8>>> {
+                Box::pin(async move { <$ty>::write(&self, buf).submit().await })
+            }
+
+            fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+                <$ty>::shutdown(self, how)
+            }
+        }
+    };
+}
+
+impl_uring_socket!(TcpStream);
+impl_uring_socket!(UnixStream);
+
+/// Owned-buffer IO adapter.
+///
+/// An operation which returned `Pending` keeps running with the buffer it was started with;
+/// like with the `unsafe_io` feature, the caller must retry with the same data until it is
+/// ready. `Stream` always does so.
+pub struct UringIo<IO> {
+    io: Rc<IO>,
+    read_fut: Option<LocalFuture<BufResult<usize, Vec<u8>>>>,
+    write_fut: Option<LocalFuture<BufResult<usize, Vec<u8>>>>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Option<Vec<u8>>,
+}
+
+impl<IO> UringIo<IO> {
+    pub fn new(io: IO) -> Self {
+        Self {
+            io: Rc::new(io),
+            read_fut: None,
+            write_fut: None,
+            read_buf: Vec::with_capacity(BUFFER_SIZE),
+            read_pos: 0,
+            write_buf: Some(Vec::with_capacity(BUFFER_SIZE)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+
+    /// Returns `None` while an operation is still in flight.
+    pub fn into_inner(self) -> Option<IO> {
+        if self.read_fut.is_some() || self.write_fut.is_some() {
+            return None;
+        }
+        Rc::try_unwrap(self.io).ok()
+    }
+}
+
+impl<IO: UringSocket> AsyncRead for UringIo<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            // serve buffered data first
+            if this.read_pos < this.read_buf.len() {
+                let n = buf.remaining().min(this.read_buf.len() - this.read_pos);
+                buf.put_slice(&this.read_buf[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            let fut = this.read_fut.get_or_insert_with(|| {
+                let mut owned = std::mem::take(&mut this.read_buf);
+                owned.clear();
+                this.io.clone().read_owned(owned)
+            });
+            let (res, owned) = match fut.as_mut().poll(cx) {
+                Poll::Ready(r) => r,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.read_fut = None;
+            this.read_buf = owned;
+            this.read_pos = 0;
+            match res {
+                Ok(0) => return Poll::Ready(Ok(())),
+                Ok(_) => (),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+impl<IO: UringSocket> AsyncWrite for UringIo<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let fut = this.write_fut.get_or_insert_with(|| {
+            let mut owned = this.write_buf.take().unwrap_or_default();
+            owned.clear();
+            owned.extend_from_slice(&buf[..buf.len().min(BUFFER_SIZE)]);
+            this.io.clone().write_owned(owned)
+        });
+        let (res, owned) = match fut.as_mut().poll(cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.write_fut = None;
+        this.write_buf = Some(owned);
+        Poll::Ready(res)
+    }
+
+    /// Writes go straight to the socket, so there is nothing to flush.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.io.shutdown(Shutdown::Write))
+    }
+}
@@ -0,0 +1,338 @@
+//! Custom server certificate verifiers.
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use rustls_fork_shadow_tls::{
+    client::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    internal::msgs::handshake::DigitallySignedStruct,
+    server::{ClientCertVerified, ClientCertVerifier},
+    Certificate, DistinguishedNames, Error, ServerName, SignatureScheme,
+};
+
+use crate::{
+    clock::Clock,
+    ocsp::{StaplePolicy, StapleSlot},
+    x509,
+};
+
+/// Accepts any certificate and any handshake signature.
+pub(crate) struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &Certificate,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &Certificate,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+}
+
+/// Verifier deciding trust with a closure, see `TlsConnector::with_cert_verifier_fn`.
+pub(crate) struct FnVerifier<F>(pub(crate) F);
+
+impl<F> ServerCertVerifier for FnVerifier<F>
+where
+    F: Fn(&Certificate, &[Certificate], &ServerName, SystemTime) -> Result<(), Error>
+        + Send
+        + Sync,
+{
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        (self.0)(end_entity, intermediates, server_name, now)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Which names the server certificate is verified for, instead of the one sent as SNI.
+#[derive(Clone)]
+pub(crate) enum VerifyNames {
+    /// Any of these.
+    Any(Vec<ServerName>),
+    /// Any name, as long as the chain checks out.
+    Unchecked,
+}
+
+/// The verifier a connector installed, and the checks it layers over it on each connection.
+#[derive(Clone, Default)]
+pub(crate) struct Layers {
+    pub(crate) base: Option<Arc<dyn ServerCertVerifier>>,
+    pub(crate) staple_policy: Option<StaplePolicy>,
+    pub(crate) names: Option<VerifyNames>,
+    pub(crate) clock: Clock,
+    /// How far past their validity period certificates are accepted.
+    pub(crate) tolerance: Option<Duration>,
+}
+
+impl Layers {
+    /// A verifier for one connection, and where it puts the stapled OCSP response. `None`
+    /// when there is nothing to layer and the config's verifier does the job.
+    pub(crate) fn session_verifier(
+        &self,
+    ) -> io::Result<Option<(Arc<SessionVerifier>, Option<StapleSlot>)>> {
+        if self.staple_policy.is_none() && self.names.is_none() && self.tolerance.is_none() {
+            // A clock only applies to a verifier set on the connector; the config's own checks
+            // certificates against the system clock.
+            if self.clock.is_system() || self.base.is_none() {
+                return Ok(None);
+            }
+        }
+        let inner = self.base.clone().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "checking OCSP staples, names or validity with a tolerance needs a verifier set \
+                 with with_webpki_roots",
+            )
+        })?;
+        let staple = self
+            .staple_policy
+            .map(|policy| (policy, StapleSlot::default()));
+        let slot = staple.as_ref().map(|(_, slot)| slot.clone());
+        let verifier = SessionVerifier {
+            inner,
+            names: self.names.clone(),
+            staple,
+            clock: self.clock.clone(),
+            tolerance: self.tolerance,
+        };
+        Ok(Some((Arc::new(verifier), slot)))
+    }
+}
+
+/// Verifier for one connection, running `inner` with the connector's layered checks.
+pub(crate) struct SessionVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    names: Option<VerifyNames>,
+    staple: Option<(StaplePolicy, StapleSlot)>,
+    clock: Clock,
+    tolerance: Option<Duration>,
+}
+
+impl SessionVerifier {
+    /// Run `inner` at `now` and, when the chain is only out of its validity period, again at
+    /// `now` moved back or forward by the tolerance.
+    fn verify_within_tolerance(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &[&[u8]],
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verify = |now| {
+            self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                &mut scts.iter().copied(),
+                ocsp_response,
+                now,
+            )
+        };
+        let result = verify(now);
+        let skewed = match (&result, self.tolerance) {
+            (Err(Error::InvalidCertificateData(reason)), Some(tolerance)) => {
+                // webpki's errors only come through as text.
+                if reason.ends_with("CertExpired") {
+                    now.checked_sub(tolerance)
+                } else if reason.ends_with("CertNotValidYet") {
+                    now.checked_add(tolerance)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        match skewed {
+            Some(skewed) => verify(skewed).or(result),
+            None => result,
+        }
+    }
+}
+
+impl ServerCertVerifier for SessionVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let now = match self.clock.is_system() {
+            true => now,
+            false => self.clock.now(),
+        };
+        let names = match &self.names {
+            Some(VerifyNames::Any(names)) => names.clone(),
+            Some(VerifyNames::Unchecked) => names_in(end_entity),
+            None => vec![server_name.clone()],
+        };
+        let scts: Vec<&[u8]> = scts.collect();
+        let mut result = Err(Error::General("no name to verify the certificate for".into()));
+        for name in &names {
+            result = self.verify_within_tolerance(
+                end_entity,
+                intermediates,
+                name,
+                &scts,
+                ocsp_response,
+                now,
+            );
+            if result.is_ok() {
+                break;
+            }
+        }
+        let verified = result?;
+        if let Some((policy, slot)) = &self.staple {
+            policy.check(ocsp_response, end_entity, intermediates, now)?;
+            if !ocsp_response.is_empty() {
+                *slot.lock().unwrap() = Some(ocsp_response.to_vec());
+            }
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+/// Client certificate verifier running `inner` at the time of a clock other than the
+/// system's, see `TlsAcceptorBuilder::with_time_provider`.
+pub(crate) struct ClockedClientVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    clock: Clock,
+}
+
+impl ClockedClientVerifier {
+    pub(crate) fn new(
+        inner: Arc<dyn ClientCertVerifier>,
+        clock: Clock,
+    ) -> Arc<dyn ClientCertVerifier> {
+        Arc::new(Self { inner, clock })
+    }
+}
+
+impl ClientCertVerifier for ClockedClientVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> Option<bool> {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        self.inner
+            .verify_client_cert(end_entity, intermediates, self.clock.now())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// DNS names `cert` is valid for, with wildcards filled in so the verifier accepts them.
+fn names_in(cert: &Certificate) -> Vec<ServerName> {
+    const DNS_NAME: u8 = 0x82;
+    let Some(names) = x509::tbs(&cert.0)
+        .and_then(|tbs| tbs.extension(x509::OID_SUBJECT_ALT_NAME))
+        .and_then(x509::sequence)
+    else {
+        return Vec::new();
+    };
+    names
+        .filter(|(tag, _, _)| *tag == DNS_NAME)
+        .filter_map(|(_, _, name)| {
+            let name = std::str::from_utf8(name).ok()?;
+            match name.strip_prefix("*.") {
+                Some(parent) => ServerName::try_from(format!("wildcard.{parent}").as_str()).ok(),
+                None => ServerName::try_from(name).ok(),
+            }
+        })
+        .collect()
+}
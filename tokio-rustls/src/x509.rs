@@ -0,0 +1,292 @@
+//! Just enough DER parsing to pick fields out of X.509 certificates and CRLs.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `(tag, whole element, content, rest)`.
+type Tlv<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
+
+/// Splits one DER element off `input`.
+pub(crate) fn read_tlv(input: &[u8]) -> Option<Tlv<'_>> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        n if n < 0x80 => (n as usize, rest),
+        0x81..=0x84 => {
+            let n = (first & 0x7f) as usize;
+            if rest.len() < n {
+                return None;
+            }
+            let len = rest[..n].iter().fold(0usize, |acc, b| acc << 8 | *b as usize);
+            (len, &rest[n..])
+        }
+        _ => return None,
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header_len = input.len() - rest.len();
+    Some((tag, &input[..header_len + len], &rest[..len], &rest[len..]))
+}
+
+/// Encodes one DER element.
+pub(crate) fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let len = content.len().to_be_bytes();
+    let skip = len.iter().take_while(|b| **b == 0).count();
+    let mut out = vec![tag];
+    match content.len() {
+        len if len < 0x80 => out.push(len as u8),
+        _ => {
+            out.push(0x80 | (len.len() - skip) as u8);
+            out.extend_from_slice(&len[skip..]);
+        }
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_BIT_STRING: u8 = 0x03;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_NULL: u8 = 0x05;
+pub(crate) const TAG_OID: u8 = 0x06;
+pub(crate) const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_UTC_TIME: u8 = 0x17;
+pub(crate) const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+
+#[cfg(any(feature = "dangerous_configuration", feature = "peer_identity"))]
+pub(crate) const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// The elements of DER content as `(tag, whole element, content)`, stopping at the first
+/// malformed one.
+#[derive(Clone)]
+pub(crate) struct Elements<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = (u8, &'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tag, element, content, rest) = read_tlv(self.0)?;
+        self.0 = rest;
+        Some((tag, element, content))
+    }
+}
+
+pub(crate) fn sequence(input: &[u8]) -> Option<Elements<'_>> {
+    match read_tlv(input)? {
+        (TAG_SEQUENCE, _, content, _) => Some(Elements(content)),
+        _ => None,
+    }
+}
+
+/// Fields of a certificate's TBSCertificate. Names and the key are whole DER elements, the
+/// serial is the content of its INTEGER and the extensions the content of their SEQUENCE.
+pub(crate) struct Tbs<'a> {
+    pub(crate) serial: &'a [u8],
+    pub(crate) issuer: &'a [u8],
+    pub(crate) validity: &'a [u8],
+    pub(crate) subject: &'a [u8],
+    pub(crate) spki: &'a [u8],
+    pub(crate) extensions: &'a [u8],
+}
+
+pub(crate) fn tbs(cert: &[u8]) -> Option<Tbs<'_>> {
+    let mut fields = sequence(sequence(cert)?.next()?.1)?.peekable();
+    if fields.peek()?.0 == TAG_VERSION {
+        fields.next();
+    }
+    let (TAG_INTEGER, _, serial) = fields.next()? else {
+        return None;
+    };
+    let _signature = fields.next()?;
+    let issuer = fields.next()?.1;
+    let validity = fields.next()?.1;
+    let subject = fields.next()?.1;
+    let spki = fields.next()?.1;
+    let extensions = match fields.find(|(tag, _, _)| *tag == TAG_EXTENSIONS) {
+        Some((_, _, extensions)) => {
+            let (TAG_SEQUENCE, _, extensions, _) = read_tlv(extensions)? else {
+                return None;
+            };
+            extensions
+        }
+        None => &[],
+    };
+    Some(Tbs {
+        serial,
+        issuer,
+        validity,
+        subject,
+        spki,
+        extensions,
+    })
+}
+
+impl<'a> Tbs<'a> {
+    /// The `(not before, not after)` window the certificate is valid in.
+    pub(crate) fn validity(&self) -> Option<(SystemTime, SystemTime)> {
+        let mut times = sequence(self.validity)?.map(|(tag, _, time)| self::time(tag, time));
+        Some((times.next()??, times.next()??))
+    }
+
+    /// The value of the extension with the DER encoded object identifier `oid`, as the
+    /// content of its OCTET STRING.
+    pub(crate) fn extension(&self, oid: &[u8]) -> Option<&'a [u8]> {
+        Elements(self.extensions).find_map(|(_, extension, _)| {
+            let mut fields = sequence(extension)?;
+            let (TAG_OID, _, id) = fields.next()? else {
+                return None;
+            };
+            if id != oid {
+                return None;
+            }
+            // Skip the critical flag when present.
+            fields.find_map(|(tag, _, value)| (tag == TAG_OCTET_STRING).then_some(value))
+        })
+    }
+}
+
+/// The TBSCertificate of `cert` re-encoded without the extension `oid`.
+pub(crate) fn tbs_without_extension(cert: &[u8], oid: &[u8]) -> Option<Vec<u8>> {
+    let (_, tbs, _) = sequence(cert)?.next()?;
+    let mut fields = Vec::new();
+    for (tag, element, content) in sequence(tbs)? {
+        if tag != TAG_EXTENSIONS {
+            fields.extend_from_slice(element);
+            continue;
+        }
+        let mut extensions = Vec::new();
+        for (_, extension, _) in sequence(content)? {
+            let (TAG_OID, _, id) = sequence(extension)?.next()? else {
+                return None;
+            };
+            if id != oid {
+                extensions.extend_from_slice(extension);
+            }
+        }
+        fields.extend(der(TAG_EXTENSIONS, &der(TAG_SEQUENCE, &extensions)));
+    }
+    Some(der(TAG_SEQUENCE, &fields))
+}
+
+/// The bits of a BIT STRING without unused trailing bits, which is how keys and signatures
+/// are encoded.
+pub(crate) fn bit_string(tag: u8, content: &[u8]) -> Option<&[u8]> {
+    match (tag, content.split_first()?) {
+        (TAG_BIT_STRING, (0, bits)) => Some(bits),
+        _ => None,
+    }
+}
+
+/// The subjectPublicKey bits of a SubjectPublicKeyInfo.
+pub(crate) fn public_key(spki: &[u8]) -> Option<&[u8]> {
+    let (tag, _, content) = sequence(spki)?.nth(1)?;
+    bit_string(tag, content)
+}
+
+/// A signed structure: a certificate, CRL or BasicOCSPResponse.
+pub(crate) struct Signed<'a> {
+    /// The whole DER element which is signed.
+    pub(crate) data: &'a [u8],
+    /// The DER encoded object identifier of the signature algorithm.
+    pub(crate) algorithm: &'a [u8],
+    pub(crate) signature: &'a [u8],
+    /// Anything following the signature.
+    pub(crate) rest: Elements<'a>,
+}
+
+pub(crate) fn signed(der: &[u8]) -> Option<Signed<'_>> {
+    let mut fields = sequence(der)?;
+    let (_, data, _) = fields.next()?;
+    let (TAG_OID, _, algorithm) = sequence(fields.next()?.1)?.next()? else {
+        return None;
+    };
+    let (tag, _, signature) = fields.next()?;
+    Some(Signed {
+        data,
+        algorithm,
+        signature: bit_string(tag, signature)?,
+        rest: fields,
+    })
+}
+
+/// The SubjectPublicKeyInfo of a certificate, as a whole DER element.
+pub(crate) fn spki(cert: &[u8]) -> Option<&[u8]> {
+    tbs(cert).map(|tbs| tbs.spki)
+}
+
+/// Fields of a CertificateList. The issuer is a whole DER element, serials are the content
+/// of their INTEGERs.
+pub(crate) struct Crl<'a> {
+    pub(crate) issuer: &'a [u8],
+    pub(crate) next_update: Option<SystemTime>,
+    pub(crate) revoked: Vec<&'a [u8]>,
+}
+
+pub(crate) fn crl(der: &[u8]) -> Option<Crl<'_>> {
+    let mut fields = sequence(sequence(der)?.next()?.1)?.peekable();
+    if fields.peek()?.0 == TAG_INTEGER {
+        fields.next();
+    }
+    let _signature = fields.next()?;
+    let issuer = fields.next()?.1;
+    let (tag, _, content) = fields.next()?;
+    let _this_update = time(tag, content)?;
+    let next_update = match fields.peek() {
+        Some(&(tag @ (TAG_UTC_TIME | TAG_GENERALIZED_TIME), _, content)) => {
+            fields.next();
+            Some(time(tag, content)?)
+        }
+        _ => None,
+    };
+    let mut revoked = Vec::new();
+    if let Some(&(TAG_SEQUENCE, entries, _)) = fields.peek() {
+        for (_, entry, _) in sequence(entries)? {
+            let (TAG_INTEGER, _, serial) = sequence(entry)?.next()? else {
+                return None;
+            };
+            revoked.push(serial);
+        }
+    }
+    Some(Crl {
+        issuer,
+        next_update,
+        revoked,
+    })
+}
+
+/// Parses a UTCTime or GeneralizedTime in the `Z` form DER requires.
+pub(crate) fn time(tag: u8, content: &[u8]) -> Option<SystemTime> {
+    let digits = content.strip_suffix(b"Z")?;
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let number = |digits: &[u8]| digits.iter().fold(0u64, |acc, d| acc * 10 + (d - b'0') as u64);
+    let (year, rest) = match (tag, digits.len()) {
+        (TAG_UTC_TIME, 12) => match number(&digits[..2]) {
+            yy if yy < 50 => (2000 + yy, &digits[2..]),
+            yy => (1900 + yy, &digits[2..]),
+        },
+        (TAG_GENERALIZED_TIME, 14) => (number(&digits[..4]), &digits[4..]),
+        _ => return None,
+    };
+    let [month, day, hour, minute, second] = [0, 2, 4, 6, 8].map(|i| number(&rest[i..i + 2]));
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    // Days since the epoch of a proleptic Gregorian date, counting years from March.
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let secs = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
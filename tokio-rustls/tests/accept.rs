@@ -0,0 +1,184 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use rustls_fork_shadow_tls::ServerName;
+use tokio_rustls_fork_shadow_tls::{
+    Accepted, FailureResponse, HelloAction, TlsAcceptor, TlsListener, TlsPairBuilder,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const CONTENT_TYPE_ALERT: u8 = 0x15;
+const ALERT_LEVEL_FATAL: u8 = 2;
+
+/// A ClientHello record which parses, offering only a cipher suite rustls doesn't implement.
+fn unsupported_client_hello() -> Vec<u8> {
+    let mut body = vec![3, 3];
+    body.extend_from_slice(&[0; 32]);
+    // No session id, TLS_RSA_WITH_RC4_128_SHA, null compression, no extensions.
+    body.extend_from_slice(&[0, 0, 2, 0x00, 0x05, 1, 0]);
+    let mut message = vec![1, 0, 0, body.len() as u8];
+    message.extend_from_slice(&body);
+    let mut record = vec![0x16, 3, 1, 0, message.len() as u8];
+    record.extend_from_slice(&message);
+    record
+}
+
+/// Send `unsupported_client_hello` to `acceptor` and return what it answers.
+async fn answer_to_unsupported_hello(acceptor: TlsAcceptor) -> Vec<u8> {
+    let (mut client, server) = duplex(16 * 1024);
+    client.write_all(&unsupported_client_hello()).await.unwrap();
+    assert!(acceptor.accept(server).await.is_err());
+    let mut answer = Vec::new();
+    client.read_to_end(&mut answer).await.unwrap();
+    answer
+}
+
+/// A TCP server echoing back what each connection sends.
+async fn echo_backend() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn client_hello_rejected_with_virtual_hosts_gets_an_alert() {
+    let (_, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor
+        .clone()
+        .with_sni_host("localhost", acceptor)
+        .unwrap();
+
+    let answer = answer_to_unsupported_hello(acceptor).await;
+    assert_eq!(answer.first(), Some(&CONTENT_TYPE_ALERT));
+    assert_eq!(answer.get(5), Some(&ALERT_LEVEL_FATAL));
+}
+
+#[tokio::test]
+async fn client_hello_accepted_by_policy_and_rejected_by_rustls_gets_an_alert() {
+    let (_, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let screened = Arc::new(AtomicBool::new(false));
+    let acceptor = acceptor.with_hello_policy({
+        let screened = screened.clone();
+        move |hello| {
+            assert_eq!(hello.cipher_suites, [0x0005]);
+            screened.store(true, Ordering::Relaxed);
+            HelloAction::Accept
+        }
+    });
+
+    let answer = answer_to_unsupported_hello(acceptor).await;
+    assert!(screened.load(Ordering::Relaxed));
+    assert_eq!(answer.first(), Some(&CONTENT_TYPE_ALERT));
+    assert_eq!(answer.get(5), Some(&ALERT_LEVEL_FATAL));
+}
+
+#[tokio::test]
+async fn unparsable_hello_is_rejected_by_default_with_a_policy() {
+    let (_, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor.with_hello_policy(|_| panic!("an unparsable hello reached the policy"));
+
+    let (mut client, server) = duplex(16 * 1024);
+    // A whole ClientHello, cut short after its version.
+    client
+        .write_all(&[0x16, 3, 1, 0, 8, 1, 0, 0, 4, 3, 3, 0, 0])
+        .await
+        .unwrap();
+    assert!(acceptor.accept(server).await.is_err());
+    let mut answer = Vec::new();
+    client.read_to_end(&mut answer).await.unwrap();
+    assert_eq!(answer.first(), Some(&CONTENT_TYPE_ALERT));
+    assert_eq!(answer.get(5), Some(&ALERT_LEVEL_FATAL));
+}
+
+#[tokio::test]
+async fn fallback_relay_leaves_the_handshake_slot_and_timeout() {
+    let backend = echo_backend().await;
+    let (connector, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor.with_fallback(backend.to_string(), |_| false);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handshake_timeout = Duration::from_millis(200);
+    let mut listener = TlsListener::new(listener, acceptor)
+        .with_max_handshakes(1)
+        .with_handshake_timeout(handshake_timeout);
+
+    let hello = unsupported_client_hello();
+    let mut probe = TcpStream::connect(addr).await.unwrap();
+    probe.write_all(&hello).await.unwrap();
+    let relayed = timeout(TIMEOUT, listener.accept()).await.unwrap();
+    assert!(relayed.is_err());
+    let mut echoed = vec![0; hello.len()];
+    probe.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(echoed, hello);
+
+    // The only handshake slot is free for another client while the relay goes on.
+    tokio::time::sleep(handshake_timeout * 2).await;
+    let domain = ServerName::try_from("localhost").unwrap();
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let (client, server) = timeout(TIMEOUT, async {
+        tokio::join!(connector.connect(domain, tcp), listener.accept())
+    })
+    .await
+    .unwrap();
+    assert!(client.is_ok());
+    assert!(server.is_ok());
+
+    // Past the handshake timeout, the relay still carries data.
+    probe.write_all(b"still relayed").await.unwrap();
+    let mut echoed = [0; 13];
+    timeout(TIMEOUT, probe.read_exact(&mut echoed))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&echoed, b"still relayed");
+}
+
+#[tokio::test]
+async fn failed_handshake_is_relayed_byte_for_byte() {
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_addr = backend.local_addr().unwrap();
+    let received = tokio::spawn(async move {
+        let (mut stream, _) = backend.accept().await.unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        received
+    });
+    let (_, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor.with_failure_response(FailureResponse::Relay {
+        backend: backend_addr.to_string(),
+    });
+
+    // Sent in one go, the bytes after the hello are read along with it but left to rustls.
+    let mut sent = unsupported_client_hello();
+    sent.extend_from_slice(b"sent after the hello");
+    let (mut client, server) = duplex(16 * 1024);
+    client.write_all(&sent).await.unwrap();
+    client.shutdown().await.unwrap();
+    let Ok(Accepted::Relayed(relay)) = acceptor.accept_or_relay(server).await else {
+        panic!("the failed handshake is not relayed");
+    };
+    assert_eq!(relay.backend(), backend_addr.to_string());
+    tokio::spawn(relay.run());
+
+    let received = timeout(TIMEOUT, received).await.unwrap().unwrap();
+    assert_eq!(received, sent);
+}
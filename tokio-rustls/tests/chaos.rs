@@ -0,0 +1,238 @@
+use std::{
+    future::poll_fn,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf},
+    time::timeout,
+};
+use rustls_fork_shadow_tls::ServerName;
+use tokio_rustls_fork_shadow_tls::{ChaosIo, ClientTlsStream, ServerTlsStream, TlsPairBuilder};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const PAYLOAD: usize = 256 * 1024;
+
+async fn chaos_pair(
+    client_io: impl FnOnce(ChaosIo<DuplexStream>) -> ChaosIo<DuplexStream>,
+    server_io: impl FnOnce(ChaosIo<DuplexStream>) -> ChaosIo<DuplexStream>,
+) -> (
+    ClientTlsStream<ChaosIo<DuplexStream>>,
+    ServerTlsStream<ChaosIo<DuplexStream>>,
+) {
+    let (connector, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let (client, server) = duplex(16 * 1024);
+    let domain = ServerName::try_from("localhost").unwrap();
+    let (client, server) = timeout(TIMEOUT, async {
+        tokio::join!(
+            connector.connect(domain, client_io(ChaosIo::new(client))),
+            acceptor.accept(server_io(ChaosIo::new(server)))
+        )
+    })
+    .await
+    .unwrap();
+    (client.unwrap(), server.unwrap())
+}
+
+fn payload() -> Vec<u8> {
+    (0..PAYLOAD).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn transfer_survives_short_and_interrupted_io() {
+    let faults = |seed| {
+        move |io: ChaosIo<DuplexStream>| {
+            io.with_seed(seed)
+                .with_short_reads(0.5)
+                .with_short_writes(0.5)
+                .with_would_block(0.2)
+                .with_delayed_wakeups(0.05, Duration::from_millis(1))
+        }
+    };
+    let (mut client, mut server) = chaos_pair(faults(1), faults(2)).await;
+
+    let sent = payload();
+    let (client_result, server_result) = timeout(TIMEOUT, async {
+        tokio::join!(
+            async {
+                client.write_all(&sent).await?;
+                client.shutdown().await?;
+                let mut received = Vec::new();
+                client.read_to_end(&mut received).await?;
+                io::Result::Ok(received)
+            },
+            async {
+                let mut received = Vec::new();
+                server.read_to_end(&mut received).await?;
+                server.write_all(&received).await?;
+                server.shutdown().await?;
+                io::Result::Ok(received)
+            }
+        )
+    })
+    .await
+    .unwrap();
+    assert!(server_result.unwrap() == sent);
+    assert!(client_result.unwrap() == sent);
+}
+
+#[tokio::test]
+async fn corrupted_record_fails_the_read() {
+    // Past the handshake, in the middle of the application data.
+    let (mut client, mut server) =
+        chaos_pair(|io| io.with_corrupt_read_at(PAYLOAD as u64 / 2), |io| io).await;
+
+    let sent = payload();
+    // The server's writes fail with the client's alert or a closed pipe, depending on timing.
+    let (_, read) = timeout(TIMEOUT, async {
+        tokio::join!(
+            async {
+                server.write_all(&sent).await?;
+                server.shutdown().await
+            },
+            // Dropping the client once its read fails ends the server's writes.
+            async move {
+                let mut received = Vec::new();
+                client.read_to_end(&mut received).await.map(|_| received)
+            }
+        )
+    })
+    .await
+    .unwrap();
+    assert!(read.is_err());
+}
+
+#[tokio::test]
+async fn eof_without_close_notify_is_unexpected() {
+    let (mut client, mut server) =
+        chaos_pair(|io| io.with_eof_after_read(PAYLOAD as u64 / 2), |io| io).await;
+
+    let sent = payload();
+    let (_, read) = timeout(TIMEOUT, async {
+        tokio::join!(
+            async {
+                server.write_all(&sent).await?;
+                server.shutdown().await
+            },
+            // Dropping the client once its read fails ends the server's writes.
+            async move {
+                let mut received = Vec::new();
+                client.read_to_end(&mut received).await.map(|_| received)
+            }
+        )
+    })
+    .await
+    .unwrap();
+    assert_eq!(read.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn same_seed_gives_same_schedule() {
+    let read_sizes = |seed| async move {
+        let (mut writer, reader) = duplex(PAYLOAD);
+        writer.write_all(&payload()).await.unwrap();
+        drop(writer);
+        let mut reader = ChaosIo::new(reader).with_seed(seed).with_short_reads(0.5);
+        let mut sizes = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            match reader.read(&mut buf).await.unwrap() {
+                0 => break sizes,
+                n => sizes.push(n),
+            }
+        }
+    };
+    assert_eq!(read_sizes(7).await, read_sizes(7).await);
+    assert_ne!(read_sizes(7).await, read_sizes(8).await);
+}
+
+/// An IO which, once stalled, returns `Pending` without registering a waker.
+struct Stall {
+    inner: DuplexStream,
+    stalled: Arc<AtomicBool>,
+}
+
+impl AsyncRead for Stall {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.stalled.load(Ordering::Relaxed) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stall {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.stalled.load(Ordering::Relaxed) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.stalled.load(Ordering::Relaxed) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn pending_io_is_not_taken_for_spinning() {
+    let (connector, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let (client, server) = duplex(16 * 1024);
+    let stalled = Arc::new(AtomicBool::new(false));
+    let client = Stall {
+        inner: client,
+        stalled: stalled.clone(),
+    };
+    let domain = ServerName::try_from("localhost").unwrap();
+    let (client, server) = timeout(TIMEOUT, async {
+        tokio::join!(connector.connect(domain, client), acceptor.accept(server))
+    })
+    .await
+    .unwrap();
+    let (mut client, mut server) = (client.unwrap(), server.unwrap());
+
+    stalled.store(true, Ordering::Relaxed);
+    // Polled again and again without a wakeup, well past the spin limit.
+    poll_fn(|cx| {
+        let mut buf = [0; 16];
+        for _ in 0..256 {
+            let mut read = ReadBuf::new(&mut buf);
+            assert!(Pin::new(&mut client).poll_read(cx, &mut read).is_pending());
+            assert!(Pin::new(&mut client).poll_flush(cx).is_pending());
+        }
+        Poll::Ready(())
+    })
+    .await;
+
+    stalled.store(false, Ordering::Relaxed);
+    client.write_all(b"after the stall").await.unwrap();
+    client.flush().await.unwrap();
+    let mut received = [0; 15];
+    timeout(TIMEOUT, server.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&received, b"after the stall");
+}
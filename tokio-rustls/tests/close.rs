@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    runtime::Handle,
+    time::timeout,
+};
+use rustls_fork_shadow_tls::ServerName;
+use tokio_rustls_fork_shadow_tls::{
+    ClientTlsStream, CloseOnDrop, MemoryBudget, ServerTlsStream, TlsPairBuilder,
+};
+
+/// A pair whose server stream closes gracefully on drop, charged to `budget`, over a pipe
+/// too small for what the server writes.
+async fn pair(
+    budget: &MemoryBudget,
+) -> (ClientTlsStream<DuplexStream>, ServerTlsStream<DuplexStream>) {
+    let (connector, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor.with_memory_budget(budget.clone());
+    let (client, server) = duplex(1024);
+    let domain = ServerName::try_from("localhost").unwrap();
+    let (client, server) = tokio::join!(connector.connect(domain, client), acceptor.accept(server));
+    let mut server = server.unwrap();
+    server.set_close_on_drop(CloseOnDrop::SpawnGracefulClose(Handle::current()));
+    (client.unwrap(), server)
+}
+
+/// Write to `server`, which the client doesn't read, until the pipe is full, then drop it.
+async fn drop_with_unsent_data(mut server: ServerTlsStream<DuplexStream>) {
+    let _ = timeout(Duration::from_secs(1), server.write_all(&[7; 16 * 1024])).await;
+    drop(server);
+    tokio::task::yield_now().await;
+}
+
+async fn released(budget: &MemoryBudget) {
+    while budget.used() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn graceful_close_keeps_the_memory_charge() {
+    let budget = MemoryBudget::new(1 << 20);
+    let (mut client, server) = pair(&budget).await;
+    drop_with_unsent_data(server).await;
+    assert_eq!(budget.used(), budget.per_stream());
+
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).await.unwrap();
+    assert!(received.iter().all(|&b| b == 7));
+    timeout(Duration::from_secs(1), released(&budget))
+        .await
+        .unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn graceful_close_gives_up_without_a_write_timeout() {
+    let budget = MemoryBudget::new(1 << 20);
+    let (mut client, server) = pair(&budget).await;
+    drop_with_unsent_data(server).await;
+
+    // Past the default deadline, with the client still not reading.
+    tokio::time::sleep(Duration::from_secs(31)).await;
+    timeout(Duration::from_secs(1), released(&budget))
+        .await
+        .unwrap();
+    // The connection ends without a close_notify.
+    let mut received = Vec::new();
+    assert!(client.read_to_end(&mut received).await.is_err());
+}
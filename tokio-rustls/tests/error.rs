@@ -0,0 +1,143 @@
+use std::io;
+
+use tokio::io::duplex;
+use rustls_fork_shadow_tls::{
+    AlertDescription, ContentType, Error as RustlsError, HandshakeType, NamedGroup, ServerName,
+};
+use tokio_rustls_fork_shadow_tls::{
+    ErrorContext, HandshakeFailureReason, TlsError, TlsPairBuilder,
+};
+
+fn reason(err: RustlsError) -> HandshakeFailureReason {
+    TlsError::Rustls(err).failure_reason()
+}
+
+fn io_reason(kind: io::ErrorKind) -> HandshakeFailureReason {
+    TlsError::Io(kind.into()).failure_reason()
+}
+
+#[test]
+fn received_alerts() {
+    assert_eq!(
+        reason(RustlsError::AlertReceived(
+            AlertDescription::ProtocolVersion
+        )),
+        HandshakeFailureReason::UnsupportedVersion
+    );
+    assert_eq!(
+        reason(RustlsError::AlertReceived(
+            AlertDescription::InsufficientSecurity
+        )),
+        HandshakeFailureReason::NoSharedCipher
+    );
+    assert_eq!(
+        reason(RustlsError::AlertReceived(AlertDescription::UnknownCA)),
+        HandshakeFailureReason::PeerAlert(AlertDescription::UnknownCA)
+    );
+    assert_eq!(
+        TlsError::HandshakeAlert(AlertDescription::ProtocolVersion).failure_reason(),
+        HandshakeFailureReason::UnsupportedVersion
+    );
+    assert_eq!(
+        TlsError::HandshakeAlert(AlertDescription::HandshakeFailure).failure_reason(),
+        HandshakeFailureReason::PeerAlert(AlertDescription::HandshakeFailure)
+    );
+}
+
+#[test]
+fn certificate_errors() {
+    for err in [
+        RustlsError::NoCertificatesPresented,
+        RustlsError::InvalidCertificateEncoding,
+        RustlsError::InvalidCertificateSignatureType,
+        RustlsError::InvalidCertificateSignature,
+        RustlsError::InvalidCertificateData("expired".into()),
+    ] {
+        assert_eq!(reason(err), HandshakeFailureReason::BadClientCert);
+    }
+}
+
+#[test]
+fn malformed_messages() {
+    for err in [
+        RustlsError::InappropriateMessage {
+            expect_types: vec![ContentType::Handshake],
+            got_type: ContentType::ApplicationData,
+        },
+        RustlsError::InappropriateHandshakeMessage {
+            expect_types: vec![HandshakeType::ClientHello],
+            got_type: HandshakeType::Finished,
+        },
+        RustlsError::CorruptMessage,
+        RustlsError::CorruptMessagePayload(ContentType::Handshake),
+        RustlsError::PeerMisbehavedError("client sent duplicate extensions".into()),
+    ] {
+        assert_eq!(reason(err), HandshakeFailureReason::DecodeError);
+    }
+}
+
+#[test]
+fn incompatibilities_are_not_told_apart_by_message() {
+    for why in [
+        "no ciphersuites in common",
+        "TLSv1.2 not offered/enabled",
+        "certificate invalid",
+    ] {
+        assert_eq!(
+            reason(RustlsError::PeerIncompatibleError(why.into())),
+            HandshakeFailureReason::Other
+        );
+    }
+    assert_eq!(
+        reason(RustlsError::DecryptError),
+        HandshakeFailureReason::Other
+    );
+}
+
+#[test]
+fn io_errors() {
+    assert_eq!(
+        io_reason(io::ErrorKind::TimedOut),
+        HandshakeFailureReason::Timeout
+    );
+    assert_eq!(
+        io_reason(io::ErrorKind::ConnectionReset),
+        HandshakeFailureReason::Transport
+    );
+    assert_eq!(
+        io_reason(io::ErrorKind::UnexpectedEof),
+        HandshakeFailureReason::Transport
+    );
+    assert_eq!(
+        io_reason(io::ErrorKind::ConnectionAborted),
+        HandshakeFailureReason::Other
+    );
+    let wrapped = io::Error::new(io::ErrorKind::InvalidData, RustlsError::CorruptMessage);
+    assert_eq!(
+        TlsError::Io(wrapped).failure_reason(),
+        HandshakeFailureReason::DecodeError
+    );
+}
+
+#[test]
+fn context_keeps_the_reason() {
+    let err = TlsError::Context {
+        context: ErrorContext::default(),
+        source: Box::new(TlsError::Io(io::ErrorKind::TimedOut.into())),
+    };
+    assert_eq!(err.failure_reason(), HandshakeFailureReason::Timeout);
+}
+
+#[tokio::test]
+async fn group_not_allowed() {
+    let (connector, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    // Both configs prefer X25519.
+    let connector = connector.with_kx_groups(&[NamedGroup::secp384r1]).unwrap();
+    let (client, server) = duplex(16 * 1024);
+    let domain = ServerName::try_from("localhost").unwrap();
+    let (client, _) = tokio::join!(connector.connect(domain, client), acceptor.accept(server));
+    let Err(err) = client else {
+        panic!("a handshake settling on a group not allowed succeeded");
+    };
+    assert_eq!(err.failure_reason(), HandshakeFailureReason::NoSharedCipher);
+}
@@ -0,0 +1,208 @@
+use tokio_rustls_fork_shadow_tls::ClientHelloInfo;
+
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_STATUS_REQUEST: u16 = 0x0005;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXTENSION_EC_POINT_FORMATS: u16 = 0x000b;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXTENSION_ALPN: u16 = 0x0010;
+const EXTENSION_SCT: u16 = 0x0012;
+const EXTENSION_PADDING: u16 = 0x0015;
+const EXTENSION_EXTENDED_MASTER_SECRET: u16 = 0x0017;
+const EXTENSION_COMPRESS_CERTIFICATE: u16 = 0x001b;
+const EXTENSION_SESSION_TICKET: u16 = 0x0023;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXTENSION_PSK_MODES: u16 = 0x002d;
+const EXTENSION_KEY_SHARE: u16 = 0x0033;
+const EXTENSION_APPLICATION_SETTINGS: u16 = 0x4469;
+const EXTENSION_RENEGOTIATION_INFO: u16 = 0xff01;
+const GREASE: u16 = 0x0a0a;
+
+/// A ClientHello message, handshake header included.
+fn client_hello(version: u16, ciphers: &[u16], extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut body = version.to_be_bytes().to_vec();
+    body.extend_from_slice(&[0; 32]);
+    // No session id.
+    body.push(0);
+    body.extend(vec16(&u16s(ciphers)));
+    // Null compression.
+    body.extend_from_slice(&[1, 0]);
+    let mut list = Vec::new();
+    for (kind, data) in extensions {
+        list.extend_from_slice(&kind.to_be_bytes());
+        list.extend(vec16(data));
+    }
+    body.extend(vec16(&list));
+    let mut message = vec![1];
+    message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    message.extend(body);
+    message
+}
+
+fn u16s(values: &[u16]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+fn vec8(data: &[u8]) -> Vec<u8> {
+    let mut vec = vec![data.len() as u8];
+    vec.extend_from_slice(data);
+    vec
+}
+
+fn vec16(data: &[u8]) -> Vec<u8> {
+    let mut vec = (data.len() as u16).to_be_bytes().to_vec();
+    vec.extend_from_slice(data);
+    vec
+}
+
+fn server_name(name: &[u8]) -> Vec<u8> {
+    let mut entry = vec![0];
+    entry.extend(vec16(name));
+    vec16(&entry)
+}
+
+fn alpn(protocols: &[&[u8]]) -> Vec<u8> {
+    let list: Vec<u8> = protocols.iter().flat_map(|p| vec8(p)).collect();
+    vec16(&list)
+}
+
+/// The example of the JA3 README, which hashes to `ada70206e40642a3e4461f35503241d5`.
+#[test]
+fn ja3_matches_published_fingerprint() {
+    let hello = client_hello(
+        0x0301,
+        &[
+            GREASE, 47, 53, 5, 10, 49161, 49162, 49171, 49172, 50, 56, 19, 4,
+        ],
+        &[
+            (EXTENSION_SERVER_NAME, server_name(b"example.com")),
+            (GREASE, Vec::new()),
+            (EXTENSION_SUPPORTED_GROUPS, vec16(&u16s(&[23, 24, 25]))),
+            (EXTENSION_EC_POINT_FORMATS, vec8(&[0])),
+        ],
+    );
+    let hello = ClientHelloInfo::parse(&hello).unwrap();
+    assert_eq!(
+        hello.ja3(),
+        "769,47-53-5-10-49161-49162-49171-49172-50-56-19-4,0-10-11,23-24-25,0"
+    );
+}
+
+/// A Chrome ClientHello, with the example fingerprint of the JA4 README.
+#[test]
+fn ja4_matches_published_fingerprint() {
+    let hello = client_hello(
+        0x0303,
+        &[
+            GREASE, 0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013,
+            0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+        ],
+        &[
+            (GREASE, Vec::new()),
+            (EXTENSION_SERVER_NAME, server_name(b"example.com")),
+            (EXTENSION_EXTENDED_MASTER_SECRET, Vec::new()),
+            (EXTENSION_RENEGOTIATION_INFO, vec8(&[])),
+            (
+                EXTENSION_SUPPORTED_GROUPS,
+                vec16(&u16s(&[GREASE, 0x001d, 0x0017, 0x0018])),
+            ),
+            (EXTENSION_EC_POINT_FORMATS, vec8(&[0])),
+            (EXTENSION_SESSION_TICKET, Vec::new()),
+            (EXTENSION_ALPN, alpn(&[b"h2", b"http/1.1"])),
+            (EXTENSION_STATUS_REQUEST, vec![1, 0, 0, 0, 0]),
+            (
+                EXTENSION_SIGNATURE_ALGORITHMS,
+                vec16(&u16s(&[
+                    0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601,
+                ])),
+            ),
+            (EXTENSION_SCT, Vec::new()),
+            (EXTENSION_KEY_SHARE, vec16(&[])),
+            (EXTENSION_PSK_MODES, vec8(&[1])),
+            (
+                EXTENSION_SUPPORTED_VERSIONS,
+                vec8(&u16s(&[GREASE, 0x0304, 0x0303])),
+            ),
+            (EXTENSION_COMPRESS_CERTIFICATE, vec8(&[0, 2])),
+            (EXTENSION_APPLICATION_SETTINGS, vec16(&vec8(b"h2"))),
+            (EXTENSION_PADDING, vec![0; 16]),
+        ],
+    );
+    let hello = ClientHelloInfo::parse(&hello).unwrap();
+    assert_eq!(hello.ja4(), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+}
+
+#[test]
+fn parse_reads_the_known_extensions() {
+    let message = client_hello(
+        0x0303,
+        &[0x1301, 0x1302],
+        &[
+            (EXTENSION_SERVER_NAME, server_name(b"example.com")),
+            (EXTENSION_ALPN, alpn(&[b"h2", b"http/1.1"])),
+            (EXTENSION_SUPPORTED_GROUPS, vec16(&u16s(&[0x001d]))),
+            (EXTENSION_SIGNATURE_ALGORITHMS, vec16(&u16s(&[0x0804]))),
+            (EXTENSION_SUPPORTED_VERSIONS, vec8(&u16s(&[0x0304]))),
+        ],
+    );
+    let hello = ClientHelloInfo::parse(&message).unwrap();
+    assert_eq!(hello.legacy_version, 0x0303);
+    assert_eq!(hello.cipher_suites, [0x1301, 0x1302]);
+    assert_eq!(
+        hello.extensions,
+        [
+            EXTENSION_SERVER_NAME,
+            EXTENSION_ALPN,
+            EXTENSION_SUPPORTED_GROUPS,
+            EXTENSION_SIGNATURE_ALGORITHMS,
+            EXTENSION_SUPPORTED_VERSIONS,
+        ]
+    );
+    assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+    assert_eq!(hello.alpn_protocols, [b"h2".to_vec(), b"http/1.1".to_vec()]);
+    assert_eq!(hello.supported_groups, [0x001d]);
+    assert_eq!(hello.signature_algorithms, [0x0804]);
+    assert_eq!(hello.supported_versions, [0x0304]);
+    assert!(hello.malformed_extensions.is_empty());
+    assert_eq!(hello.raw, message);
+}
+
+#[test]
+fn non_utf8_server_name_is_kept_raw() {
+    let hello = client_hello(
+        0x0303,
+        &[0x1301],
+        &[(EXTENSION_SERVER_NAME, server_name(&[0xff, b'a']))],
+    );
+    let hello = ClientHelloInfo::parse(&hello).unwrap();
+    assert_eq!(hello.server_name, None);
+    assert_eq!(hello.server_name_raw.as_deref(), Some(&[0xff, b'a'][..]));
+    assert!(hello.ja4().starts_with("t12d"));
+}
+
+#[test]
+fn malformed_extension_is_listed() {
+    // The protocol list claims more bytes than the extension holds.
+    let hello = client_hello(
+        0x0303,
+        &[0x1301],
+        &[
+            (EXTENSION_ALPN, vec![0, 9, 2, b'h', b'2']),
+            (EXTENSION_SERVER_NAME, server_name(b"example.com")),
+        ],
+    );
+    let hello = ClientHelloInfo::parse(&hello).unwrap();
+    assert_eq!(hello.malformed_extensions, [EXTENSION_ALPN]);
+    assert!(hello.alpn_protocols.is_empty());
+    assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+}
+
+#[test]
+fn truncated_hello_does_not_parse() {
+    let hello = client_hello(0x0303, &[0x1301], &[]);
+    assert!(ClientHelloInfo::parse(&hello[..hello.len() - 1]).is_none());
+    assert!(ClientHelloInfo::parse(b"GET / HTTP/1.1\r\n\r\n").is_none());
+}
@@ -0,0 +1,119 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use tokio::io::duplex;
+use rustls_fork_shadow_tls::ServerName;
+use tokio_rustls_fork_shadow_tls::{
+    generate_self_signed, HandshakeFailureReason, PinSet, TlsAcceptor, TlsConnector, TlsError,
+};
+
+/// A pin of 32 zero bytes.
+const ZERO_PIN: &str = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+fn pin(hash: &[u8; 32]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut pin = "sha256/".to_owned();
+    for chunk in hash.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => pin.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => pin.push('='),
+            }
+        }
+    }
+    pin
+}
+
+/// Handshake with a connector requiring `pins`, returning the server's leaf key hash and
+/// the client's result.
+async fn connect_with(pins: impl FnOnce(&[u8; 32]) -> PinSet) -> ([u8; 32], Result<(), TlsError>) {
+    let generated = generate_self_signed(&["localhost"]).unwrap();
+    let leaf = PinSet::spki_sha256(&generated.chain[0]).unwrap();
+    let connector = TlsConnector::from(generated.client_config()).with_pins(pins(&leaf));
+    let acceptor = TlsAcceptor::from(generated.server_config);
+    let (client, server) = duplex(16 * 1024);
+    let domain = ServerName::try_from("localhost").unwrap();
+    let (client, _) = tokio::join!(connector.connect(domain, client), acceptor.accept(server));
+    (leaf, client.map(drop))
+}
+
+#[test]
+fn with_pin_accepts_standard_base64() {
+    assert!(PinSet::new().with_pin(ZERO_PIN).is_ok());
+    assert!(PinSet::new().with_pin(&pin(&[0xfb; 32])).is_ok());
+}
+
+#[test]
+fn with_pin_rejects_anything_else() {
+    let zero = ZERO_PIN.strip_prefix("sha256/").unwrap();
+    let fb = pin(&[0xfb; 32]);
+    let fb = fb.strip_prefix("sha256/").unwrap();
+    for bad in [
+        // No or another algorithm.
+        zero.to_owned(),
+        format!("sha1/{zero}"),
+        // URL-safe, alone and mixed with the standard alphabet.
+        format!("sha256/{}", fb.replace('+', "-").replace('/', "_")),
+        format!("sha256/{}", fb.replacen('+', "-", 1)),
+        // Missing and misplaced padding.
+        ZERO_PIN.trim_end_matches('=').to_owned(),
+        format!("sha256/{}=A", &zero[..42]),
+        // Set bits past the last byte.
+        format!("sha256/{}B=", &zero[..42]),
+        // Not 32 bytes.
+        "sha256/AAAA".to_owned(),
+        format!("sha256/{zero}AAAA"),
+    ] {
+        let err = PinSet::new().with_pin(&bad).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{bad}");
+    }
+}
+
+#[tokio::test]
+async fn pinned_key_is_accepted() {
+    let (_, result) = connect_with(|leaf| {
+        PinSet::new()
+            .with_pin(ZERO_PIN)
+            .unwrap()
+            .with_pin(&pin(leaf))
+            .unwrap()
+    })
+    .await;
+    result.unwrap();
+}
+
+#[tokio::test]
+async fn unpinned_chain_is_rejected_and_reported() {
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let (leaf, result) = connect_with(|_| {
+        let reported = reported.clone();
+        PinSet::new()
+            .with_pin(ZERO_PIN)
+            .unwrap()
+            .with_failure_report(move |failure| {
+                assert_eq!(
+                    failure.server_name,
+                    ServerName::try_from("localhost").unwrap()
+                );
+                reported.lock().unwrap().push(failure.presented.clone());
+            })
+    })
+    .await;
+    let err = result.unwrap_err();
+    assert_eq!(err.failure_reason(), HandshakeFailureReason::BadClientCert);
+    let reported = reported.lock().unwrap();
+    assert_eq!(reported.len(), 1);
+    assert_eq!(reported[0].first(), Some(&leaf));
+}
+
+#[tokio::test]
+async fn empty_set_rejects_every_server() {
+    let (_, result) = connect_with(|_| PinSet::new()).await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,201 @@
+use std::{future::Future, io, time::Duration};
+
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt, BufWriter, DuplexStream},
+    time::timeout,
+};
+use tokio_rustls_fork_shadow_tls::Proxy;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Open a tunnel to `host:port` through `proxy`, played by `server`, and return what the
+/// client reads through the tunnel once it is open.
+///
+/// The client's writes are buffered, so a request which isn't flushed never reaches the
+/// server.
+async fn tunnel<S, Fut>(proxy: Proxy, host: &str, port: u16, server: S) -> io::Result<Vec<u8>>
+where
+    S: FnOnce(DuplexStream) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let (client, server_io) = duplex(16 * 1024);
+    let mut io = BufWriter::new(client);
+    let client = async {
+        proxy.tunnel(&mut io, host, port).await?;
+        let mut tunneled = Vec::new();
+        io.read_to_end(&mut tunneled).await?;
+        Ok(tunneled)
+    };
+    let (tunneled, ()) = timeout(TIMEOUT, async { tokio::join!(client, server(server_io)) })
+        .await
+        .unwrap();
+    tunneled
+}
+
+async fn expect(io: &mut DuplexStream, expected: &[u8]) {
+    let mut received = vec![0; expected.len()];
+    io.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn http_connect_with_auth() {
+    let tunneled = tunnel(
+        Proxy::http("proxy:3128").with_auth("user", "pass"),
+        "example.com",
+        443,
+        |mut io| async move {
+            expect(
+                &mut io,
+                b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\
+                  Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+            )
+            .await;
+            io.write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunneled")
+                .await
+                .unwrap();
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(tunneled, b"tunneled");
+}
+
+#[tokio::test]
+async fn http_connect_to_ipv6() {
+    let tunneled = tunnel(Proxy::http("proxy:3128"), "::1", 443, |mut io| async move {
+        expect(
+            &mut io,
+            b"CONNECT [::1]:443 HTTP/1.1\r\nHost: [::1]:443\r\n\r\n",
+        )
+        .await;
+        io.write_all(b"HTTP/1.1 200 OK\r\nVia: proxy\r\n\r\n")
+            .await
+            .unwrap();
+    })
+    .await
+    .unwrap();
+    assert!(tunneled.is_empty());
+}
+
+#[tokio::test]
+async fn http_connect_refused() {
+    let err = tunnel(
+        Proxy::http("proxy:3128"),
+        "example.com",
+        443,
+        |mut io| async move {
+            expect(
+                &mut io,
+                b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+            )
+            .await;
+            io.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+}
+
+#[tokio::test]
+async fn http_response_too_large() {
+    let err = tunnel(
+        Proxy::http("proxy:3128"),
+        "example.com",
+        443,
+        |mut io| async move {
+            let mut request = [0; 64];
+            let _ = io.read(&mut request).await.unwrap();
+            let _ = io.write_all(&[b'x'; 16 * 1024]).await;
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn socks5_with_auth_to_domain() {
+    let tunneled = tunnel(
+        Proxy::socks5("proxy:1080").with_auth("user", "pass"),
+        "example.com",
+        443,
+        |mut io| async move {
+            expect(&mut io, &[0x05, 0x02, 0x00, 0x02]).await;
+            io.write_all(&[0x05, 0x02]).await.unwrap();
+            expect(&mut io, b"\x01\x04user\x04pass").await;
+            io.write_all(&[0x01, 0x00]).await.unwrap();
+            expect(&mut io, b"\x05\x01\x00\x03\x0bexample.com\x01\xbb").await;
+            // Bound to a domain name.
+            io.write_all(b"\x05\x00\x00\x03\x05proxy\x04\x38tunneled")
+                .await
+                .unwrap();
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(tunneled, b"tunneled");
+}
+
+#[tokio::test]
+async fn socks5_without_auth_to_ipv4() {
+    let tunneled = tunnel(
+        Proxy::socks5("proxy:1080"),
+        "192.0.2.1",
+        443,
+        |mut io| async move {
+            expect(&mut io, &[0x05, 0x01, 0x00]).await;
+            io.write_all(&[0x05, 0x00]).await.unwrap();
+            expect(&mut io, &[0x05, 0x01, 0x00, 0x01, 192, 0, 2, 1, 0x01, 0xbb]).await;
+            io.write_all(&[0x05, 0x00, 0x00, 0x01, 198, 51, 100, 1, 0x04, 0x38])
+                .await
+                .unwrap();
+            io.write_all(b"tunneled").await.unwrap();
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(tunneled, b"tunneled");
+}
+
+#[tokio::test]
+async fn socks5_auth_rejected() {
+    let err = tunnel(
+        Proxy::socks5("proxy:1080").with_auth("user", "wrong"),
+        "example.com",
+        443,
+        |mut io| async move {
+            expect(&mut io, &[0x05, 0x02, 0x00, 0x02]).await;
+            io.write_all(&[0x05, 0x02]).await.unwrap();
+            expect(&mut io, b"\x01\x04user\x05wrong").await;
+            io.write_all(&[0x01, 0x01]).await.unwrap();
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn socks5_connect_refused() {
+    let err = tunnel(
+        Proxy::socks5("proxy:1080"),
+        "example.com",
+        443,
+        |mut io| async move {
+            expect(&mut io, &[0x05, 0x01, 0x00]).await;
+            io.write_all(&[0x05, 0x00]).await.unwrap();
+            expect(&mut io, b"\x05\x01\x00\x03\x0bexample.com\x01\xbb").await;
+            // Connection refused by the destination host.
+            io.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+}
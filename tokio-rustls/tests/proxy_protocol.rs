@@ -0,0 +1,187 @@
+use std::{
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{duplex, AsyncWriteExt},
+    time::timeout,
+};
+use rustls_fork_shadow_tls::ServerName;
+use tokio_rustls_fork_shadow_tls::{ProxyHeader, TlsError, TlsPairBuilder};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Handshake through an acceptor expecting a PROXY protocol header, sending `header` first,
+/// and return the header the server stream carries.
+async fn accept_with_header(header: &[u8]) -> Option<ProxyHeader> {
+    let (connector, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor.with_proxy_protocol(true);
+    let (mut client, server) = duplex(16 * 1024);
+    client.write_all(header).await.unwrap();
+    let domain = ServerName::try_from("localhost").unwrap();
+    let (client, server) = timeout(TIMEOUT, async {
+        tokio::join!(connector.connect(domain, client), acceptor.accept(server))
+    })
+    .await
+    .unwrap();
+    client.unwrap();
+    server.unwrap().proxy_header().copied()
+}
+
+/// Send `header` and nothing else to an acceptor expecting a PROXY protocol header, and
+/// return the error it fails with.
+async fn header_error(header: &[u8]) -> io::Error {
+    let (_, acceptor) = TlsPairBuilder::new().configs().unwrap();
+    let acceptor = acceptor.with_proxy_protocol(true);
+    let (mut client, server) = duplex(16 * 1024);
+    client.write_all(header).await.unwrap();
+    client.shutdown().await.unwrap();
+    match timeout(TIMEOUT, acceptor.accept(server)).await.unwrap() {
+        Ok(_) => panic!("a bad proxy protocol header was accepted"),
+        Err(TlsError::Io(err)) => err,
+        Err(err) => panic!("unexpected error {err:?}"),
+    }
+}
+
+fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x20 | command, family]);
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.extend_from_slice(addresses);
+    header
+}
+
+fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+}
+
+#[tokio::test]
+async fn v1_tcp4() {
+    let header = accept_with_header(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n").await;
+    assert_eq!(
+        header,
+        Some(ProxyHeader {
+            source: addr("192.0.2.1:56324"),
+            destination: addr("198.51.100.1:443"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn v1_tcp6() {
+    let header = accept_with_header(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+    assert_eq!(
+        header,
+        Some(ProxyHeader {
+            source: addr("[2001:db8::1]:56324"),
+            destination: addr("[2001:db8::2]:443"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn v1_unknown() {
+    assert_eq!(accept_with_header(b"PROXY UNKNOWN\r\n").await, None);
+    assert_eq!(
+        accept_with_header(b"PROXY UNKNOWN 192.0.2.1 198.51.100.1 56324 443\r\n").await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn v1_trailing_tokens() {
+    let err = header_error(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443 extra\r\n").await;
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn v1_family_mismatch() {
+    let err = header_error(b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn v1_truncated() {
+    let err = header_error(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n").await;
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    // Cut before the CRLF.
+    let err = header_error(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443").await;
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn v1_oversized() {
+    let mut line = b"PROXY UNKNOWN ".to_vec();
+    line.resize(120, b'x');
+    line.extend_from_slice(b"\r\n");
+    let err = header_error(&line).await;
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn v2_local() {
+    assert_eq!(accept_with_header(&v2(0x0, 0x00, &[])).await, None);
+}
+
+#[tokio::test]
+async fn v2_proxy_ipv4() {
+    let mut addresses = vec![192, 0, 2, 1, 198, 51, 100, 1];
+    addresses.extend_from_slice(&56324u16.to_be_bytes());
+    addresses.extend_from_slice(&443u16.to_be_bytes());
+    assert_eq!(
+        accept_with_header(&v2(0x1, 0x11, &addresses)).await,
+        Some(ProxyHeader {
+            source: addr("192.0.2.1:56324"),
+            destination: addr("198.51.100.1:443"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn v2_proxy_ipv6() {
+    let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let destination: Ipv6Addr = "2001:db8::2".parse().unwrap();
+    let mut addresses = source.octets().to_vec();
+    addresses.extend_from_slice(&destination.octets());
+    addresses.extend_from_slice(&56324u16.to_be_bytes());
+    addresses.extend_from_slice(&443u16.to_be_bytes());
+    assert_eq!(
+        accept_with_header(&v2(0x1, 0x21, &addresses)).await,
+        Some(ProxyHeader {
+            source: addr("[2001:db8::1]:56324"),
+            destination: addr("[2001:db8::2]:443"),
+        })
+    );
+}
+
+#[tokio::test]
+async fn v2_proxy_unix() {
+    // Two 108 byte socket paths.
+    let mut addresses = vec![0; 216];
+    addresses[..9].copy_from_slice(b"/run/src\0");
+    addresses[108..117].copy_from_slice(b"/run/dst\0");
+    assert_eq!(accept_with_header(&v2(0x1, 0x31, &addresses)).await, None);
+}
+
+#[tokio::test]
+async fn v2_bad_signature() {
+    let mut header = v2(0x0, 0x00, &[]);
+    header[11] = b'X';
+    let err = header_error(&header).await;
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn v2_bad_length() {
+    // Too short for two IPv4 addresses and ports.
+    let err = header_error(&v2(0x1, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1])).await;
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    // Longer than what follows.
+    let mut header = v2(0x1, 0x11, &[0; 12]);
+    header[14..16].copy_from_slice(&100u16.to_be_bytes());
+    let err = header_error(&header).await;
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
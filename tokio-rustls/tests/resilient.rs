@@ -0,0 +1,205 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf},
+    time::timeout,
+};
+use rustls_fork_shadow_tls::ServerName;
+use tokio_rustls_fork_shadow_tls::{
+    ResilientTlsStream, ServerTlsStream, TlsAcceptor, TlsConnector, TlsPairBuilder,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The client end of an in-memory connection, which can report the end of the pipe as a
+/// connection reset, as a TCP connection the server dropped would.
+struct Pipe {
+    inner: DuplexStream,
+    reset_on_eof: bool,
+}
+
+impl AsyncRead for Pipe {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        if self.reset_on_eof && buf.filled().len() == filled && buf.remaining() > 0 {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Pipe {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A dialer whose every connection is accepted by `acceptor` and handed to `serve`, with the
+/// number of the connection counting from 0, and the number of dials made.
+fn mock_dialer<S, Fut>(
+    acceptor: TlsAcceptor,
+    reset_on_eof: bool,
+    serve: S,
+) -> (
+    impl FnMut() -> std::future::Ready<io::Result<Pipe>> + Unpin,
+    Arc<AtomicUsize>,
+)
+where
+    S: Fn(usize, ServerTlsStream<DuplexStream>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let dials = Arc::new(AtomicUsize::new(0));
+    let serve = Arc::new(serve);
+    let dialer = {
+        let dials = dials.clone();
+        move || {
+            let n = dials.fetch_add(1, Ordering::Relaxed);
+            let (client, server) = duplex(16 * 1024);
+            let acceptor = acceptor.clone();
+            let serve = serve.clone();
+            tokio::spawn(async move {
+                if let Ok(stream) = acceptor.accept(server).await {
+                    serve(n, stream).await;
+                }
+            });
+            std::future::ready(Ok(Pipe {
+                inner: client,
+                reset_on_eof,
+            }))
+        }
+    };
+    (dialer, dials)
+}
+
+fn configs() -> (TlsConnector, TlsAcceptor) {
+    TlsPairBuilder::new().configs().unwrap()
+}
+
+fn domain() -> ServerName {
+    ServerName::try_from("localhost").unwrap()
+}
+
+#[tokio::test]
+async fn eof_without_close_notify_is_returned() {
+    let (connector, acceptor) = configs();
+    let (dialer, dials) = mock_dialer(acceptor, false, |_, mut stream| async move {
+        let _ = stream.write_all(b"partial").await;
+        let _ = stream.flush().await;
+        // Dropped without a close_notify.
+    });
+    let mut stream = ResilientTlsStream::connect(connector, domain(), dialer)
+        .await
+        .unwrap();
+
+    let mut buf = [0; 7];
+    timeout(TIMEOUT, stream.read_exact(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf, b"partial");
+    let read = timeout(TIMEOUT, stream.read(&mut buf)).await.unwrap();
+    assert_eq!(read.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(dials.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn failed_read_is_returned_and_next_write_reconnects() {
+    let (connector, acceptor) = configs();
+    let (dialer, dials) = mock_dialer(acceptor, true, |n, mut stream| async move {
+        if n == 0 {
+            // The first connection dies right after the handshake.
+            return;
+        }
+        let mut request = [0; 4];
+        stream.read_exact(&mut request).await.unwrap();
+        stream.write_all(b"pong").await.unwrap();
+        stream.flush().await.unwrap();
+    });
+    let mut stream = ResilientTlsStream::connect(connector, domain(), dialer)
+        .await
+        .unwrap();
+
+    let mut buf = [0; 4];
+    let read = timeout(TIMEOUT, stream.read(&mut buf)).await.unwrap();
+    assert_eq!(read.unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+
+    timeout(TIMEOUT, async {
+        stream.write_all(b"ping").await?;
+        stream.flush().await?;
+        stream.read_exact(&mut buf).await
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(&buf, b"pong");
+    assert_eq!(dials.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn resumed_read_continues_on_new_connection() {
+    let (connector, acceptor) = configs();
+    let (dialer, dials) = mock_dialer(acceptor, true, |n, mut stream| async move {
+        if n == 0 {
+            return;
+        }
+        stream.write_all(b"resumed").await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(TIMEOUT).await;
+    });
+    let mut stream = ResilientTlsStream::connect(connector, domain(), dialer)
+        .await
+        .unwrap()
+        .with_resumed_reads(true);
+
+    let mut buf = [0; 7];
+    timeout(TIMEOUT, stream.read_exact(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf, b"resumed");
+    assert_eq!(dials.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn reconnections_are_capped() {
+    let (connector, acceptor) = configs();
+    // Every connection dies right after its handshake.
+    let (dialer, dials) = mock_dialer(acceptor, true, |_, _| async {});
+    let mut stream = ResilientTlsStream::connect(connector, domain(), dialer)
+        .await
+        .unwrap()
+        .with_resumed_reads(true)
+        .with_max_reconnects(2);
+
+    let mut buf = [0; 4];
+    let read = timeout(TIMEOUT, stream.read(&mut buf)).await.unwrap();
+    assert_eq!(read.unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(dials.load(Ordering::Relaxed), 3);
+}
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+use tokio_rustls_fork_shadow_tls::{tls_pair, TlsPairBuilder};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn concurrent_read_and_write() {
+    let (client, server) = tls_pair().await.unwrap();
+    let (mut server_read, mut server_write) = tokio::io::split(server);
+    let echo = tokio::spawn(async move {
+        tokio::io::copy(&mut server_read, &mut server_write)
+            .await
+            .unwrap();
+        server_write.shutdown().await.unwrap();
+    });
+
+    // More than the pipe holds, so neither side finishes unless both make progress.
+    let sent: Vec<u8> = (0..512 * 1024).map(|i| i as u8).collect();
+    let (mut read, mut write) = client.split();
+    let mut received = Vec::new();
+    let (written, read_result) = timeout(TIMEOUT, async {
+        tokio::join!(
+            async {
+                write.write_all(&sent).await?;
+                write.shutdown().await
+            },
+            read.read_to_end(&mut received)
+        )
+    })
+    .await
+    .unwrap();
+    written.unwrap();
+    read_result.unwrap();
+    assert!(received == sent);
+    echo.await.unwrap();
+}
+
+#[tokio::test]
+async fn write_half_shutdown_ends_pending_read() {
+    let (client, mut server) = tls_pair().await.unwrap();
+    let (mut read, mut write) = client.split();
+
+    // The read half is polled first and left pending, the write half then shuts down.
+    let mut buf = [0; 16];
+    let (read_result, shut_down) = timeout(TIMEOUT, async {
+        tokio::join!(read.read(&mut buf), async {
+            tokio::task::yield_now().await;
+            write.shutdown().await
+        })
+    })
+    .await
+    .unwrap();
+    shut_down.unwrap();
+    assert_eq!(read_result.unwrap(), 0);
+
+    // The peer got the close_notify, and what it sends now isn't read.
+    let mut request = Vec::new();
+    server.read_to_end(&mut request).await.unwrap();
+    assert!(request.is_empty());
+    server.write_all(b"response").await.unwrap();
+    server.flush().await.unwrap();
+    assert_eq!(read.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn reads_after_write_half_shut_down_when_enabled() {
+    let (client, mut server) = tls_pair().await.unwrap();
+    let (read, mut write) = client.split();
+    let mut read = read.with_reads_after_shutdown(true);
+    write.write_all(b"request").await.unwrap();
+    write.shutdown().await.unwrap();
+
+    let mut request = Vec::new();
+    server.read_to_end(&mut request).await.unwrap();
+    assert_eq!(request, b"request");
+    server.write_all(b"response").await.unwrap();
+    server.shutdown().await.unwrap();
+
+    let mut response = Vec::new();
+    read.read_to_end(&mut response).await.unwrap();
+    assert_eq!(response, b"response");
+}
+
+#[tokio::test]
+async fn reunite_after_write_half_shut_down() {
+    let (client, mut server) = tls_pair().await.unwrap();
+    let (read, mut write) = client.split();
+    write.shutdown().await.unwrap();
+
+    let mut stream = read.reunite(write).unwrap();
+    let mut request = Vec::new();
+    server.read_to_end(&mut request).await.unwrap();
+    assert!(request.is_empty());
+    server.write_all(b"response").await.unwrap();
+    server.shutdown().await.unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert_eq!(response, b"response");
+}
+
+#[tokio::test]
+async fn reunite_rejects_halves_of_other_streams() {
+    let (first, _first_server) = tls_pair().await.unwrap();
+    let (second, _second_server) = tls_pair().await.unwrap();
+    let (read, _) = first.split();
+    let (_, write) = second.split();
+    assert!(read.reunite(write).is_err());
+}
+
+#[tokio::test]
+async fn pending_read_is_woken_by_peer_reply() {
+    let (client, mut server) = tls_pair().await.unwrap();
+    let (mut read, mut write) = client.split();
+    let peer = tokio::spawn(async move {
+        let mut request = [0; 4];
+        server.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"ping");
+        server.write_all(b"pong").await.unwrap();
+        server
+    });
+
+    // The read half is polled first and left pending, the write half then sends the request.
+    let mut reply = [0; 4];
+    let (read_result, written) = timeout(TIMEOUT, async {
+        tokio::join!(read.read_exact(&mut reply), async {
+            tokio::task::yield_now().await;
+            write.write_all(b"ping").await?;
+            write.flush().await
+        })
+    })
+    .await
+    .unwrap();
+    written.unwrap();
+    read_result.unwrap();
+    assert_eq!(&reply, b"pong");
+    drop(peer.await.unwrap());
+}
+
+#[tokio::test]
+async fn pending_write_is_woken_while_read_half_is_pending() {
+    let (client, mut server) = TlsPairBuilder::new()
+        .with_duplex_size(1024)
+        .build()
+        .await
+        .unwrap();
+    let (mut read, mut write) = client.split();
+    let sent = vec![7; 64 * 1024];
+    let len = sent.len();
+    let peer = tokio::spawn(async move {
+        // Let the client fill the pipe before draining it.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut received = vec![0; len];
+        server.read_exact(&mut received).await.unwrap();
+        server.write_all(b"done").await.unwrap();
+        received
+    });
+
+    let mut reply = [0; 4];
+    let (read_result, written) = timeout(TIMEOUT, async {
+        tokio::join!(read.read_exact(&mut reply), async {
+            write.write_all(&sent).await?;
+            write.flush().await
+        })
+    })
+    .await
+    .unwrap();
+    written.unwrap();
+    read_result.unwrap();
+    assert_eq!(&reply, b"done");
+    assert!(peer.await.unwrap() == sent);
+}